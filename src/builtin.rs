@@ -0,0 +1,148 @@
+//! Generic component types the dcb binary registers, so that a federation can be run from a
+//! config file alone:
+//!     1) "generator" sends `payload` through its "out" route every `period`, `count` times;
+//!     2) "relay" forwards every payload it receives through its "out" route;
+//!     3) "printer" prints every payload it receives once it can no longer be rolled back.
+//!
+//! Applications with their own component types register them next to these ones (see
+//! register) and call cli::execute from their own main.
+
+use crate::component::Component;
+use crate::context::Context;
+use crate::models::{CommitAction, MsgCore, Timestamp};
+use crate::registry::Registry;
+use serde::{Deserialize, Serialize};
+
+const TICK: &str = "tick";
+
+/// Registers the built-in component types
+pub fn register(registry: &mut Registry) -> &mut Registry {
+    registry
+        .register_persistent::<Generator>("generator")
+        .register_persistent::<Relay>("relay")
+        .register_persistent::<Printer>("printer")
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Generator {
+    params: GeneratorParams,
+    sent: u32,
+}
+
+/// e.g. { "period": 10, "count": 5, "payload": "ping" }; the payload is empty if omitted
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GeneratorParams {
+    pub period: Timestamp,
+    pub count: u32,
+    #[serde(default)]
+    pub payload: String,
+}
+
+impl Component for Generator {
+    type Params = GeneratorParams;
+
+    fn init(params: GeneratorParams, ctx: &mut Context) -> Self {
+        if params.count > 0 {
+            ctx.schedule(TICK, "", params.period);
+        }
+        Generator { params, sent: 0 }
+    }
+
+    fn routes() -> Vec<&'static str> {
+        vec!["out"]
+    }
+
+    fn on_message(mut self, msg: &MsgCore, ctx: &mut Context) -> Self {
+        if msg.route != TICK || ctx.sender() != Some(ctx.id()) {
+            return self;
+        }
+        ctx.send("out", self.params.payload.clone(), 0);
+        self.sent += 1;
+        if self.sent < self.params.count {
+            ctx.schedule(TICK, "", self.params.period);
+        }
+        self
+    }
+}
+
+/// Takes no params
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Relay;
+
+impl Component for Relay {
+    type Params = ();
+
+    fn init(_params: (), _ctx: &mut Context) -> Self {
+        Relay
+    }
+
+    fn routes() -> Vec<&'static str> {
+        vec!["out"]
+    }
+
+    fn on_message(self, msg: &MsgCore, ctx: &mut Context) -> Self {
+        ctx.send("out", msg.payload.clone(), 0);
+        self
+    }
+}
+
+/// Takes no params; prints "<time> <payload>" lines to the standard output
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Printer;
+
+impl Component for Printer {
+    type Params = ();
+
+    fn init(_params: (), _ctx: &mut Context) -> Self {
+        Printer
+    }
+
+    fn on_message(self, msg: &MsgCore, ctx: &mut Context) -> Self {
+        ctx.commit(msg.payload.clone());
+        self
+    }
+
+    fn execute(action: &CommitAction) {
+        println!("{} {}", action.timestamp, action.payload);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tick(generator: Generator, lvt: Timestamp) -> (Generator, Context) {
+        let mut ctx = Context::new(1, lvt, Some(1), lvt - generator.params.period);
+        let msg = MsgCore {
+            payload: String::new(),
+            route: String::from(TICK),
+            exec_ts: lvt,
+        };
+        (generator.on_message(&msg, &mut ctx), ctx)
+    }
+
+    #[test]
+    fn generator_sends_count_payloads_every_period() {
+        let params = serde_json::json!({ "period": 10, "count": 2, "payload": "ping" });
+        let mut ctx = Context::new(1, 0, None, 0);
+        let generator = Generator::init(GeneratorParams::deserialize(params).unwrap(), &mut ctx);
+        assert_eq!(ctx.events()[0].exec_ts, 10);
+
+        let (generator, ctx) = tick(generator, 10);
+        assert_eq!(ctx.messages()[0].payload, "ping");
+        assert_eq!(ctx.events()[0].exec_ts, 20);
+
+        let (_, ctx) = tick(generator, 20);
+        assert_eq!(ctx.messages().len(), 1);
+        assert!(ctx.events().is_empty());
+    }
+
+    #[test]
+    fn builtin_types_are_registered() {
+        let mut registry = Registry::new();
+        register(&mut registry);
+        assert!(["generator", "relay", "printer"]
+            .iter()
+            .all(|t| registry.contains(t)));
+    }
+}
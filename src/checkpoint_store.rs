@@ -0,0 +1,364 @@
+use crate::models::{Checkpoint, Dependencies, Timestamp};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{LinkedList, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Where a RollbackManager keeps its checkpoints
+///
+/// Checkpoints are always pushed in ascending timestamp order, and only the latest ones are
+/// ever removed by a rollback, so a store behaves like a queue whose oldest entries are
+/// removed by free and whose latest entries are removed by restore.
+pub trait CheckpointStore<State> {
+    /// Appends a checkpoint whose timestamp is greater than that of every stored checkpoint
+    fn push(&mut self, checkpoint: Checkpoint<State>) -> io::Result<()>;
+
+    /// Timestamp of the oldest checkpoint, None if there are none
+    fn first_timestamp(&self) -> Option<Timestamp>;
+
+    /// Timestamp of the latest checkpoint, None if there are none
+    fn last_timestamp(&self) -> Option<Timestamp>;
+
+    /// Timestamps of every checkpoint, in ascending order
+    fn timestamps(&self) -> Vec<Timestamp>;
+
+    /// Timestamp and dependency vector of every checkpoint, in ascending timestamp order (see
+    /// recovery_line)
+    fn dependencies(&self) -> io::Result<Vec<(Timestamp, Dependencies)>>;
+
+    /// Returns the latest checkpoint whose timestamp is not greater than ts, None if there are
+    /// none; unlike restore, nothing is removed
+    fn latest(&self, ts: Timestamp) -> io::Result<Option<Checkpoint<State>>>;
+
+    /// Removes every checkpoint whose timestamp is greater than ts, then returns the latest
+    /// remaining checkpoint, None if there are none
+    fn restore(&mut self, ts: Timestamp) -> io::Result<Option<Checkpoint<State>>>;
+
+    /// Removes every checkpoint whose timestamp is not greater than ts
+    fn free(&mut self, ts: Timestamp) -> io::Result<()>;
+}
+
+/// Keeps every checkpoint in memory; this is the default store
+impl<State> CheckpointStore<State> for LinkedList<Checkpoint<State>>
+where
+    State: Clone,
+{
+    fn push(&mut self, checkpoint: Checkpoint<State>) -> io::Result<()> {
+        self.push_back(checkpoint);
+        Ok(())
+    }
+
+    fn first_timestamp(&self) -> Option<Timestamp> {
+        self.front().map(|c| c.timestamp)
+    }
+
+    fn last_timestamp(&self) -> Option<Timestamp> {
+        self.back().map(|c| c.timestamp)
+    }
+
+    fn timestamps(&self) -> Vec<Timestamp> {
+        self.iter().map(|c| c.timestamp).collect()
+    }
+
+    fn dependencies(&self) -> io::Result<Vec<(Timestamp, Dependencies)>> {
+        Ok(self
+            .iter()
+            .map(|c| (c.timestamp, c.dependencies.clone()))
+            .collect())
+    }
+
+    fn latest(&self, ts: Timestamp) -> io::Result<Option<Checkpoint<State>>> {
+        Ok(self.iter().rev().find(|c| c.timestamp <= ts).cloned())
+    }
+
+    fn restore(&mut self, ts: Timestamp) -> io::Result<Option<Checkpoint<State>>> {
+        while self.back().is_some_and(|last| last.timestamp > ts) {
+            self.pop_back();
+        }
+        Ok(self.back().cloned())
+    }
+
+    fn free(&mut self, ts: Timestamp) -> io::Result<()> {
+        while self.front().is_some_and(|first| first.timestamp <= ts) {
+            self.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// Lets a component type's instances keep their checkpoints in different stores, e.g. in
+/// memory or on disk depending on how they are built (see Registry::register_persistent)
+pub type BoxedStore<State> = Box<dyn CheckpointStore<State> + Send>;
+
+impl<State, Store> CheckpointStore<State> for Box<Store>
+where
+    Store: CheckpointStore<State> + ?Sized,
+{
+    fn push(&mut self, checkpoint: Checkpoint<State>) -> io::Result<()> {
+        (**self).push(checkpoint)
+    }
+
+    fn first_timestamp(&self) -> Option<Timestamp> {
+        (**self).first_timestamp()
+    }
+
+    fn last_timestamp(&self) -> Option<Timestamp> {
+        (**self).last_timestamp()
+    }
+
+    fn timestamps(&self) -> Vec<Timestamp> {
+        (**self).timestamps()
+    }
+
+    fn dependencies(&self) -> io::Result<Vec<(Timestamp, Dependencies)>> {
+        (**self).dependencies()
+    }
+
+    fn latest(&self, ts: Timestamp) -> io::Result<Option<Checkpoint<State>>> {
+        (**self).latest(ts)
+    }
+
+    fn restore(&mut self, ts: Timestamp) -> io::Result<Option<Checkpoint<State>>> {
+        (**self).restore(ts)
+    }
+
+    fn free(&mut self, ts: Timestamp) -> io::Result<()> {
+        (**self).free(ts)
+    }
+}
+
+/// Writes every checkpoint to its own JSON file in a directory and only keeps the latest ones
+/// in memory, so long simulations do not run out of memory and checkpoints outlive the node
+///
+/// Rolling back past the checkpoints kept in memory reads the latest remaining one back from
+/// disk; the dependency vector of every checkpoint is kept in memory though, since recovery
+/// lines are computed from them (see recovery_line).
+#[derive(Debug)]
+pub struct FileStore<State> {
+    dir: PathBuf,
+    in_memory: usize,
+
+    // timestamp and dependency vector of every checkpoint written to dir, in ascending order
+    index: VecDeque<(Timestamp, Dependencies)>,
+
+    // the latest checkpoints, at most in_memory of them, in ascending timestamp order
+    recent: LinkedList<Checkpoint<State>>,
+}
+
+const EXTENSION: &str = "checkpoint";
+
+impl<State> FileStore<State>
+where
+    State: Clone + Serialize + DeserializeOwned,
+{
+    /// Creates an empty store in dir, removing the checkpoints a previous store left there
+    pub fn create(dir: impl AsRef<Path>, in_memory: usize) -> io::Result<FileStore<State>> {
+        let mut store = FileStore::open(dir, in_memory)?;
+        store.free(Timestamp::MAX)?;
+        Ok(store)
+    }
+
+    /// Opens the store in dir, creating the directory if needed; the checkpoints already
+    /// written there are kept, e.g. those of a node that is restarted
+    pub fn open(dir: impl AsRef<Path>, in_memory: usize) -> io::Result<FileStore<State>> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut timestamps = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                if let Some(ts) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                    timestamps.push(ts);
+                }
+            }
+        }
+        timestamps.sort_unstable();
+        let mut store = FileStore {
+            dir,
+            in_memory,
+            index: VecDeque::new(),
+            recent: LinkedList::new(),
+        };
+        for ts in timestamps {
+            let dependencies = store.load(ts)?.dependencies;
+            store.index.push_back((ts, dependencies));
+        }
+        Ok(store)
+    }
+
+    /// Reads a checkpoint back from disk
+    pub fn load(&self, ts: Timestamp) -> io::Result<Checkpoint<State>> {
+        let file = File::open(self.path(ts))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(io::Error::from)
+    }
+
+    fn path(&self, ts: Timestamp) -> PathBuf {
+        self.dir.join(format!("{}.{}", ts, EXTENSION))
+    }
+
+    // timestamp of the latest checkpoint not later than ts
+    fn latest_timestamp(&self, ts: Timestamp) -> Option<Timestamp> {
+        self.index
+            .iter()
+            .rev()
+            .map(|(timestamp, _)| *timestamp)
+            .find(|timestamp| *timestamp <= ts)
+    }
+}
+
+impl<State> CheckpointStore<State> for FileStore<State>
+where
+    State: Clone + Serialize + DeserializeOwned,
+{
+    fn push(&mut self, checkpoint: Checkpoint<State>) -> io::Result<()> {
+        // the checkpoint is renamed once complete, so a crash never leaves half of one behind
+        let path = self.path(checkpoint.timestamp);
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, &checkpoint)?;
+        writer.flush()?;
+        fs::rename(tmp, path)?;
+
+        self.index
+            .push_back((checkpoint.timestamp, checkpoint.dependencies.clone()));
+        self.recent.push_back(checkpoint);
+        if self.recent.len() > self.in_memory {
+            self.recent.pop_front();
+        }
+        Ok(())
+    }
+
+    fn first_timestamp(&self) -> Option<Timestamp> {
+        self.index.front().map(|(ts, _)| *ts)
+    }
+
+    fn last_timestamp(&self) -> Option<Timestamp> {
+        self.index.back().map(|(ts, _)| *ts)
+    }
+
+    fn timestamps(&self) -> Vec<Timestamp> {
+        self.index.iter().map(|(ts, _)| *ts).collect()
+    }
+
+    fn dependencies(&self) -> io::Result<Vec<(Timestamp, Dependencies)>> {
+        Ok(self.index.iter().cloned().collect())
+    }
+
+    fn latest(&self, ts: Timestamp) -> io::Result<Option<Checkpoint<State>>> {
+        match self.latest_timestamp(ts) {
+            Some(latest) => match self.recent.iter().find(|c| c.timestamp == latest) {
+                Some(checkpoint) => Ok(Some(checkpoint.clone())),
+                None => self.load(latest).map(Some),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn restore(&mut self, ts: Timestamp) -> io::Result<Option<Checkpoint<State>>> {
+        while let Some(last) = self.last_timestamp() {
+            if last <= ts {
+                break;
+            }
+            fs::remove_file(self.path(last))?;
+            self.index.pop_back();
+        }
+        self.recent.restore(ts)?;
+
+        match (self.recent.back(), self.last_timestamp()) {
+            (Some(checkpoint), _) => Ok(Some(checkpoint.clone())),
+            (None, Some(last)) => {
+                let checkpoint = self.load(last)?;
+                if self.in_memory > 0 {
+                    self.recent.push_back(checkpoint.clone());
+                }
+                Ok(Some(checkpoint))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn free(&mut self, ts: Timestamp) -> io::Result<()> {
+        while let Some(first) = self.first_timestamp() {
+            if first > ts {
+                break;
+            }
+            fs::remove_file(self.path(first))?;
+            self.index.pop_front();
+        }
+        self.recent.free(ts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rollback_manager::RollbackManager;
+
+    fn get_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dcb_checkpoint_store_test_{}", name))
+    }
+
+    fn get_checkpoint(timestamp: Timestamp) -> Checkpoint<String> {
+        Checkpoint {
+            timestamp,
+            state: format!("state at {}", timestamp),
+            dependencies: vec![(1, timestamp)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn filestore_spills_old_checkpoints_and_reads_them_back() {
+        let dir = get_dir("spill");
+        let mut store = FileStore::create(&dir, 2).unwrap();
+        for ts in [0, 10, 20, 30] {
+            store.push(get_checkpoint(ts)).unwrap();
+        }
+        assert_eq!(store.timestamps(), vec![0, 10, 20, 30]);
+        assert_eq!(store.recent.len(), 2);
+        // the dependencies of spilled checkpoints are kept in memory
+        let dependencies = store.dependencies().unwrap();
+        for (i, ts) in vec![0, 10, 20, 30].into_iter().enumerate() {
+            assert_eq!(dependencies[i], (ts, get_checkpoint(ts).dependencies));
+        }
+
+        // 10 is no longer in memory
+        assert_eq!(store.latest(25).unwrap(), Some(get_checkpoint(20)));
+        assert_eq!(store.latest(15).unwrap(), Some(get_checkpoint(10)));
+        assert_eq!(store.timestamps(), vec![0, 10, 20, 30]);
+        assert_eq!(store.restore(15).unwrap(), Some(get_checkpoint(10)));
+        assert_eq!(store.timestamps(), vec![0, 10]);
+        assert!(!store.path(20).exists());
+
+        store.free(0).unwrap();
+        assert_eq!(store.first_timestamp(), Some(10));
+        assert!(!store.path(0).exists());
+
+        // a restarted node finds the checkpoints that were not removed
+        let mut reopened = FileStore::<String>::open(&dir, 2).unwrap();
+        assert_eq!(reopened.timestamps(), vec![10]);
+        assert_eq!(reopened.restore(100).unwrap(), Some(get_checkpoint(10)));
+
+        let created = FileStore::<String>::create(&dir, 2).unwrap();
+        assert!(created.timestamps().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rollbackmanager_rolls_back_to_spilled_checkpoints() {
+        let dir = get_dir("rollback");
+        let store = FileStore::create(&dir, 1).unwrap();
+        let mut manager = RollbackManager::with_store(1, 0, store).unwrap();
+        for lvt in [10, 20, 30] {
+            manager.update(lvt as i32, lvt).unwrap();
+            manager.take_checkpoint().unwrap();
+        }
+        assert_eq!(manager.checkpoints().timestamps(), vec![0, 11, 21, 31]);
+
+        manager.rollback(15).unwrap();
+        assert_eq!((manager.lvt(), *manager.state()), (11, 10));
+        assert_eq!(manager.checkpoints().timestamps(), vec![0, 11]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
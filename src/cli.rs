@@ -0,0 +1,549 @@
+use crate::config::{CheckpointMode, FederationCfg, SyncMode};
+use crate::init::{init, Persistence};
+use crate::models::Timestamp;
+use crate::registry::{Registry, RegistryError};
+use crate::{savepoint, snapshot};
+use std::path::Path;
+
+pub const EXIT_OK: i32 = 0;
+
+/// The node could not run, e.g. because it could not listen on its address or could not
+/// recover its persisted state, the node asked for a snapshot could not be reached, or the
+/// savepoint could not be taken or restored
+pub const EXIT_FAILURE: i32 = 1;
+
+/// The command line is invalid
+pub const EXIT_USAGE: i32 = 2;
+
+/// The config file cannot be read or is invalid, including invalid component params or routes
+pub const EXIT_INVALID_CONFIG: i32 = 3;
+
+/// The requested node is not declared in the config file
+pub const EXIT_UNKNOWN_NODE: i32 = 4;
+
+/// A component the node runs has a type that is not registered
+pub const EXIT_UNKNOWN_TYPE: i32 = 5;
+
+const USAGE: &str = "usage:
+    dcb run --config <file> --node <name> [--recover | --restore <dir>]
+                                             runs one node of the federation, optionally
+                                             restarting it from its persisted state or
+                                             booting it from the savepoint in dir
+    dcb snapshot --config <file> --dir <dir> [--node <name>]
+                                             takes a snapshot of the running federation
+                                             into dir, starting from the given node or the
+                                             first declared one
+    dcb save --config <file> --at <time> --dir <dir>
+                                             waits for the running federation to reach
+                                             time, then saves it into dir
+    dcb validate --config <file>             checks a config file, and the components
+                                             of registered types against their type
+    dcb info --config <file>                 prints the topology described by a config file";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Command {
+    Run {
+        config: String,
+        node: String,
+        /// restart the node from the state it persisted (see init::init)
+        recover: bool,
+        /// directory of the savepoint the node is booted from (see the savepoint module)
+        restore: Option<String>,
+    },
+    Snapshot {
+        config: String,
+        dir: String,
+        /// node that starts the snapshot, the first declared node if None
+        node: Option<String>,
+    },
+    Save {
+        config: String,
+        at: Timestamp,
+        dir: String,
+    },
+    Validate {
+        config: String,
+    },
+    Info {
+        config: String,
+    },
+}
+
+/// Parses the command line arguments, without the program name
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let (command, options) = match args.split_first() {
+        Some((command, options)) => (command, options),
+        None => return Err(String::from("missing command")),
+    };
+
+    let mut config = None;
+    let mut node = None;
+    let mut dir = None;
+    let mut at = None;
+    let mut restore = None;
+    let mut recover = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = match option.as_str() {
+            "--recover" => {
+                recover = true;
+                continue;
+            }
+            "--config" => &mut config,
+            "--node" => &mut node,
+            "--dir" => &mut dir,
+            "--at" => &mut at,
+            "--restore" => &mut restore,
+            _ => return Err(format!("unknown option {}", option)),
+        };
+        match options.next() {
+            Some(v) => *value = Some(v.clone()),
+            None => return Err(format!("missing value for {}", option)),
+        }
+    }
+
+    let config = config.ok_or_else(|| String::from("missing --config"))?;
+    match command.as_str() {
+        "snapshot" | "save" | "validate" | "info" if recover => {
+            Err(format!("{} does not take --recover", command))
+        }
+        "snapshot" | "save" | "validate" | "info" if restore.is_some() => {
+            Err(format!("{} does not take --restore", command))
+        }
+        "run" | "validate" | "info" if dir.is_some() => {
+            Err(format!("{} does not take --dir", command))
+        }
+        "run" | "snapshot" | "validate" | "info" if at.is_some() => {
+            Err(format!("{} does not take --at", command))
+        }
+        "run" if recover && restore.is_some() => {
+            Err(String::from("--recover and --restore cannot be combined"))
+        }
+        "save" if node.is_some() => Err(String::from("save does not take --node")),
+        "save" => Ok(Command::Save {
+            config,
+            at: at
+                .ok_or_else(|| String::from("missing --at"))?
+                .parse()
+                .map_err(|_| String::from("--at must be a time"))?,
+            dir: dir.ok_or_else(|| String::from("missing --dir"))?,
+        }),
+        "snapshot" => Ok(Command::Snapshot {
+            config,
+            dir: dir.ok_or_else(|| String::from("missing --dir"))?,
+            node,
+        }),
+        "run" => Ok(Command::Run {
+            config,
+            node: node.ok_or_else(|| String::from("missing --node"))?,
+            recover,
+            restore,
+        }),
+        "validate" | "info" if node.is_some() => Err(format!("{} does not take --node", command)),
+        "validate" => Ok(Command::Validate { config }),
+        "info" => Ok(Command::Info { config }),
+        _ => Err(format!("unknown command {}", command)),
+    }
+}
+
+/// Runs the dcb command line and returns the process exit code
+///
+/// Components are instantiated from the component types of the given registry. The dcb
+/// binary only registers the built-in types (see the builtin module); applications with their
+/// own types call execute from their own main, with a registry they registered them in.
+pub fn execute(args: &[String], registry: &Registry) -> i32 {
+    let command = match parse_args(args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {}\n{}", e, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let path = match &command {
+        Command::Run { config, .. }
+        | Command::Snapshot { config, .. }
+        | Command::Save { config, .. }
+        | Command::Validate { config }
+        | Command::Info { config } => config,
+    };
+    let cfg = match FederationCfg::load(path) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return EXIT_INVALID_CONFIG;
+        }
+    };
+
+    match command {
+        // the config may be meant for a binary that registers other component types, so only
+        // the components of registered types are checked against their type
+        Command::Validate { .. } => match registry.check_registered(&cfg) {
+            Ok(unknown) => {
+                for c in unknown {
+                    eprintln!(
+                        "warning: component {} has type {} which is not registered, its params \
+                         and routes were not checked",
+                        c.id, c.type_name
+                    );
+                }
+                println!("{} is valid", path);
+                EXIT_OK
+            }
+            Err(e) => {
+                eprintln!("error: {}", e);
+                exit_code(&e)
+            }
+        },
+        Command::Info { .. } => {
+            print!("{}", info(&cfg));
+            EXIT_OK
+        }
+        Command::Run {
+            node,
+            recover,
+            restore,
+            ..
+        } => run(&cfg, &node, recover, restore.as_deref(), registry),
+        Command::Snapshot { dir, node, .. } => {
+            let node = node.unwrap_or_else(|| cfg.nodes[0].name.clone());
+            let address = match cfg.nodes.iter().find(|n| n.name == node) {
+                Some(n) => &n.address,
+                None => {
+                    eprintln!("error: node {} is not declared", node);
+                    return EXIT_UNKNOWN_NODE;
+                }
+            };
+            match snapshot::trigger(address, &dir) {
+                Ok(()) => {
+                    println!("snapshot into {} requested from node {}", dir, node);
+                    EXIT_OK
+                }
+                Err(e) => {
+                    eprintln!("error: cannot reach node {} on {}: {}", node, address, e);
+                    EXIT_FAILURE
+                }
+            }
+        }
+        Command::Save { at, dir, .. } => {
+            // the coordinator is the node with the lowest address (see init::init)
+            let address = cfg
+                .nodes
+                .iter()
+                .filter(|n| cfg.components.iter().any(|c| c.node == n.name))
+                .map(|n| &n.address)
+                .min();
+            let Some(address) = address else {
+                eprintln!("error: no node runs any component");
+                return EXIT_INVALID_CONFIG;
+            };
+            match savepoint::request(address, at, &dir) {
+                Ok(()) => {
+                    println!("savepoint at {} saved into {}", at, dir);
+                    EXIT_OK
+                }
+                Err(e) => {
+                    eprintln!("error: savepoint at {} failed: {}", at, e);
+                    EXIT_FAILURE
+                }
+            }
+        }
+    }
+}
+
+fn exit_code(e: &RegistryError) -> i32 {
+    match e {
+        RegistryError::UnknownType { .. } => EXIT_UNKNOWN_TYPE,
+        RegistryError::InvalidParams { .. }
+        | RegistryError::UnroutedRoute { .. }
+        | RegistryError::UndeclaredRoute { .. }
+        | RegistryError::NotPersistent { .. } => EXIT_INVALID_CONFIG,
+        RegistryError::Storage { .. } | RegistryError::Savepoint { .. } => EXIT_FAILURE,
+    }
+}
+
+fn run(
+    cfg: &FederationCfg,
+    node: &str,
+    recover: bool,
+    restore: Option<&str>,
+    registry: &Registry,
+) -> i32 {
+    let setup = match cfg.node(node) {
+        Some(setup) => setup,
+        None => {
+            eprintln!("error: node {} is not declared", node);
+            return EXIT_UNKNOWN_NODE;
+        }
+    };
+
+    let dead_letters = match setup.dead_letters.sink() {
+        Ok(sink) => sink,
+        Err(e) => {
+            eprintln!("error: cannot open the dead-letter sink: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    if recover && setup.persistence.is_none() {
+        eprintln!("error: --recover requires persistence in the config file");
+        return EXIT_INVALID_CONFIG;
+    }
+    if restore.is_some() && setup.persistence.is_some() {
+        eprintln!("error: --restore does not support persistence in the config file");
+        return EXIT_INVALID_CONFIG;
+    }
+
+    let mut components = Vec::new();
+    for component in &setup.components {
+        let built = match (&setup.persistence, restore) {
+            (Some(persistence), _) => {
+                registry.build_persistent(component, dead_letters.clone(), persistence, recover)
+            }
+            (None, Some(dir)) => {
+                registry.build_restored(component, dead_letters.clone(), Path::new(dir))
+            }
+            (None, None) => registry.build(component, dead_letters.clone()),
+        };
+        match built {
+            Ok(mut c) => {
+                c.set_checkpoint_mode(setup.checkpoints);
+                components.push(c)
+            }
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return exit_code(&e);
+            }
+        }
+    }
+
+    match init(
+        setup.address.clone(),
+        setup.remote_addrs,
+        components,
+        setup.end_ts,
+        setup.workers,
+        setup.lookahead,
+        setup.persistence.map(|p| Persistence {
+            dir: p.dir,
+            recover,
+        }),
+    ) {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("error: node {} on {} failed: {}", node, setup.address, e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Human readable description of the topology
+fn info(cfg: &FederationCfg) -> String {
+    let mut out = String::new();
+    match cfg.end_ts {
+        Some(end_ts) => out += &format!("end_ts: {}\n", end_ts),
+        None => out += "end_ts: none\n",
+    }
+    out += &format!("workers: {}\n", cfg.workers);
+    match cfg.sync {
+        SyncMode::Optimistic => out += "sync: optimistic\n",
+        SyncMode::Conservative => {
+            out += &format!("sync: conservative (lookahead {})\n", cfg.lookahead())
+        }
+    }
+    if cfg.checkpoints == CheckpointMode::CommunicationInduced {
+        out += "checkpoints: communication induced\n";
+    }
+    if let Some(persistence) = &cfg.persistence {
+        out += &format!("persistence: {}\n", persistence.dir.display());
+    }
+    for node in &cfg.nodes {
+        out += &format!("node {} ({})\n", node.name, node.address);
+        for component in cfg.components.iter().filter(|c| c.node == node.name) {
+            out += &format!("    component {} ({})\n", component.id, component.type_name);
+            let mut routes: Vec<_> = component.routes.iter().collect();
+            routes.sort_by(|a, b| a.0.cmp(b.0));
+            for (route, dests) in routes {
+                let dests: Vec<String> = dests
+                    .iter()
+                    .map(|d| match d.delay {
+                        0 => format!("{}/{}", d.to, d.route),
+                        delay => format!("{}/{} (+{})", d.to, d.route, delay),
+                    })
+                    .collect();
+                out += &format!("        {} -> {}\n", route, dests.join(", "));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_args_accepts_every_command() {
+        assert_eq!(
+            parse_args(&get_args("run --node A --config fed.json")),
+            Ok(Command::Run {
+                config: String::from("fed.json"),
+                node: String::from("A"),
+                recover: false,
+                restore: None,
+            })
+        );
+        assert_eq!(
+            parse_args(&get_args("run --recover --node A --config fed.json")),
+            Ok(Command::Run {
+                config: String::from("fed.json"),
+                node: String::from("A"),
+                recover: true,
+                restore: None,
+            })
+        );
+        assert_eq!(
+            parse_args(&get_args("run --node A --config fed.json --restore save")),
+            Ok(Command::Run {
+                config: String::from("fed.json"),
+                node: String::from("A"),
+                recover: false,
+                restore: Some(String::from("save")),
+            })
+        );
+        assert_eq!(
+            parse_args(&get_args("snapshot --config fed.json --dir snap")),
+            Ok(Command::Snapshot {
+                config: String::from("fed.json"),
+                dir: String::from("snap"),
+                node: None,
+            })
+        );
+        assert_eq!(
+            parse_args(&get_args("save --config fed.json --at 1000 --dir save")),
+            Ok(Command::Save {
+                config: String::from("fed.json"),
+                at: 1000,
+                dir: String::from("save"),
+            })
+        );
+        assert_eq!(
+            parse_args(&get_args("validate --config fed.json")),
+            Ok(Command::Validate {
+                config: String::from("fed.json"),
+            })
+        );
+        assert_eq!(
+            parse_args(&get_args("info --config fed.json")),
+            Ok(Command::Info {
+                config: String::from("fed.json"),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_args_rejects_invalid_command_lines() {
+        assert!(parse_args(&get_args("")).is_err());
+        assert!(parse_args(&get_args("start --config fed.json")).is_err());
+        assert!(parse_args(&get_args("run --config fed.json")).is_err());
+        assert!(parse_args(&get_args("run --node A")).is_err());
+        assert!(parse_args(&get_args("info --config")).is_err());
+        assert!(parse_args(&get_args("info --config fed.json --verbose")).is_err());
+        assert!(parse_args(&get_args("validate --config fed.json --recover")).is_err());
+        assert!(parse_args(&get_args("snapshot --config fed.json")).is_err());
+        assert!(parse_args(&get_args("run --config fed.json --node A --dir snap")).is_err());
+        assert!(parse_args(&get_args("save --config fed.json --dir save")).is_err());
+        assert!(parse_args(&get_args("save --config fed.json --at soon --dir save")).is_err());
+        assert!(parse_args(&get_args("snapshot --config fed.json --dir s --at 5")).is_err());
+        assert!(parse_args(&get_args("run --config f --node A --recover --restore s")).is_err());
+    }
+
+    #[test]
+    fn execute_fails_with_meaningful_exit_codes() {
+        let path = std::env::temp_dir().join("dcb_cli_test.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "nodes": [{ "name": "A", "address": "127.0.0.1:28411" }],
+                "components": [{ "id": 1, "type": "unknown", "node": "A" }]
+            }"#,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let execute = |args: &str| execute(&get_args(args), &Registry::new());
+        assert_eq!(execute("validate"), EXIT_USAGE);
+        assert_eq!(
+            execute("validate --config missing.json"),
+            EXIT_INVALID_CONFIG
+        );
+        assert_eq!(execute(&format!("validate --config {}", path)), EXIT_OK);
+        assert_eq!(execute(&format!("info --config {}", path)), EXIT_OK);
+        assert_eq!(
+            execute(&format!("run --config {} --node B", path)),
+            EXIT_UNKNOWN_NODE
+        );
+        assert_eq!(
+            execute(&format!("run --config {} --node A", path)),
+            EXIT_UNKNOWN_TYPE
+        );
+        assert_eq!(
+            execute(&format!("run --config {} --node A --recover", path)),
+            EXIT_INVALID_CONFIG
+        );
+        assert_eq!(
+            execute(&format!("snapshot --config {} --dir snap --node B", path)),
+            EXIT_UNKNOWN_NODE
+        );
+        // nothing listens on A
+        assert_eq!(
+            execute(&format!("snapshot --config {} --dir snap", path)),
+            EXIT_FAILURE
+        );
+        assert_eq!(
+            execute(&format!("save --config {} --at 10 --dir save", path)),
+            EXIT_FAILURE
+        );
+    }
+
+    #[test]
+    fn execute_runs_builtin_types() {
+        let path = std::env::temp_dir().join("dcb_cli_builtin_test.json");
+        let write = |params: &str| {
+            let cfg = r#"{
+                "end_ts": 100,
+                "nodes": [{ "name": "A", "address": "127.0.0.1:28415" }],
+                "components": [
+                    {
+                        "id": 1, "type": "generator", "node": "A", "params": PARAMS,
+                        "routes": { "out": { "to": 2, "route": "in", "delay": 1 } }
+                    },
+                    {
+                        "id": 2, "type": "relay", "node": "A",
+                        "routes": { "out": { "to": 3, "route": "in", "delay": 1 } }
+                    },
+                    { "id": 3, "type": "printer", "node": "A" }
+                ]
+            }"#;
+            std::fs::write(&path, cfg.replace("PARAMS", params)).unwrap();
+        };
+        let mut registry = Registry::new();
+        crate::builtin::register(&mut registry);
+        let execute = |args: &str| {
+            let args = get_args(&format!("{} --config {}", args, path.to_str().unwrap()));
+            execute(&args, &registry)
+        };
+
+        write(r#"{ "period": 10 }"#);
+        assert_eq!(execute("validate"), EXIT_INVALID_CONFIG);
+
+        write(r#"{ "period": 10, "count": 3 }"#);
+        assert_eq!(execute("validate"), EXIT_OK);
+        assert_eq!(execute("run --node A"), EXIT_OK);
+        std::fs::remove_file(path).unwrap();
+    }
+}
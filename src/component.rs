@@ -1,11 +1,53 @@
-use crate::models::MsgCore;
+use crate::context::Context;
+use crate::models::{CommitAction, MsgCore, Timestamp};
+use serde::de::DeserializeOwned;
 
 pub trait Component {
-    fn init() -> (Self, Vec<MsgCore>)
+    /// Per-instance configuration, deserialized from the params of the instance's ComponentCfg
+    /// when its Translator is built
+    type Params: DeserializeOwned + Clone + Send;
+
+    /// Creates the initial state of an instance; messages and events may be emitted through ctx
+    ///
+    /// The id of the instance is available through ctx.id()
+    fn init(params: Self::Params, ctx: &mut Context) -> Self
     where
         Self: Sized;
 
-    fn on_message(self, msg: &MsgCore) -> (Self, Vec<MsgCore>)
+    /// Routes the component emits through ctx.send
+    ///
+    /// Unless the list is empty, it is checked against the route table of every instance at
+    /// startup: each of these routes must be routed and nothing else may be, a route pattern
+    /// counting as routing every route it matches. At runtime, messages emitted through a
+    /// route that is not routed go to a dead-letter sink.
+    fn routes() -> Vec<&'static str>
+    where
+        Self: Sized,
+    {
+        Vec::new()
+    }
+
+    /// Handles a message; ctx describes the message and collects everything emitted in response
+    fn on_message(self, msg: &MsgCore, ctx: &mut Context) -> Self
     where
         Self: Sized;
+
+    /// Executes an action emitted through ctx.commit once it can no longer be rolled back
+    fn execute(_action: &CommitAction)
+    where
+        Self: Sized,
+    {
+    }
+
+    /// Called on the restored state after the component was rolled back to ts
+    ///
+    /// Useful to release external resources or reset caches that are not part of the state
+    fn on_rollback(&self, _ts: Timestamp) {}
+
+    /// Called on the current state after GVT advanced to gvt, i.e. after everything that
+    /// happened before gvt became final
+    fn on_commit(&self, _gvt: Timestamp) {}
+
+    /// Called on the final state once the simulation is over
+    fn on_end(&self) {}
 }
@@ -0,0 +1,873 @@
+use crate::checkpoint_store::CheckpointStore;
+use crate::config::CheckpointMode;
+use crate::gateway::Gateway;
+use crate::message_log::MessageLog;
+use crate::messenger::Messenger;
+use crate::models::{Checkpoint, CommitAction, ComponentId, Message, Timestamp};
+use crate::recovery_line::History;
+use crate::rollback_manager::{Failure, RollbackManager};
+use crate::savepoint::SavedComponent;
+use crate::snapshot::ComponentSnapshot;
+use serde::Serialize;
+use std::collections::LinkedList;
+use std::io;
+
+/// A component as seen by the DCB runtime, regardless of its state type
+pub trait Runnable: Send {
+    fn id(&self) -> ComponentId;
+
+    /// Sends the messages emitted when the component was initialized
+    fn start(&mut self, messenger: &Messenger);
+
+    /// Handles a message or anti-message popped from the component's queue
+    ///
+    /// Fails if the component's history cannot be saved, e.g. because its checkpoint store
+    /// cannot be written; the component must not be used anymore then.
+    fn handle(&mut self, msg: Message, messenger: &Messenger) -> io::Result<()>;
+
+    /// Executes everything that became final now that GVT reached gvt and frees the history
+    /// that can no longer be rolled back to, nor recovered to if line is set (see
+    /// RollbackManager::fossil_collect)
+    ///
+    /// Fails like handle.
+    fn commit(&mut self, gvt: Timestamp, line: Option<Timestamp>) -> io::Result<()>;
+
+    /// Must be called once, after the simulation is over
+    fn end(&mut self);
+
+    /// Restores the component to its checkpoint at line in a recovery line, e.g. after it was
+    /// reopened from its persisted state, and sends what the rest of the federation needs to
+    /// catch up with gvt, the latest GVT it reached (see RollbackManager::recover)
+    ///
+    /// A reopened component must be recovered instead of started, and the messages it sent
+    /// on init are sent again if they are not earlier than gvt.
+    ///
+    /// Fails for components that cannot be recovered.
+    fn recover(
+        &mut self,
+        _line: Timestamp,
+        _gvt: Timestamp,
+        _messenger: &Messenger,
+    ) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("component {} cannot be recovered", self.id()),
+        ))
+    }
+
+    /// Timestamp and dependency vector of every checkpoint of the component, from which
+    /// recovery lines are computed (see recovery_line)
+    ///
+    /// Fails for components that cannot be recovered.
+    fn checkpoints(&self) -> io::Result<History> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("component {} cannot be recovered", self.id()),
+        ))
+    }
+
+    /// Whether checkpoints were taken or freed since the last call, i.e. whether checkpoints
+    /// may return something else than it did then
+    fn checkpoints_changed(&mut self) -> bool {
+        false
+    }
+
+    /// Changes when the component takes checkpoints; must be called before start
+    fn set_checkpoint_mode(&mut self, _mode: CheckpointMode) {}
+
+    /// Records the component's current state and the messages it holds back, for a snapshot
+    /// of the federation (see the snapshot module)
+    ///
+    /// Fails for components whose state cannot be serialized.
+    fn snapshot(&self) -> io::Result<ComponentSnapshot> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("the state of component {} cannot be serialized", self.id()),
+        ))
+    }
+
+    /// Saves what the component must be restored from to resume the simulation from at, once
+    /// GVT reached it (see the savepoint module)
+    ///
+    /// Fails for components whose state cannot be serialized.
+    fn save(&self, _at: Timestamp) -> io::Result<SavedComponent> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("the state of component {} cannot be serialized", self.id()),
+        ))
+    }
+}
+
+/// Runs a single component optimistically: it keeps the component's history in a
+/// RollbackManager, rolls the component back whenever a straggler or an anti-message arrives
+/// and executes commit actions once GVT passes them.
+///
+/// Checkpoints are kept in the given CheckpointStore, in memory by default.
+///
+/// Every message the component sends carries its dependency vector, and the vector of every
+/// message it handles is merged into its own (see DependencyVector). In
+/// CheckpointMode::CommunicationInduced, these vectors also decide when checkpoints are forced.
+pub struct ComponentManager<State, Store = LinkedList<Checkpoint<State>>> {
+    gateway: Box<dyn Gateway<State> + Send>,
+    should_take_checkpoint: fn(&State, &RollbackManager<State, Store>) -> bool,
+    rollback_manager: RollbackManager<State, Store>,
+    checkpoint_mode: CheckpointMode,
+
+    // messages emitted on init, sent when the component is started, and again when it is
+    // recovered if they are still needed
+    outbox: Vec<Message>,
+
+    // anti-messages that arrived before the message they cancel
+    pending_antis: Vec<Message>,
+
+    committed_gvt: Timestamp,
+
+    // the checkpoint in the latest recovery line the component freed its history to, which
+    // may advance while GVT does not
+    committed_line: Option<Timestamp>,
+
+    // messages earlier than the GVT the component was recovered to were already sent
+    // before, so they are not sent again while it catches up; the same goes for actions
+    // earlier than committed_gvt, which were executed
+    replay_line: Timestamp,
+
+    // whether checkpoints were taken or freed since the scheduler last asked, see
+    // Runnable::checkpoints_changed
+    checkpoints_changed: bool,
+
+    // None unless the state can be serialized, see serializable
+    serialize_state: Option<fn(&State) -> serde_json::Result<serde_json::Value>>,
+}
+
+impl<State> ComponentManager<State>
+where
+    State: Clone,
+{
+    /// Constructor; initializes the component right away
+    pub fn new(
+        id: ComponentId,
+        gateway: Box<dyn Gateway<State> + Send>,
+        should_take_checkpoint: fn(&State, &RollbackManager<State>) -> bool,
+    ) -> ComponentManager<State> {
+        ComponentManager::with_store(id, gateway, should_take_checkpoint, LinkedList::new())
+            .expect("in-memory checkpoints cannot fail")
+    }
+}
+
+impl<State, Store> ComponentManager<State, Store>
+where
+    State: Clone,
+    Store: CheckpointStore<State>,
+{
+    /// Same as new, but checkpoints are kept in the given store; fails if the store cannot
+    /// be written
+    pub fn with_store(
+        id: ComponentId,
+        gateway: Box<dyn Gateway<State> + Send>,
+        should_take_checkpoint: fn(&State, &RollbackManager<State, Store>) -> bool,
+        checkpoints: Store,
+    ) -> Result<ComponentManager<State, Store>, Failure> {
+        let (initial_state, outbox, actions) = gateway.init();
+        let rollback_manager = RollbackManager::with_store(id, initial_state, checkpoints)?;
+        ComponentManager::from_parts(
+            gateway,
+            should_take_checkpoint,
+            rollback_manager,
+            outbox,
+            actions,
+        )
+    }
+
+    /// Reopens a component whose checkpoints and message log were persisted before its node
+    /// stopped (see RollbackManager::reopen); it must be recovered instead of started
+    ///
+    /// The component is initialized again, but only to emit the messages of its init that
+    /// must be sent again; its actions are in the log already.
+    pub fn reopen(
+        id: ComponentId,
+        gateway: Box<dyn Gateway<State> + Send>,
+        should_take_checkpoint: fn(&State, &RollbackManager<State, Store>) -> bool,
+        checkpoints: Store,
+        log: MessageLog,
+    ) -> Result<ComponentManager<State, Store>, Failure> {
+        let (_, outbox, _) = gateway.init();
+        let rollback_manager = RollbackManager::reopen(id, checkpoints, log)?;
+        let executed = rollback_manager.executed();
+        let mut manager = ComponentManager::from_parts(
+            gateway,
+            should_take_checkpoint,
+            rollback_manager,
+            outbox,
+            Vec::new(),
+        )?;
+        manager.committed_gvt = executed;
+        Ok(manager)
+    }
+
+    /// Restores a component from what a savepoint holds of it (see the savepoint module):
+    /// it resumes from the given checkpoint and sends the pending messages once it is started
+    ///
+    /// The component is initialized again, but only to emit the messages and actions of its
+    /// init that are not earlier than at.
+    pub fn restore(
+        id: ComponentId,
+        gateway: Box<dyn Gateway<State> + Send>,
+        should_take_checkpoint: fn(&State, &RollbackManager<State, Store>) -> bool,
+        checkpoints: Store,
+        saved: (Timestamp, Checkpoint<State>, Vec<Message>),
+    ) -> Result<ComponentManager<State, Store>, Failure> {
+        let (at, checkpoint, pending) = saved;
+        let (_, mut outbox, mut actions) = gateway.init();
+        outbox.retain(|msg| msg.exec_ts >= at);
+        outbox.extend(pending);
+        actions.retain(|action| action.timestamp >= at);
+        let rollback_manager = RollbackManager::from_checkpoint(id, checkpoint, checkpoints)?;
+        let mut manager = ComponentManager::from_parts(
+            gateway,
+            should_take_checkpoint,
+            rollback_manager,
+            outbox,
+            actions,
+        )?;
+        manager.replay_line = at;
+        manager.committed_gvt = at;
+        Ok(manager)
+    }
+
+    fn from_parts(
+        gateway: Box<dyn Gateway<State> + Send>,
+        should_take_checkpoint: fn(&State, &RollbackManager<State, Store>) -> bool,
+        mut rollback_manager: RollbackManager<State, Store>,
+        outbox: Vec<Message>,
+        actions: Vec<CommitAction>,
+    ) -> Result<ComponentManager<State, Store>, Failure> {
+        for action in actions {
+            rollback_manager.save_action(action)?;
+        }
+        Ok(ComponentManager {
+            gateway,
+            should_take_checkpoint,
+            rollback_manager,
+            checkpoint_mode: CheckpointMode::default(),
+            outbox,
+            pending_antis: Vec::new(),
+            committed_gvt: 0,
+            committed_line: None,
+            replay_line: 0,
+            checkpoints_changed: false,
+            serialize_state: None,
+        })
+    }
+
+    /// Lets the component's state be recorded by snapshots (see Runnable::snapshot)
+    pub fn serializable(mut self) -> ComponentManager<State, Store>
+    where
+        State: Serialize,
+    {
+        self.serialize_state = Some(|state| serde_json::to_value(state));
+        self
+    }
+
+    /// Writes the component's history ahead to the given log (see RollbackManager::set_log)
+    pub fn set_log(&mut self, log: MessageLog) -> Result<(), Failure> {
+        self.rollback_manager.set_log(log)
+    }
+
+    pub fn rollback_manager(&self) -> &RollbackManager<State, Store> {
+        &self.rollback_manager
+    }
+
+    /// Rolls back to ts and sends whatever the rollback requires, except for the message
+    /// cancelled by the given anti-message
+    ///
+    /// The component coasts forward from the restored checkpoint to ts: what it handled in
+    /// between is handled again (see Gateway::replay), but what it sends and the actions it
+    /// emits are discarded, since they were sent or emitted already. Nothing earlier than the
+    /// committed GVT is ever sent again, as its destination may have freed what it needs to
+    /// handle it.
+    fn rollback(
+        &mut self,
+        ts: Timestamp,
+        messenger: &Messenger,
+        cancelled_by: Option<&Message>,
+    ) -> Result<(), Failure> {
+        for msg in self.undo(ts, cancelled_by)? {
+            messenger.send(msg).unwrap();
+        }
+        Ok(())
+    }
+
+    // same as rollback, but returns what must be sent instead of sending it
+    fn undo(
+        &mut self,
+        ts: Timestamp,
+        cancelled_by: Option<&Message>,
+    ) -> Result<Vec<Message>, Failure> {
+        let (msgs, to_coast_through) = self.rollback_manager.rollback(ts)?;
+        self.checkpoints_changed = true;
+        for msg in to_coast_through {
+            let state = self.rollback_manager.state().clone();
+            let state = self.gateway.replay(state, msg.clone());
+            self.rollback_manager.coast(&msg, state)?;
+        }
+        self.gateway.on_rollback(self.rollback_manager.state(), ts);
+        Ok(msgs
+            .into_iter()
+            .filter(|msg| {
+                !cancelled_by.is_some_and(|anti| anti.is_inverse_of(msg))
+                    && msg.exec_ts >= self.committed_gvt
+            })
+            .collect())
+    }
+
+    fn serialize(&self, state: &State) -> io::Result<serde_json::Value> {
+        match self.serialize_state {
+            Some(serialize) => Ok(serialize(state)?),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "the state of component {} cannot be serialized",
+                    self.rollback_manager.id()
+                ),
+            )),
+        }
+    }
+
+    // hands msg over to the component, then sends what it sent and saves what it emitted
+    fn execute(&mut self, msg: Message, messenger: &Messenger) -> Result<(), Failure> {
+        self.rollback_manager.save_received_message(msg.clone())?;
+        self.rollback_manager.merge_dependencies(&msg.dependencies);
+
+        let ts = msg.exec_ts;
+        let state = self.rollback_manager.state().clone();
+        let (new_state, msgs, actions) = self.gateway.on_message(state, msg);
+        self.rollback_manager.update(new_state, ts)?;
+
+        for mut msg in msgs {
+            if msg.exec_ts < self.replay_line {
+                continue;
+            }
+            msg.dependencies = self.rollback_manager.dependencies().get_map().clone();
+            self.rollback_manager.save_sent_message(msg.clone())?;
+            messenger.send(msg).unwrap();
+        }
+
+        for action in actions {
+            if action.timestamp < self.committed_gvt {
+                continue;
+            }
+            self.rollback_manager.save_action(action)?;
+        }
+        Ok(())
+    }
+
+    // whether a checkpoint must be taken right before msg is handled, whatever the policy
+    fn must_checkpoint(&self, msg: &Message) -> bool {
+        // a message that depends on a state of the component that was rolled back is an
+        // orphan whose anti-message is on its way, so a checkpoint is forced right before it
+        // for that anti-message to undo it without undoing anything else
+        let orphan = self
+            .rollback_manager
+            .dependencies()
+            .check(&msg.dependencies)
+            .is_err();
+        // in communication-induced mode, the dependencies of the current checkpoint interval
+        // are fixed once the component sent a message in it
+        let induced = self.checkpoint_mode == CheckpointMode::CommunicationInduced
+            && self.rollback_manager.sent_since_checkpoint()
+            && self
+                .rollback_manager
+                .dependencies()
+                .is_new(&msg.dependencies);
+        orphan || induced
+    }
+
+    fn cancel(&mut self, anti: Message, messenger: &Messenger) -> Result<(), Failure> {
+        let handled = self
+            .rollback_manager
+            .received_messages()
+            .iter()
+            .any(|msg| anti.is_inverse_of(msg));
+        if handled {
+            self.rollback(anti.exec_ts, messenger, Some(&anti))?;
+        } else {
+            self.pending_antis.push(anti);
+        }
+        Ok(())
+    }
+}
+
+impl<State, Store> Runnable for ComponentManager<State, Store>
+where
+    State: Clone + Send,
+    Store: CheckpointStore<State> + Send,
+{
+    fn id(&self) -> ComponentId {
+        self.rollback_manager.id()
+    }
+
+    fn start(&mut self, messenger: &Messenger) {
+        // these messages are not saved and depend on nothing: rolling back never undoes the
+        // component's init
+        for msg in self.outbox.iter() {
+            messenger.send(msg.clone()).unwrap();
+        }
+    }
+
+    fn handle(&mut self, msg: Message, messenger: &Messenger) -> io::Result<()> {
+        if msg.is_anti {
+            return Ok(self.cancel(msg, messenger)?);
+        }
+        if let Some(index) = self
+            .pending_antis
+            .iter()
+            .position(|a| a.is_inverse_of(&msg))
+        {
+            self.pending_antis.remove(index);
+            return Ok(());
+        }
+
+        if msg.exec_ts < self.rollback_manager.lvt() {
+            self.rollback(msg.exec_ts, messenger, None)?;
+        }
+
+        // a checkpoint cannot be taken between two messages executed at the same time, so
+        // the messages the component handled at that time are undone, then handled again
+        // along with msg right after the checkpoint, as if they were all received before
+        // anything was sent at that time; unless the component was restored exactly to a
+        // checkpoint at that time, msg is then later than its LVT
+        let mut simultaneous = Vec::new();
+        let ts = msg.exec_ts;
+        if ts == self.rollback_manager.lvt() && self.must_checkpoint(&msg) {
+            for undone in self.undo(ts, None)? {
+                if !undone.is_anti && undone.to == self.id() && undone.exec_ts == ts {
+                    simultaneous.push(undone);
+                } else {
+                    messenger.send(undone).unwrap();
+                }
+            }
+        }
+        if ts > self.rollback_manager.lvt()
+            && (!simultaneous.is_empty()
+                || self.must_checkpoint(&msg)
+                || (self.should_take_checkpoint)(
+                    self.rollback_manager.state(),
+                    &self.rollback_manager,
+                ))
+        {
+            self.rollback_manager.take_checkpoint()?;
+            self.checkpoints_changed = true;
+        }
+
+        for received in simultaneous.iter() {
+            self.rollback_manager
+                .merge_dependencies(&received.dependencies);
+        }
+        self.execute(msg, messenger)?;
+        for received in simultaneous {
+            self.execute(received, messenger)?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self, gvt: Timestamp, line: Option<Timestamp>) -> io::Result<()> {
+        if gvt <= self.committed_gvt {
+            if line > self.committed_line {
+                self.rollback_manager
+                    .fossil_collect(self.committed_gvt, line)?;
+                self.checkpoints_changed = true;
+                self.committed_line = line;
+            }
+            return Ok(());
+        }
+        let actions = self.rollback_manager.commit(gvt);
+        for action in actions.iter() {
+            self.gateway.execute(action);
+        }
+        if !actions.is_empty() {
+            self.rollback_manager.mark_executed(gvt)?;
+        }
+        self.rollback_manager.fossil_collect(gvt, line)?;
+        self.checkpoints_changed = true;
+        self.gateway.on_commit(self.rollback_manager.state(), gvt);
+        self.committed_gvt = gvt;
+        self.committed_line = line;
+        Ok(())
+    }
+
+    fn end(&mut self) {
+        self.gateway.on_end(self.rollback_manager.state());
+    }
+
+    fn recover(
+        &mut self,
+        line: Timestamp,
+        gvt: Timestamp,
+        messenger: &Messenger,
+    ) -> io::Result<()> {
+        let msgs = self.rollback_manager.recover(line, gvt)?;
+        self.checkpoints_changed = true;
+        // whatever was waiting for an anti-message was dropped along with the queues
+        self.pending_antis.clear();
+        self.replay_line = gvt;
+        self.gateway
+            .on_rollback(self.rollback_manager.state(), line);
+        let outbox = self.outbox.iter().filter(|msg| msg.exec_ts >= gvt).cloned();
+        for msg in outbox.chain(msgs) {
+            messenger.send(msg).unwrap();
+        }
+        Ok(())
+    }
+
+    fn checkpoints(&self) -> io::Result<History> {
+        self.rollback_manager.checkpoints().dependencies()
+    }
+
+    fn checkpoints_changed(&mut self) -> bool {
+        std::mem::take(&mut self.checkpoints_changed)
+    }
+
+    fn set_checkpoint_mode(&mut self, mode: CheckpointMode) {
+        self.checkpoint_mode = mode;
+    }
+
+    fn snapshot(&self) -> io::Result<ComponentSnapshot> {
+        let state = self.serialize(self.rollback_manager.state())?;
+        Ok(ComponentSnapshot {
+            id: self.id(),
+            lvt: self.rollback_manager.lvt(),
+            state,
+            queued: self.pending_antis.clone(),
+            in_transit: Vec::new(),
+        })
+    }
+
+    fn save(&self, at: Timestamp) -> io::Result<SavedComponent> {
+        let (checkpoint, pending) = self.rollback_manager.savepoint(at)?;
+        Ok(SavedComponent {
+            id: self.id(),
+            at,
+            checkpoint: Checkpoint {
+                timestamp: checkpoint.timestamp,
+                state: self.serialize(&checkpoint.state)?,
+                dependencies: checkpoint.dependencies,
+            },
+            pending,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gvt::Gvt;
+    use crate::models::{Dependencies, Packet};
+    use crate::scheduler::Scheduler;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    /// Counts the messages it handles and answers each of them
+    struct Echo;
+
+    impl Gateway<u32> for Echo {
+        fn init(&self) -> (u32, Vec<Message>, Vec<CommitAction>) {
+            (0, Vec::new(), Vec::new())
+        }
+
+        fn on_message(&self, state: u32, msg: Message) -> (u32, Vec<Message>, Vec<CommitAction>) {
+            let reply = Message {
+                from: msg.to,
+                to: msg.from,
+                sent_ts: msg.exec_ts,
+                exec_ts: msg.exec_ts + 1,
+                ..msg
+            };
+            (state + 1, vec![reply], Vec::new())
+        }
+    }
+
+    /// Same as Echo, but every message also emits a commit action, recorded once executed
+    struct Ledger(Arc<Mutex<Vec<Timestamp>>>);
+
+    impl Gateway<u32> for Ledger {
+        fn init(&self) -> (u32, Vec<Message>, Vec<CommitAction>) {
+            (0, Vec::new(), Vec::new())
+        }
+
+        fn on_message(&self, state: u32, msg: Message) -> (u32, Vec<Message>, Vec<CommitAction>) {
+            let action = CommitAction {
+                timestamp: msg.exec_ts,
+                payload: String::default(),
+            };
+            let (state, replies, _) = Echo.on_message(state, msg);
+            (state, replies, vec![action])
+        }
+
+        fn execute(&self, action: &CommitAction) {
+            self.0.lock().unwrap().push(action.timestamp);
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Hook {
+        Execute(Timestamp),
+        Rollback { state: u32, ts: Timestamp },
+        Commit { state: u32, gvt: Timestamp },
+    }
+
+    /// Same as Ledger, but records the actions it executes along with the rollbacks and
+    /// commits it is notified of, in order
+    struct Hooks(Arc<Mutex<Vec<Hook>>>);
+
+    impl Gateway<u32> for Hooks {
+        fn init(&self) -> (u32, Vec<Message>, Vec<CommitAction>) {
+            (0, Vec::new(), Vec::new())
+        }
+
+        fn on_message(&self, state: u32, msg: Message) -> (u32, Vec<Message>, Vec<CommitAction>) {
+            Ledger(Arc::default()).on_message(state, msg)
+        }
+
+        fn execute(&self, action: &CommitAction) {
+            self.0.lock().unwrap().push(Hook::Execute(action.timestamp));
+        }
+
+        fn on_rollback(&self, state: &u32, ts: Timestamp) {
+            let state = *state;
+            self.0.lock().unwrap().push(Hook::Rollback { state, ts });
+        }
+
+        fn on_commit(&self, state: &u32, gvt: Timestamp) {
+            let state = *state;
+            self.0.lock().unwrap().push(Hook::Commit { state, gvt });
+        }
+    }
+
+    fn get_message(exec_ts: Timestamp, dependencies: Dependencies) -> Message {
+        Message {
+            id: exec_ts as u32,
+            is_anti: false,
+            epoch: 0,
+            dependencies,
+            from: 2,
+            to: 1,
+            sent_ts: 0,
+            exec_ts,
+            route: String::from("in"),
+            payload: String::default(),
+        }
+    }
+
+    #[test]
+    fn orphans_force_a_checkpoint_and_messages_carry_dependencies() {
+        let (network_sender, network_receiver) = channel();
+        let messenger = Messenger {
+            scheduler: Arc::new(Scheduler::new(Vec::new(), Timestamp::MAX)),
+            network_sender,
+            gvt: Arc::new(Gvt::new()),
+        };
+        let mut manager = ComponentManager::new(1, Box::new(Echo), |_, _| false);
+
+        let dependencies: Dependencies = vec![(2, 8)].into_iter().collect();
+        manager
+            .handle(get_message(10, dependencies), &messenger)
+            .unwrap();
+        let orphan: Dependencies = vec![(1, 50), (3, 4)].into_iter().collect();
+        manager.handle(get_message(20, orphan), &messenger).unwrap();
+
+        let rollback_manager = manager.rollback_manager();
+        assert_eq!(rollback_manager.checkpoints().timestamps(), vec![0, 11]);
+        let expected: Dependencies = vec![(1, 20), (2, 8), (3, 4)].into_iter().collect();
+        assert_eq!(rollback_manager.dependencies().get_map(), &expected);
+
+        let replies: Vec<_> = network_receiver.try_iter().collect();
+        match &replies[..] {
+            [Packet::Message(first), Packet::Message(second)] => {
+                assert_eq!(
+                    first.dependencies,
+                    vec![(1, 10), (2, 8)].into_iter().collect()
+                );
+                assert_eq!(second.dependencies, expected);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn communication_induced_checkpoints_fix_dependencies_after_a_send() {
+        let (network_sender, _network_receiver) = channel();
+        let messenger = Messenger {
+            scheduler: Arc::new(Scheduler::new(Vec::new(), Timestamp::MAX)),
+            network_sender,
+            gvt: Arc::new(Gvt::new()),
+        };
+        for (mode, expected) in [
+            (CheckpointMode::Policy, vec![0]),
+            (CheckpointMode::CommunicationInduced, vec![0, 21]),
+        ] {
+            let mut manager = ComponentManager::new(1, Box::new(Echo), |_, _| false);
+            manager.set_checkpoint_mode(mode);
+            let first: Dependencies = vec![(2, 8)].into_iter().collect();
+            manager
+                .handle(get_message(10, first.clone()), &messenger)
+                .unwrap();
+            manager.handle(get_message(20, first), &messenger).unwrap();
+            let new: Dependencies = vec![(3, 4)].into_iter().collect();
+            manager.handle(get_message(30, new), &messenger).unwrap();
+            assert_eq!(
+                manager.rollback_manager().checkpoints().timestamps(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn messages_at_the_same_time_are_handled_again_after_a_forced_checkpoint() {
+        let (network_sender, network_receiver) = channel();
+        let messenger = Messenger {
+            scheduler: Arc::new(Scheduler::new(Vec::new(), Timestamp::MAX)),
+            network_sender,
+            gvt: Arc::new(Gvt::new()),
+        };
+        let mut manager = ComponentManager::new(1, Box::new(Echo), |_, _| false);
+        manager.set_checkpoint_mode(CheckpointMode::CommunicationInduced);
+        let first: Dependencies = vec![(2, 8)].into_iter().collect();
+        manager.handle(get_message(10, first), &messenger).unwrap();
+        manager
+            .handle(get_message(20, Dependencies::new()), &messenger)
+            .unwrap();
+        network_receiver.try_iter().for_each(drop);
+
+        // the reply to the message at 20 was sent before the new dependency arrived
+        let new: Dependencies = vec![(3, 4)].into_iter().collect();
+        manager.handle(get_message(20, new), &messenger).unwrap();
+
+        let rollback_manager = manager.rollback_manager();
+        assert_eq!(rollback_manager.checkpoints().timestamps(), vec![0, 11]);
+        assert_eq!(*rollback_manager.state(), 3);
+        let expected: Dependencies = vec![(1, 20), (2, 8), (3, 4)].into_iter().collect();
+        assert_eq!(rollback_manager.dependencies().get_map(), &expected);
+        let sent: Vec<_> = network_receiver
+            .try_iter()
+            .map(|packet| match packet {
+                Packet::Message(msg) => (msg.is_anti, msg.exec_ts, msg.dependencies),
+                _ => panic!(),
+            })
+            .collect();
+        let first_reply: Dependencies = vec![(1, 20), (2, 8)].into_iter().collect();
+        assert_eq!(
+            sent,
+            vec![
+                (true, 21, first_reply),
+                (false, 21, expected.clone()),
+                (false, 21, expected)
+            ]
+        );
+    }
+
+    #[test]
+    fn stragglers_coast_forward_from_checkpoints_older_than_gvt() {
+        let (network_sender, network_receiver) = channel();
+        let messenger = Messenger {
+            scheduler: Arc::new(Scheduler::new(Vec::new(), Timestamp::MAX)),
+            network_sender,
+            gvt: Arc::new(Gvt::new()),
+        };
+        let executed = Arc::new(Mutex::new(Vec::new()));
+        let mut manager =
+            ComponentManager::new(1, Box::new(Ledger(executed.clone())), |_, _| false);
+        for ts in [5, 10, 15] {
+            manager
+                .handle(get_message(ts, Dependencies::new()), &messenger)
+                .unwrap();
+        }
+        manager.commit(12, None).unwrap();
+        assert_eq!(*executed.lock().unwrap(), vec![5, 10]);
+        // the only checkpoint left is older than GVT
+        assert_eq!(
+            manager.rollback_manager().checkpoints().timestamps(),
+            vec![0]
+        );
+        network_receiver.try_iter().for_each(drop);
+
+        // only what was sent at 13 or later is cancelled or handled again
+        manager
+            .handle(get_message(13, Dependencies::new()), &messenger)
+            .unwrap();
+        let mut sent: Vec<_> = network_receiver
+            .try_iter()
+            .map(|packet| match packet {
+                Packet::Message(msg) => (msg.is_anti, msg.exec_ts),
+                _ => panic!(),
+            })
+            .collect();
+        sent.sort();
+        assert_eq!(sent, vec![(false, 14), (false, 15), (true, 16)]);
+        assert_eq!(*manager.rollback_manager().state(), 3);
+
+        manager
+            .handle(get_message(15, Dependencies::new()), &messenger)
+            .unwrap();
+        manager.commit(20, None).unwrap();
+        assert_eq!(*executed.lock().unwrap(), vec![5, 10, 13, 15]);
+    }
+
+    #[test]
+    fn on_rollback_is_called_with_the_restored_state_after_a_straggler() {
+        let (network_sender, _network_receiver) = channel();
+        let messenger = Messenger {
+            scheduler: Arc::new(Scheduler::new(Vec::new(), Timestamp::MAX)),
+            network_sender,
+            gvt: Arc::new(Gvt::new()),
+        };
+        let hooks = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = ComponentManager::new(1, Box::new(Hooks(hooks.clone())), |_, _| true);
+        manager
+            .handle(get_message(10, Dependencies::new()), &messenger)
+            .unwrap();
+        manager
+            .handle(get_message(20, Dependencies::new()), &messenger)
+            .unwrap();
+        assert!(hooks.lock().unwrap().is_empty());
+
+        // the checkpoint taken right before the message at 20 is restored
+        manager
+            .handle(get_message(15, Dependencies::new()), &messenger)
+            .unwrap();
+        assert_eq!(
+            *hooks.lock().unwrap(),
+            vec![Hook::Rollback { state: 1, ts: 15 }]
+        );
+        assert_eq!(*manager.rollback_manager().state(), 2);
+    }
+
+    #[test]
+    fn on_commit_is_called_once_the_committed_actions_ran() {
+        let (network_sender, _network_receiver) = channel();
+        let messenger = Messenger {
+            scheduler: Arc::new(Scheduler::new(Vec::new(), Timestamp::MAX)),
+            network_sender,
+            gvt: Arc::new(Gvt::new()),
+        };
+        let hooks = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = ComponentManager::new(1, Box::new(Hooks(hooks.clone())), |_, _| false);
+        for ts in [5, 10, 15] {
+            manager
+                .handle(get_message(ts, Dependencies::new()), &messenger)
+                .unwrap();
+        }
+        manager.commit(12, None).unwrap();
+        assert_eq!(
+            *hooks.lock().unwrap(),
+            vec![
+                Hook::Execute(5),
+                Hook::Execute(10),
+                Hook::Commit { state: 3, gvt: 12 }
+            ]
+        );
+
+        // GVT did not advance, so there is nothing to notify
+        manager.commit(12, None).unwrap();
+        assert_eq!(hooks.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,718 @@
+use crate::component::Component;
+use crate::dead_letter::{DeadLetterCfg, DeadLetterSink};
+use crate::message_log::SyncPolicy;
+use crate::models::{ComponentId, Timestamp};
+use crate::route_pattern;
+use crate::translator::{Destination, Translator};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Declarative description of a whole federation, read from a JSON file
+///
+/// ```json
+/// {
+///     "end_ts": 1000,
+///     "workers": 4,
+///     "sync": "conservative",
+///     "checkpoints": "communication_induced",
+///     "dead_letters": { "file": { "path": "dead_letters.jsonl" } },
+///     "persistence": { "dir": "state", "sync": "flush" },
+///     "nodes": [
+///         { "name": "A", "address": "127.0.0.1:8000" },
+///         { "name": "B", "address": "127.0.0.1:8001" }
+///     ],
+///     "components": [
+///         {
+///             "id": 1, "type": "vehicle", "node": "A",
+///             "params": { "mass": 1200 },
+///             "routes": {
+///                 "position": [
+///                     { "to": 2, "route": "vehicle_position", "delay": 5 },
+///                     { "to": 3, "route": "in", "delay": 1 }
+///                 ],
+///                 "speed": {
+///                     "to": 2, "route": "vehicle_speed", "delay": 5,
+///                     "transform": [{ "scale": { "field": "v", "factor": 3.6 } }]
+///                 },
+///                 "wheel/*": { "to": 3, "route": "wheel_$1", "delay": 1 }
+///             }
+///         },
+///         { "id": 2, "type": "monitor", "node": "B" },
+///         { "id": 3, "type": "logger", "node": "B" }
+///     ]
+/// }
+/// ```
+///
+/// The same file is given to every node; each node only runs the components assigned to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FederationCfg {
+    /// The simulation ends once GVT passes end_ts, or once there are no messages left if None
+    #[serde(default)]
+    pub end_ts: Option<Timestamp>,
+
+    /// Number of worker threads of every node
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+
+    /// "optimistic" (the default) or "conservative"
+    #[serde(default)]
+    pub sync: SyncMode,
+
+    /// "policy" (the default) or "communication_induced"
+    #[serde(default)]
+    pub checkpoints: CheckpointMode,
+
+    /// Where messages emitted through unknown routes go: "log" (the default), "discard" or
+    /// { "file": { "path": ... } }
+    #[serde(default)]
+    pub dead_letters: DeadLetterCfg,
+
+    /// Where nodes persist their state so that the federation can be recovered after a crash;
+    /// nothing is persisted if None
+    #[serde(default)]
+    pub persistence: Option<PersistenceCfg>,
+
+    pub nodes: Vec<NodeCfg>,
+    pub components: Vec<ComponentCfg>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeCfg {
+    pub name: String,
+    pub address: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentCfg {
+    pub id: ComponentId,
+
+    /// Name under which the component's type is known to the node
+    #[serde(rename = "type")]
+    pub type_name: String,
+
+    /// Name of the node that runs the component
+    pub node: String,
+
+    /// Deserialized into the component's Params when its Translator is built
+    #[serde(default)]
+    pub params: serde_json::Value,
+
+    /// Translator route table: where each route the component emits is delivered
+    ///
+    /// A route may lead to a single destination or to a list of them
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub routes: HashMap<String, Vec<RouteCfg>>,
+}
+
+/// How nodes synchronize their components
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncMode {
+    /// Components run ahead and are rolled back when they receive a straggler (Time Warp)
+    #[default]
+    Optimistic,
+
+    /// Components only handle messages that are known to be safe: every link must have a
+    /// delay, and after each GVT round nodes only handle messages earlier than GVT plus the
+    /// lowest delay, which no message sent from then on can precede
+    Conservative,
+}
+
+/// When components take checkpoints
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckpointMode {
+    /// Components checkpoint whenever the policy they were registered with asks for it
+    #[default]
+    Policy,
+
+    /// On top of their policy, components are forced to checkpoint before handling a message
+    /// that brings a new dependency once they sent a message since their latest checkpoint
+    /// (Fixed-Dependency-After-Send); messages executed at the same time count as received
+    /// before anything is sent at that time. No checkpoint can then be part of a Z-cycle, so
+    /// every checkpoint belongs to a consistent global checkpoint and rollbacks cannot
+    /// cascade through useless ones (the domino effect)
+    CommunicationInduced,
+}
+
+/// How the state of the nodes is persisted, e.g. { "dir": "state", "in_memory": 16 }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersistenceCfg {
+    /// Every node persists its state in a subdirectory named after it, and every component
+    /// in a subdirectory of its node's named after its id
+    pub dir: PathBuf,
+
+    /// Number of checkpoints every component also keeps in memory (see FileStore)
+    #[serde(default = "default_in_memory")]
+    pub in_memory: usize,
+
+    /// When the message logs of the components are synced to disk
+    #[serde(default)]
+    pub sync: SyncPolicy,
+}
+
+/// A route destination as written in the config file, e.g.
+/// { "to": 2, "route": "in", "delay": 5, "transform": [...] }; the delay and the transforms are
+/// optional
+pub type RouteCfg = Destination;
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<HashMap<String, Vec<RouteCfg>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Destinations {
+        One(RouteCfg),
+        Many(Vec<RouteCfg>),
+    }
+
+    let routes = HashMap::<String, Destinations>::deserialize(deserializer)?;
+    Ok(routes
+        .into_iter()
+        .map(|(route, destinations)| match destinations {
+            Destinations::One(dest) => (route, vec![dest]),
+            Destinations::Many(dests) => (route, dests),
+        })
+        .collect())
+}
+
+/// Everything a node needs to run its share of the federation (see init::init)
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeSetup {
+    pub address: String,
+
+    /// Address of the node running each remote component
+    pub remote_addrs: HashMap<ComponentId, String>,
+
+    pub components: Vec<ComponentCfg>,
+    pub end_ts: Option<Timestamp>,
+    pub workers: usize,
+    pub dead_letters: DeadLetterCfg,
+
+    /// Lookahead of the federation in conservative mode, None in optimistic mode
+    pub lookahead: Option<Timestamp>,
+
+    /// How the node persists its state; dir is the node's own directory
+    pub persistence: Option<PersistenceCfg>,
+
+    pub checkpoints: CheckpointMode,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io(std::io::Error),
+
+    /// The file is not a valid federation description
+    Parse(serde_json::Error),
+
+    /// Two nodes have the same name or the same address
+    DuplicateNode(String),
+
+    /// The federation declares no nodes
+    NoNodes,
+
+    /// A node does not run any component
+    EmptyNode(String),
+
+    /// Two components have the same id
+    DuplicateComponent(ComponentId),
+
+    /// A component is assigned to a node that is not declared
+    UnknownNode {
+        component: ComponentId,
+        node: String,
+    },
+
+    /// A route has an empty list of destinations
+    EmptyRoute {
+        component: ComponentId,
+        route: String,
+    },
+
+    /// A route leads to a component that is not declared
+    UnknownDestination {
+        component: ComponentId,
+        route: String,
+        to: ComponentId,
+    },
+
+    /// A destination refers to a capture its route pattern does not have
+    UnknownCapture {
+        component: ComponentId,
+        route: String,
+        capture: usize,
+    },
+
+    /// Two route patterns match the same name and neither is more specific
+    AmbiguousRoutes {
+        component: ComponentId,
+        route: String,
+        other: String,
+    },
+
+    /// A route has no delay, which conservative mode requires
+    NoLookahead {
+        component: ComponentId,
+        route: String,
+    },
+
+    /// workers must be greater than zero
+    NoWorkers,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "cannot read the config file: {}", e),
+            ConfigError::Parse(e) => write!(f, "invalid config file: {}", e),
+            ConfigError::DuplicateNode(node) => write!(f, "node {} is declared twice", node),
+            ConfigError::NoNodes => write!(f, "no nodes declared"),
+            ConfigError::EmptyNode(node) => write!(f, "node {} runs no components", node),
+            ConfigError::DuplicateComponent(id) => {
+                write!(f, "component {} is declared twice", id)
+            }
+            ConfigError::UnknownNode { component, node } => {
+                write!(f, "component {} runs on unknown node {}", component, node)
+            }
+            ConfigError::EmptyRoute { component, route } => {
+                write!(
+                    f,
+                    "route {} of component {} has no destinations",
+                    route, component
+                )
+            }
+            ConfigError::UnknownDestination {
+                component,
+                route,
+                to,
+            } => write!(
+                f,
+                "route {} of component {} leads to unknown component {}",
+                route, component, to
+            ),
+            ConfigError::UnknownCapture {
+                component,
+                route,
+                capture,
+            } => write!(
+                f,
+                "route {} of component {} has no capture ${}",
+                route, component, capture
+            ),
+            ConfigError::AmbiguousRoutes {
+                component,
+                route,
+                other,
+            } => write!(
+                f,
+                "routes {} and {} of component {} are ambiguous",
+                route, other, component
+            ),
+            ConfigError::NoLookahead { component, route } => write!(
+                f,
+                "route {} of component {} needs a delay in conservative mode",
+                route, component
+            ),
+            ConfigError::NoWorkers => write!(f, "workers must be greater than zero"),
+        }
+    }
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+fn default_in_memory() -> usize {
+    16
+}
+
+impl FederationCfg {
+    /// Reads and validates a config file
+    pub fn load(path: impl AsRef<Path>) -> Result<FederationCfg, ConfigError> {
+        let json = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        FederationCfg::from_json(&json)
+    }
+
+    /// Parses and validates a federation description
+    pub fn from_json(json: &str) -> Result<FederationCfg, ConfigError> {
+        let cfg: FederationCfg = serde_json::from_str(json).map_err(ConfigError::Parse)?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Checks that the topology is consistent: unique nodes and components, every component
+    /// assigned to a declared node, every route leading to a declared component and no
+    /// ambiguous route patterns
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.workers == 0 {
+            return Err(ConfigError::NoWorkers);
+        }
+        if self.nodes.is_empty() {
+            return Err(ConfigError::NoNodes);
+        }
+
+        let mut names = HashSet::new();
+        let mut addresses = HashSet::new();
+        for node in &self.nodes {
+            if !names.insert(&node.name) || !addresses.insert(&node.address) {
+                return Err(ConfigError::DuplicateNode(node.name.clone()));
+            }
+        }
+
+        let mut ids = HashSet::new();
+        for component in &self.components {
+            if !ids.insert(component.id) {
+                return Err(ConfigError::DuplicateComponent(component.id));
+            }
+            if !names.contains(&component.node) {
+                return Err(ConfigError::UnknownNode {
+                    component: component.id,
+                    node: component.node.clone(),
+                });
+            }
+        }
+
+        for component in &self.components {
+            for (route, dests) in &component.routes {
+                if dests.is_empty() {
+                    return Err(ConfigError::EmptyRoute {
+                        component: component.id,
+                        route: route.clone(),
+                    });
+                }
+                if let Some(dest) = dests.iter().find(|d| !ids.contains(&d.to)) {
+                    return Err(ConfigError::UnknownDestination {
+                        component: component.id,
+                        route: route.clone(),
+                        to: dest.to,
+                    });
+                }
+                let captures = route_pattern::capture_count(route);
+                if let Some(capture) = dests
+                    .iter()
+                    .map(|d| route_pattern::max_capture(&d.route))
+                    .find(|c| *c > captures)
+                {
+                    return Err(ConfigError::UnknownCapture {
+                        component: component.id,
+                        route: route.clone(),
+                        capture,
+                    });
+                }
+                if self.sync == SyncMode::Conservative && dests.iter().any(|d| d.delay == 0) {
+                    return Err(ConfigError::NoLookahead {
+                        component: component.id,
+                        route: route.clone(),
+                    });
+                }
+            }
+
+            let mut routes: Vec<&String> = component.routes.keys().collect();
+            routes.sort();
+            for (i, route) in routes.iter().enumerate() {
+                if let Some(other) = routes[i + 1..]
+                    .iter()
+                    .find(|other| route_pattern::ambiguous(route, other))
+                {
+                    return Err(ConfigError::AmbiguousRoutes {
+                        component: component.id,
+                        route: route.to_string(),
+                        other: other.to_string(),
+                    });
+                }
+            }
+        }
+
+        // nodes find each other through the components they run
+        for node in &self.nodes {
+            if !self.components.iter().any(|c| c.node == node.name) {
+                return Err(ConfigError::EmptyNode(node.name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the setup of the given node
+    ///
+    /// Returns None if no such node is declared
+    pub fn node(&self, name: &str) -> Option<NodeSetup> {
+        let address = &self.nodes.iter().find(|n| n.name == name)?.address;
+        let addresses: HashMap<&String, &String> =
+            self.nodes.iter().map(|n| (&n.name, &n.address)).collect();
+        let (components, remote): (Vec<&ComponentCfg>, Vec<&ComponentCfg>) =
+            self.components.iter().partition(|c| c.node == name);
+
+        Some(NodeSetup {
+            address: address.clone(),
+            remote_addrs: remote
+                .into_iter()
+                .map(|c| (c.id, addresses[&c.node].clone()))
+                .collect(),
+            components: components.into_iter().cloned().collect(),
+            end_ts: self.end_ts,
+            workers: self.workers,
+            dead_letters: self.dead_letters.clone(),
+            lookahead: match self.sync {
+                SyncMode::Optimistic => None,
+                SyncMode::Conservative => Some(self.lookahead()),
+            },
+            persistence: self.persistence.clone().map(|p| PersistenceCfg {
+                dir: p.dir.join(name),
+                ..p
+            }),
+            checkpoints: self.checkpoints,
+        })
+    }
+
+    /// Lowest delay among every route, i.e. how far ahead of GVT every component can safely
+    /// run; Timestamp::MAX if there are no routes
+    pub fn lookahead(&self) -> Timestamp {
+        self.components
+            .iter()
+            .flat_map(|c| c.routes.values().flatten())
+            .map(|d| d.delay)
+            .min()
+            .unwrap_or(Timestamp::MAX)
+    }
+}
+
+impl PersistenceCfg {
+    /// Directory where the given component persists its checkpoints and messages
+    pub fn component_dir(&self, id: ComponentId) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+}
+
+impl ComponentCfg {
+    /// Builds the Translator of the component instance; fails if its params are not valid
+    /// for State
+    pub fn translator<State: Component>(
+        &self,
+        dead_letters: Arc<dyn DeadLetterSink>,
+    ) -> Result<Translator<State>, serde_json::Error> {
+        Translator::new(
+            self.id,
+            self.routes.clone(),
+            self.params.clone(),
+            dead_letters,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::Context;
+    use crate::dead_letter::DiscardSink;
+    use crate::models::MsgCore;
+
+    /// Takes a flag, like the players of the federations below
+    struct Player;
+
+    impl Component for Player {
+        type Params = bool;
+
+        fn init(_params: bool, _ctx: &mut Context) -> Self {
+            Player
+        }
+
+        fn on_message(self, _msg: &MsgCore, _ctx: &mut Context) -> Self {
+            self
+        }
+    }
+
+    fn get_json(to: ComponentId) -> String {
+        format!(
+            r#"{{
+                "end_ts": 100,
+                "nodes": [
+                    {{ "name": "A", "address": "127.0.0.1:8000" }},
+                    {{ "name": "B", "address": "127.0.0.1:8001" }}
+                ],
+                "components": [
+                    {{
+                        "id": 1, "type": "player", "node": "A", "params": true,
+                        "routes": {{
+                            "out": {{ "to": {}, "route": "in", "delay": 5 }},
+                            "all": [{{ "to": 2, "route": "a" }}, {{ "to": 1, "route": "b" }}]
+                        }}
+                    }},
+                    {{ "id": 2, "type": "player", "node": "B" }}
+                ]
+            }}"#,
+            to
+        )
+    }
+
+    #[test]
+    fn node_builds_the_setup_of_a_node() {
+        let cfg = FederationCfg::from_json(&get_json(2)).unwrap();
+        assert_eq!(cfg.workers, 1);
+        assert!(cfg.node("C").is_none());
+
+        let setup = cfg.node("A").unwrap();
+        assert_eq!(setup.address, "127.0.0.1:8000");
+        assert_eq!(setup.end_ts, Some(100));
+        assert_eq!(setup.remote_addrs.len(), 1);
+        assert_eq!(setup.remote_addrs[&2], "127.0.0.1:8001");
+        assert_eq!(setup.components.len(), 1);
+        assert_eq!(setup.components[0].type_name, "player");
+        assert_eq!(setup.components[0].params, serde_json::json!(true));
+
+        assert_eq!(setup.dead_letters, DeadLetterCfg::Log);
+        assert_eq!(setup.lookahead, None);
+        assert_eq!(setup.persistence, None);
+
+        let translator = setup.components[0]
+            .translator::<Player>(Arc::new(DiscardSink))
+            .unwrap();
+
+        assert_eq!(translator.local_id, 1);
+        assert_eq!(
+            translator.route_to_dest["out"],
+            vec![Destination {
+                to: 2,
+                route: String::from("in"),
+                delay: 5,
+                transform: Vec::new(),
+            }]
+        );
+        let all: Vec<_> = translator.route_to_dest["all"]
+            .iter()
+            .map(|d| (d.to, d.route.as_str(), d.delay))
+            .collect();
+        assert_eq!(all, vec![(2, "a", 0), (1, "b", 0)]);
+    }
+
+    #[test]
+    fn every_node_persists_its_state_in_its_own_directory() {
+        let json = get_json(2).replacen(
+            r#""end_ts": 100,"#,
+            r#""end_ts": 100, "persistence": { "dir": "state", "sync": { "every": 10 } },"#,
+            1,
+        );
+        let cfg = FederationCfg::from_json(&json).unwrap();
+        let persistence = cfg.node("B").unwrap().persistence.unwrap();
+        assert_eq!(persistence.dir, Path::new("state").join("B"));
+        assert_eq!(persistence.in_memory, 16);
+        assert_eq!(persistence.sync, SyncPolicy::Every(10));
+        assert_eq!(persistence.component_dir(2), Path::new("state/B/2"));
+    }
+
+    #[test]
+    fn routes_must_lead_to_declared_components() {
+        match FederationCfg::from_json(&get_json(3)) {
+            Err(ConfigError::UnknownDestination {
+                component: 1,
+                to: 3,
+                ..
+            }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn components_must_be_unique_and_run_on_declared_nodes() {
+        let mut cfg = FederationCfg::from_json(&get_json(2)).unwrap();
+        cfg.components[1].node = String::from("C");
+        match cfg.validate() {
+            Err(ConfigError::UnknownNode { component: 2, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        cfg.components[1].node = String::from("B");
+        cfg.components[1].id = 1;
+        match cfg.validate() {
+            Err(ConfigError::DuplicateComponent(1)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn every_node_must_run_a_component() {
+        let mut cfg = FederationCfg::from_json(&get_json(2)).unwrap();
+        cfg.components[1].node = String::from("A");
+        match cfg.validate() {
+            Err(ConfigError::EmptyNode(node)) => assert_eq!(node, "B"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn routes_must_have_destinations() {
+        let mut cfg = FederationCfg::from_json(&get_json(2)).unwrap();
+        cfg.components[0].routes.get_mut("all").unwrap().clear();
+        match cfg.validate() {
+            Err(ConfigError::EmptyRoute {
+                component: 1,
+                route,
+            }) => assert_eq!(route, "all"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn conservative_mode_requires_a_delay_on_every_route() {
+        let mut cfg = FederationCfg::from_json(&get_json(2)).unwrap();
+        cfg.sync = SyncMode::Conservative;
+        match cfg.validate() {
+            Err(ConfigError::NoLookahead {
+                component: 1,
+                route,
+            }) => assert_eq!(route, "all"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        for dest in cfg.components[0].routes.get_mut("all").unwrap() {
+            dest.delay = 3;
+        }
+        cfg.validate().unwrap();
+        assert_eq!(cfg.lookahead(), 3);
+        assert_eq!(cfg.node("B").unwrap().lookahead, Some(3));
+    }
+
+    #[test]
+    fn route_patterns_must_be_unambiguous_and_have_their_captures() {
+        let mut cfg = FederationCfg::from_json(&get_json(2)).unwrap();
+        let routes = &mut cfg.components[0].routes;
+        let mut dest = routes["out"][0].clone();
+        dest.route = String::from("$1_$2");
+        routes.insert(String::from("sensor/*"), vec![dest.clone()]);
+        match cfg.validate() {
+            Err(ConfigError::UnknownCapture {
+                component: 1,
+                route,
+                capture: 2,
+            }) => assert_eq!(route, "sensor/*"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let routes = &mut cfg.components[0].routes;
+        dest.route = String::from("$1");
+        routes.insert(String::from("sensor/*"), vec![dest.clone()]);
+        routes.insert(String::from("*/sensor"), vec![dest.clone()]);
+        match cfg.validate() {
+            Err(ConfigError::AmbiguousRoutes {
+                component: 1,
+                route,
+                other,
+            }) => assert_eq!((route.as_str(), other.as_str()), ("*/sensor", "sensor/*")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // a more specific route takes precedence over the others
+        let routes = &mut cfg.components[0].routes;
+        dest.route = String::from("in");
+        routes.remove("*/sensor");
+        routes.insert(String::from("sensor/sensor"), vec![dest.clone()]);
+        routes.insert(String::from("*"), vec![dest]);
+        cfg.validate().unwrap();
+    }
+}
@@ -1,53 +1,44 @@
-use crate::gateway::Gateway;
+use crate::gvt::Gvt;
 use crate::messenger::Messenger;
-use crate::models::ComponentId;
-use crate::msg_queue::MsgQueue;
-use crate::rollback_manager::RollbackManager;
-use std::sync::Arc;
-
-#[allow(dead_code)]
-pub fn consume_msg_queue<State: Clone>(
-    component_id: ComponentId,
-    gateway: impl Gateway<State>,
-    should_take_checkpoint: fn(&State, &RollbackManager<State>) -> bool,
-    messenger: Arc<Messenger>,
-    queue: Arc<MsgQueue>,
-) {
-    let (initial_state, initial_messages) = gateway.init();
-    for msg in initial_messages {
-        messenger.send(msg).unwrap();
-    }
-
-    let mut rollback_manager = RollbackManager::new(component_id, initial_state.clone());
-    let mut current_state = initial_state;
-
-    loop {
-        let received = queue.pop();
-
-        let violates_lcc = received.exec_ts < rollback_manager.lvt();
-        if violates_lcc {
-            let msgs = rollback_manager.rollback(received.exec_ts).unwrap();
-            for msg in msgs {
-                messenger.send(msg).unwrap();
+use crate::models::{Control, Packet, Timestamp};
+use crate::scheduler::Scheduler;
+use std::io;
+
+/// Worker loop: handles the tasks given by the scheduler until it is closed
+///
+/// Messages whose exec_ts is greater than end_ts are dropped without being handled.
+///
+/// If a component fails (see Runnable::handle), the node at address is shut down: its
+/// scheduler is closed, its server is told to terminate, and the error is returned.
+pub fn consume_msg_queue(
+    scheduler: &Scheduler,
+    messenger: &Messenger,
+    gvt: &Gvt,
+    end_ts: Option<Timestamp>,
+    address: &str,
+) -> io::Result<()> {
+    while let Some(task) = scheduler.next() {
+        let mut component = task.component;
+        let mut handled = Ok(());
+        if let Some(received) = task.msg {
+            if end_ts.is_none_or(|end_ts| received.exec_ts <= end_ts) {
+                handled = component.handle(received, messenger);
             }
         }
-
-        if received.exec_ts > rollback_manager.lvt()
-            && should_take_checkpoint(&current_state, &rollback_manager)
-        {
-            rollback_manager.take_checkpoint();
-        }
-
-        rollback_manager.save_message(received.clone()).unwrap();
-
-        let ts = received.exec_ts;
-        let (new_state, msgs) = gateway.on_message(current_state, received);
-        rollback_manager.update(new_state.clone(), ts).unwrap();
-        current_state = new_state;
-
-        for msg in msgs {
-            rollback_manager.save_message(msg.clone()).unwrap();
-            messenger.send(msg).unwrap();
+        let handled = handled.and_then(|()| component.commit(gvt.get(), gvt.line(component.id())));
+        let id = component.id();
+        scheduler.done(component);
+        if let Err(e) = handled {
+            eprintln!("error: component {} failed, stopping the node: {}", id, e);
+            scheduler.close();
+            // the client is still running, since this worker holds a sender
+            let _ = messenger.network_sender.send(Packet::Control {
+                from: String::from(address),
+                to: String::from(address),
+                control: Control::Terminate,
+            });
+            return Err(e);
         }
     }
+    Ok(())
 }
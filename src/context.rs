@@ -0,0 +1,213 @@
+use crate::models::{CommitAction, ComponentId, MsgCore, Timestamp};
+use crate::translator::TranslateError;
+
+/// Execution context handed to a component whenever it is initialized or handles a message.
+///
+/// It tells the component who it is and which event it is handling, and it collects
+/// everything the component emits while handling that event:
+///     1) messages, which are routed to other components by the Translator;
+///     2) events, which are delivered back to the component itself;
+///     3) commit actions, which are executed only after they can no longer be rolled back.
+///
+/// Messages and events are scheduled relative to the LVT, so a component cannot emit anything
+/// into its past. Those that would be scheduled past Timestamp::MAX are not emitted, they are
+/// dead letters instead (see dead_letters).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Context {
+    id: ComponentId,
+    lvt: Timestamp,
+    sender: Option<ComponentId>,
+    sent_ts: Timestamp,
+    messages: Vec<MsgCore>,
+    events: Vec<MsgCore>,
+    actions: Vec<CommitAction>,
+    dead_letters: Vec<TranslateError>,
+}
+
+impl Context {
+    /// Constructor
+    ///
+    /// sender must be None when the context is not related to a received message (i.e. on init)
+    pub fn new(
+        id: ComponentId,
+        lvt: Timestamp,
+        sender: Option<ComponentId>,
+        sent_ts: Timestamp,
+    ) -> Context {
+        Context {
+            id,
+            lvt,
+            sender,
+            sent_ts,
+            messages: Vec::new(),
+            events: Vec::new(),
+            actions: Vec::new(),
+            dead_letters: Vec::new(),
+        }
+    }
+
+    /// Id of the component that is being executed
+    pub fn id(&self) -> ComponentId {
+        self.id
+    }
+
+    /// Current LVT of the component, i.e. the exec_ts of the message being handled
+    pub fn lvt(&self) -> Timestamp {
+        self.lvt
+    }
+
+    /// Id of the component that sent the message being handled
+    pub fn sender(&self) -> Option<ComponentId> {
+        self.sender
+    }
+
+    /// Timestamp at which the message being handled was sent
+    pub fn sent_ts(&self) -> Timestamp {
+        self.sent_ts
+    }
+
+    /// Emits a message through one of the component's routes, to be executed `delay` time
+    /// units after the current LVT
+    pub fn send(&mut self, route: impl Into<String>, payload: impl Into<String>, delay: Timestamp) {
+        let msg = MsgCore {
+            route: route.into(),
+            payload: payload.into(),
+            exec_ts: self.lvt,
+        };
+        if let Some(msg) = self.delay(msg, delay) {
+            self.messages.push(msg);
+        }
+    }
+
+    /// Schedules an event that will be delivered back to this component `delay` time units
+    /// after the current LVT
+    pub fn schedule(
+        &mut self,
+        route: impl Into<String>,
+        payload: impl Into<String>,
+        delay: Timestamp,
+    ) {
+        let event = MsgCore {
+            route: route.into(),
+            payload: payload.into(),
+            exec_ts: self.lvt,
+        };
+        if let Some(event) = self.delay(event, delay) {
+            self.events.push(event);
+        }
+    }
+
+    // moves msg delay time units later, unless that is past Timestamp::MAX, in which case it
+    // is kept as a dead letter
+    fn delay(&mut self, mut msg: MsgCore, delay: Timestamp) -> Option<MsgCore> {
+        match msg.exec_ts.checked_add(delay) {
+            Some(exec_ts) => {
+                msg.exec_ts = exec_ts;
+                Some(msg)
+            }
+            None => {
+                self.dead_letters.push(TranslateError::Overflow {
+                    from: self.id,
+                    msg,
+                    delay,
+                });
+                None
+            }
+        }
+    }
+
+    /// Requests an irreversible side effect (writing a file, driving an actuator...)
+    ///
+    /// The action is handed back to the component's execute function once GVT passes the
+    /// current LVT, and is discarded if the component is rolled back before that
+    pub fn commit(&mut self, payload: impl Into<String>) {
+        self.actions.push(CommitAction {
+            timestamp: self.lvt,
+            payload: payload.into(),
+        });
+    }
+
+    pub fn messages(&self) -> &[MsgCore] {
+        &self.messages
+    }
+
+    pub fn events(&self) -> &[MsgCore] {
+        &self.events
+    }
+
+    pub fn actions(&self) -> &[CommitAction] {
+        &self.actions
+    }
+
+    /// Messages and events that were not emitted because they would be executed past
+    /// Timestamp::MAX; the Translator sends them to its dead-letter sink
+    pub fn dead_letters(&self) -> &[TranslateError] {
+        &self.dead_letters
+    }
+
+    /// Consumes the context, returning the emitted messages, events and commit actions
+    pub fn into_emitted(self) -> (Vec<MsgCore>, Vec<MsgCore>, Vec<CommitAction>) {
+        (self.messages, self.events, self.actions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn send_and_schedule_are_relative_to_lvt() {
+        let mut ctx = Context::new(1, 100, Some(2), 90);
+        ctx.send("out", "a", 5);
+        ctx.schedule("tick", "b", 0);
+        ctx.commit("c");
+
+        let (messages, events, actions) = ctx.into_emitted();
+        assert_eq!(
+            messages,
+            vec![MsgCore {
+                route: String::from("out"),
+                payload: String::from("a"),
+                exec_ts: 105,
+            }]
+        );
+        assert_eq!(
+            events,
+            vec![MsgCore {
+                route: String::from("tick"),
+                payload: String::from("b"),
+                exec_ts: 100,
+            }]
+        );
+        assert_eq!(
+            actions,
+            vec![CommitAction {
+                timestamp: 100,
+                payload: String::from("c"),
+            }]
+        );
+    }
+
+    #[test]
+    fn what_would_be_executed_past_the_latest_time_is_a_dead_letter() {
+        let mut ctx = Context::new(1, 100, Some(2), 90);
+        ctx.send("out", "a", Timestamp::MAX);
+        ctx.schedule("tick", "b", Timestamp::MAX - 100);
+
+        assert_eq!(
+            ctx.dead_letters(),
+            [TranslateError::Overflow {
+                from: 1,
+                msg: MsgCore {
+                    route: String::from("out"),
+                    payload: String::from("a"),
+                    exec_ts: 100,
+                },
+                delay: Timestamp::MAX,
+            }]
+        );
+        let (messages, events, _) = ctx.into_emitted();
+        assert!(messages.is_empty());
+        assert_eq!(events[0].exec_ts, Timestamp::MAX);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::gvt::{self, Gvt};
+use crate::messenger::Messenger;
+use crate::models::{ComponentId, Control, Packet, Timestamp};
+use crate::network;
+use crate::recovery_line::History;
+use crate::savepoint;
+use crate::scheduler::Scheduler;
+use crate::snapshot::Recorder;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::mpsc::{SendError, Sender};
+use std::sync::Arc;
+use std::thread;
+
+/// Handles the control signals a node receives from the coordinator
+#[derive(Clone)]
+pub struct NodeControl {
+    pub address: String,
+    pub gvt: Arc<Gvt>,
+    pub scheduler: Arc<Scheduler>,
+    pub network_sender: Sender<Packet>,
+
+    /// Only set on the coordinator node, which receives the acknowledgements
+    pub coordinator: Option<Sender<(String, Control)>>,
+
+    /// Where every GVT is persisted, if the node persists its state
+    pub gvt_path: Option<PathBuf>,
+
+    pub snapshots: Arc<Recorder>,
+}
+
+impl NodeControl {
+    pub fn handle(&self, from: String, control: Control) {
+        match control {
+            // a node that was restarted takes no part in rounds until it is recovered, and
+            // neither does a node that is halted to recover another one
+            Control::Cut { .. } | Control::Poll { .. } if self.scheduler.is_halted() => (),
+            Control::Cut { epoch } => {
+                let sent = self.gvt.cut(epoch);
+                let checkpoints = self.checkpoints(self.gvt.get());
+                self.reply(
+                    from,
+                    Control::CutAck {
+                        epoch,
+                        sent,
+                        checkpoints,
+                    },
+                );
+            }
+            Control::Poll { epoch } => {
+                // the order matters: once a message is counted as received it is already in a
+                // queue, so it is either reflected in local_min or it was handled and whatever
+                // it caused is reflected in local_min or in red_min
+                let received = self.gvt.received(epoch - 1);
+                let local_min = self.scheduler.local_min();
+                let red_min = self.gvt.red_min();
+                self.reply(
+                    from,
+                    Control::PollAck {
+                        epoch,
+                        received,
+                        local_min,
+                        red_min,
+                    },
+                );
+            }
+            // nor does it commit anything, even if a GVT sent before it crashed reaches it
+            Control::Gvt { .. } if self.scheduler.is_halted() => (),
+            Control::Gvt {
+                value,
+                window,
+                line,
+            } => {
+                // components free their history once they commit, and workers commit as soon
+                // as GVT advances, so GVT must be persisted first to be recovered from
+                if let Some(path) = &self.gvt_path {
+                    if let Err(e) = gvt::save(path, value.max(self.gvt.get())) {
+                        eprintln!("error: cannot persist GVT, stopping the node: {}", e);
+                        self.shut_down();
+                        return;
+                    }
+                    if let Some(line) = line {
+                        self.gvt.set_line(line);
+                    }
+                }
+                self.gvt.advance(value);
+                self.scheduler.set_window(window);
+                self.scheduler.commit_all();
+            }
+            Control::Halt { epoch } => {
+                self.scheduler.halt();
+                let checkpoints = self.checkpoints(Timestamp::MAX);
+                self.reply(
+                    from,
+                    Control::Halted {
+                        epoch,
+                        last_epoch: self.gvt.epoch(),
+                        gvt: self.gvt.get(),
+                        checkpoints,
+                    },
+                );
+            }
+            Control::RecoveryLine {
+                line,
+                gvt,
+                window,
+                epoch,
+            } => {
+                if let Some(path) = &self.gvt_path {
+                    if let Err(e) = gvt::save(path, gvt.max(self.gvt.get())) {
+                        let error = Some(format!("cannot persist GVT: {}", e));
+                        self.reply(from, Control::Recovered { epoch, error });
+                        return;
+                    }
+                }
+                self.gvt.advance(gvt);
+                self.gvt.set_line(line.clone());
+                self.scheduler.set_window(window);
+                self.gvt.restart(epoch);
+                let messenger = Messenger {
+                    scheduler: self.scheduler.clone(),
+                    network_sender: self.network_sender.clone(),
+                    gvt: self.gvt.clone(),
+                };
+                let recovered = self.scheduler.recover(&line, gvt, &messenger);
+                let error = recovered.err().map(|e| e.to_string());
+                self.reply(from, Control::Recovered { epoch, error });
+            }
+            Control::Snapshot { dir } => self.on_marker(dir, None),
+            Control::Marker { dir } => self.on_marker(dir, Some(from)),
+            Control::Save { at, dir } => self.save(from, at, dir),
+            Control::RequestSave { at, reply_to, .. } if self.coordinator.is_none() => {
+                let error = format!("{} is not the coordinator", self.address);
+                self.reply(
+                    reply_to,
+                    Control::Saved {
+                        at,
+                        error: Some(error),
+                    },
+                );
+            }
+            Control::Terminate => {
+                self.gvt.terminate();
+                self.scheduler.close();
+            }
+            ack => {
+                if let Some(coordinator) = &self.coordinator {
+                    // the coordinator stops before the node does, once the simulation is over
+                    if let Err(SendError((_, Control::RequestSave { at, reply_to, .. }))) =
+                        coordinator.send((from, ack))
+                    {
+                        let error = String::from("the simulation is over");
+                        self.reply(
+                            reply_to,
+                            Control::Saved {
+                                at,
+                                error: Some(error),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // checkpoints of every local component that are not later than until, if the node
+    // persists its state (see Scheduler::checkpoints)
+    fn checkpoints(&self, until: Timestamp) -> Option<BTreeMap<ComponentId, History>> {
+        self.gvt_path.as_ref()?;
+        match self.scheduler.checkpoints(until) {
+            Ok(checkpoints) => Some(checkpoints),
+            Err(e) => {
+                eprintln!("warning: cannot read the checkpoints of the node: {}", e);
+                None
+            }
+        }
+    }
+
+    // the node's share of a snapshot is recorded on its own thread, since every component
+    // must be given back first; the packets held back meanwhile are handled once it is done
+    fn on_marker(&self, dir: PathBuf, from: Option<String>) {
+        if !self.snapshots.on_marker(dir, from) {
+            return;
+        }
+        let control = self.clone();
+        thread::spawn(move || {
+            control
+                .snapshots
+                .record(&control.scheduler, &control.network_sender);
+            let messenger = Messenger {
+                scheduler: control.scheduler.clone(),
+                network_sender: control.network_sender.clone(),
+                gvt: control.gvt.clone(),
+            };
+            while let Some(packet) = control.snapshots.release() {
+                network::deliver(&messenger, &control, packet);
+            }
+        });
+    }
+
+    // every component must be given back before the node is saved, which may take long, so
+    // the node is saved on its own thread as well
+    fn save(&self, from: String, at: Timestamp, dir: PathBuf) {
+        let control = self.clone();
+        thread::spawn(move || {
+            let saved = control
+                .scheduler
+                .save(at)
+                .and_then(|saved| savepoint::save(&dir, &saved));
+            let error = saved.err().map(|e| e.to_string());
+            control.reply(from, Control::SaveAck { at, error });
+        });
+    }
+
+    // closes the scheduler and tells the node's server to terminate, as if the node crashed
+    fn shut_down(&self) {
+        self.scheduler.close();
+        self.reply(self.address.clone(), Control::Terminate);
+    }
+
+    fn reply(&self, to: String, control: Control) {
+        self.network_sender
+            .send(Packet::Control {
+                from: self.address.clone(),
+                to,
+                control,
+            })
+            .unwrap();
+    }
+}
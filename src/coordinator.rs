@@ -0,0 +1,330 @@
+use crate::models::{Control, Packet, Timestamp};
+use crate::recovery_line::{self, RecoveryLine};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Time between two GVT rounds
+pub const GVT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time between two polls of the same GVT round
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Why replies stopped being collected
+enum Interrupted {
+    /// A node asked to be recovered
+    Recover,
+    /// The coordinator's node stopped
+    Stopped,
+}
+
+/// Runs GVT rounds until the simulation is over, then tells every node to terminate
+///
+/// In conservative mode, lookahead is the lowest delay of every route; nodes are then told
+/// to only handle messages earlier than GVT + lookahead.
+///
+/// The simulation is over once GVT passes end_ts or once there is nothing left to handle
+/// (GVT is Timestamp::MAX), whichever comes first.
+///
+/// A round is made of a Cut, after which nodes tag the messages they send with the new epoch,
+/// followed by Polls until every message of the previous epoch was received. At that point,
+/// every message that could still cause a rollback is either pending in some queue or was
+/// sent in the current epoch, so GVT is the lowest local_min or red_min.
+///
+/// If nodes persist their state, they also report the checkpoints that can no longer be
+/// rolled back when they are cut; the latest recovery line made of them (see recovery_line)
+/// is sent along with GVT, and components free the checkpoints earlier than it. A node that
+/// was restarted after a crash, the coordinator included, asks to be recovered at any time:
+/// the ongoing round is abandoned and every node is halted, then told to restore its
+/// components to the latest recovery line not later than the latest GVT a node knows of.
+/// Every node still has the checkpoints of the last line that was sent, so there always is
+/// one. Nodes then resume in a new epoch, and messages sent before are dropped.
+///
+/// A savepoint at T can be requested at any time (see savepoint::request). It is taken in the
+/// round in which GVT reaches T, before GVT is sent to the nodes: until then, no node freed
+/// the history it needs to save its components at T. A savepoint that GVT already passed is
+/// rejected, and so is a savepoint the simulation ends before.
+///
+/// If the coordinator's own node stops, e.g. because one of its components failed, the
+/// coordinator stops with it, as if it crashed.
+pub fn run_coordinator(
+    address: String,
+    nodes: Vec<String>,
+    end_ts: Option<Timestamp>,
+    lookahead: Option<Timestamp>,
+    network_sender: Sender<Packet>,
+    acks: Receiver<(String, Control)>,
+) {
+    // the coordinator must be the last node to terminate, as it is the one telling the others
+    let mut nodes: Vec<String> = nodes.into_iter().filter(|n| *n != address).collect();
+    nodes.push(address.clone());
+
+    let broadcast = |control: Control| {
+        for node in nodes.iter() {
+            network_sender
+                .send(Packet::Control {
+                    from: address.clone(),
+                    to: node.clone(),
+                    control: control.clone(),
+                })
+                .unwrap();
+        }
+    };
+
+    let reply = |to: String, control: Control| {
+        network_sender
+            .send(Packet::Control {
+                from: address.clone(),
+                to,
+                control,
+            })
+            .unwrap();
+    };
+
+    // savepoints requested but not taken yet, with where to reply once they are
+    let requests: RefCell<Vec<(Timestamp, PathBuf, String)>> = RefCell::new(Vec::new());
+
+    // waits for a reply of every node, ignoring those that are not expected; savepoint
+    // requests can arrive at any time and are kept aside. Stops as soon as a node asks to be
+    // recovered, since the other nodes may then never reply.
+    let collect = |expected: &dyn Fn(&Control) -> bool| -> Result<Vec<Control>, Interrupted> {
+        let mut replies = HashMap::new();
+        while replies.len() < nodes.len() {
+            match acks.recv().map_err(|_| Interrupted::Stopped)? {
+                (_, Control::RequestSave { at, dir, reply_to }) => {
+                    requests.borrow_mut().push((at, dir, reply_to));
+                }
+                (_, Control::Recover) => return Err(Interrupted::Recover),
+                (from, ack) if expected(&ack) => {
+                    replies.insert(from, ack);
+                }
+                _ => (),
+            }
+        }
+        Ok(replies.into_values().collect())
+    };
+    let window = |gvt: Timestamp| match lookahead {
+        Some(lookahead) => gvt.saturating_add(lookahead),
+        None => Timestamp::MAX,
+    };
+
+    // halts every node and recovers them to the latest recovery line, starting over whenever
+    // another node asks to be recovered meanwhile; returns the latest GVT a node knew of and
+    // the line, or None if the coordinator's node stopped
+    let recover = |epoch: &mut u32| -> Result<Option<(Timestamp, RecoveryLine)>, String> {
+        loop {
+            *epoch += 1;
+            let halt = *epoch;
+            broadcast(Control::Halt { epoch: halt });
+            let replies =
+                match collect(&|ack| matches!(ack, Control::Halted { epoch: e, .. } if *e == halt))
+                {
+                    Ok(replies) => replies,
+                    Err(Interrupted::Recover) => continue,
+                    Err(Interrupted::Stopped) => return Ok(None),
+                };
+            let mut gvt = 0;
+            let mut histories = BTreeMap::new();
+            for reply in replies {
+                if let Control::Halted {
+                    last_epoch,
+                    gvt: g,
+                    checkpoints,
+                    ..
+                } = reply
+                {
+                    // messages of every epoch a node moved to are stale from now on
+                    *epoch = (*epoch).max(last_epoch);
+                    gvt = gvt.max(g);
+                    histories.extend(checkpoints.ok_or("a node cannot read its checkpoints")?);
+                }
+            }
+            let line = recovery_line::latest(&histories, gvt)
+                .ok_or_else(|| format!("no recovery line is earlier than GVT {}", gvt))?;
+
+            *epoch += 1;
+            let resumed = *epoch;
+            broadcast(Control::RecoveryLine {
+                line: line.clone(),
+                gvt,
+                window: window(gvt),
+                epoch: resumed,
+            });
+            let replies = match collect(
+                &|ack| matches!(ack, Control::Recovered { epoch: e, .. } if *e == resumed),
+            ) {
+                Ok(replies) => replies,
+                Err(Interrupted::Recover) => continue,
+                Err(Interrupted::Stopped) => return Ok(None),
+            };
+            let error = replies.into_iter().find_map(|ack| match ack {
+                Control::Recovered { error, .. } => error,
+                _ => None,
+            });
+            return match error {
+                Some(error) => Err(error),
+                None => Ok(Some((gvt, line))),
+            };
+        }
+    };
+
+    let terminate = || {
+        // requests that arrived since the last replies were collected are still queued
+        for ack in acks.try_iter() {
+            if let (_, Control::RequestSave { at, dir, reply_to }) = ack {
+                requests.borrow_mut().push((at, dir, reply_to));
+            }
+        }
+        for (at, _, reply_to) in requests.take() {
+            let error = format!("the simulation ended before GVT reached {}", at);
+            reply(
+                reply_to,
+                Control::Saved {
+                    at,
+                    error: Some(error),
+                },
+            );
+        }
+        broadcast(Control::Terminate);
+    };
+
+    // the latest GVT sent to the nodes, and the latest recovery line
+    let mut committed = 0;
+    let mut line = None;
+    let mut epoch = 0;
+    let mut recovering = false;
+    'rounds: loop {
+        if recovering {
+            match recover(&mut epoch) {
+                Ok(Some((gvt, recovered))) => {
+                    committed = gvt;
+                    line = Some(recovered);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("error: cannot recover the federation: {}", e);
+                    terminate();
+                    return;
+                }
+            }
+            recovering = false;
+        }
+        thread::sleep(GVT_INTERVAL);
+        epoch += 1;
+
+        broadcast(Control::Cut { epoch });
+        let replies =
+            match collect(&|ack| matches!(ack, Control::CutAck { epoch: e, .. } if *e == epoch)) {
+                Ok(replies) => replies,
+                Err(Interrupted::Recover) => {
+                    recovering = true;
+                    continue;
+                }
+                Err(Interrupted::Stopped) => return,
+            };
+        let mut sent = 0;
+        let mut histories = Some(BTreeMap::new());
+        for reply in replies {
+            if let Control::CutAck {
+                sent: s,
+                checkpoints,
+                ..
+            } = reply
+            {
+                sent += s;
+                histories = histories
+                    .zip(checkpoints)
+                    .map(|(mut histories, checkpoints)| {
+                        histories.extend(checkpoints);
+                        histories
+                    });
+            }
+        }
+        // nodes only report checkpoints that are not later than the GVT they committed
+        if let Some(latest) = histories.and_then(|h| recovery_line::latest(&h, committed)) {
+            line = Some(latest);
+        }
+
+        let gvt = loop {
+            broadcast(Control::Poll { epoch });
+            let replies = match collect(
+                &|ack| matches!(ack, Control::PollAck { epoch: e, .. } if *e == epoch),
+            ) {
+                Ok(replies) => replies,
+                Err(Interrupted::Recover) => {
+                    recovering = true;
+                    continue 'rounds;
+                }
+                Err(Interrupted::Stopped) => return,
+            };
+            let mut received = 0;
+            let mut gvt = Timestamp::MAX;
+            for reply in replies {
+                if let Control::PollAck {
+                    received: r,
+                    local_min,
+                    red_min,
+                    ..
+                } = reply
+                {
+                    received += r;
+                    gvt = gvt.min(local_min).min(red_min);
+                }
+            }
+            if received == sent {
+                break gvt;
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        let (reached, waiting): (Vec<_>, Vec<_>) = requests
+            .take()
+            .into_iter()
+            .partition(|(at, _, _)| *at <= gvt);
+        requests.borrow_mut().extend(waiting);
+        let mut reached = VecDeque::from(reached);
+        reached.make_contiguous().sort_by_key(|(at, _, _)| *at);
+        while let Some((at, dir, reply_to)) = reached.pop_front() {
+            let error = if at < committed {
+                Some(format!("GVT already passed {}", at))
+            } else {
+                broadcast(Control::Save {
+                    at,
+                    dir: dir.clone(),
+                });
+                let replies =
+                    match collect(&|ack| matches!(ack, Control::SaveAck { at: a, .. } if *a == at))
+                    {
+                        Ok(replies) => replies,
+                        Err(Interrupted::Recover) => {
+                            // taken once the federation is recovered, if GVT did not pass them then
+                            requests.borrow_mut().push((at, dir, reply_to));
+                            requests.borrow_mut().extend(reached);
+                            recovering = true;
+                            continue 'rounds;
+                        }
+                        Err(Interrupted::Stopped) => return,
+                    };
+                replies.into_iter().find_map(|ack| match ack {
+                    Control::SaveAck { error, .. } => error,
+                    _ => None,
+                })
+            };
+            reply(reply_to, Control::Saved { at, error });
+        }
+
+        broadcast(Control::Gvt {
+            value: gvt,
+            window: window(gvt),
+            line: line.clone(),
+        });
+        committed = gvt;
+        if gvt == Timestamp::MAX || end_ts.is_some_and(|end_ts| gvt > end_ts) {
+            terminate();
+            return;
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::translator::TranslateError;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Receives the messages a Translator could not route
+///
+/// Components run optimistically, so a dead letter may come from an execution that is rolled
+/// back later on; sinks are meant for diagnostics, not for side effects.
+pub trait DeadLetterSink: Send + Sync {
+    fn receive(&self, error: TranslateError);
+}
+
+/// Prints dead letters to stderr
+pub struct LogSink;
+
+impl DeadLetterSink for LogSink {
+    fn receive(&self, error: TranslateError) {
+        eprintln!("dead letter: {}", error);
+    }
+}
+
+/// Silently drops dead letters
+pub struct DiscardSink;
+
+impl DeadLetterSink for DiscardSink {
+    fn receive(&self, _error: TranslateError) {}
+}
+
+/// Appends dead letters to a file, one JSON object per line
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl DeadLetterSink for FileSink {
+    fn receive(&self, error: TranslateError) {
+        let mut line = serde_json::to_string(&error).unwrap();
+        line.push('\n');
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            eprintln!("dead letter: {} (cannot be written: {})", error, e);
+        }
+    }
+}
+
+/// Where a node sends its dead letters, as written in the config file
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterCfg {
+    #[default]
+    Log,
+    Discard,
+    File {
+        path: String,
+    },
+}
+
+impl DeadLetterCfg {
+    pub fn sink(&self) -> io::Result<Arc<dyn DeadLetterSink>> {
+        Ok(match self {
+            DeadLetterCfg::Log => Arc::new(LogSink),
+            DeadLetterCfg::Discard => Arc::new(DiscardSink),
+            DeadLetterCfg::File { path } => Arc::new(FileSink::new(path)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::MsgCore;
+    use std::fs;
+
+    #[test]
+    fn filesink_appends_one_line_per_dead_letter() {
+        let path = std::env::temp_dir().join("dcb_dead_letters_test.jsonl");
+        let _ = fs::remove_file(&path);
+        let cfg: DeadLetterCfg = serde_json::from_value(serde_json::json!({
+            "file": { "path": path.to_str().unwrap() }
+        }))
+        .unwrap();
+
+        let sink = cfg.sink().unwrap();
+        for route in ["a", "b"] {
+            sink.receive(TranslateError::UnknownRoute {
+                from: 1,
+                msg: MsgCore {
+                    payload: String::default(),
+                    route: String::from(route),
+                    exec_ts: 10,
+                },
+            });
+        }
+
+        let lines: Vec<TranslateError> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        match &lines[1] {
+            TranslateError::UnknownRoute { from, msg } => {
+                assert_eq!(*from, 1);
+                assert_eq!(msg.route, "b");
+            }
+            other => panic!("unexpected dead letter: {:?}", other),
+        }
+    }
+}
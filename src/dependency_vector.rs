@@ -1,66 +1,86 @@
-use crate::models::{ComponentId, Timestamp};
+use crate::models::{ComponentId, Dependencies, Timestamp};
+use serde::{Deserialize, Serialize};
 use std::cmp::max;
-use std::collections::HashMap;
 
 /// Dependency Vector Manager
-#[allow(dead_code)]
-#[derive(Debug, Eq, PartialEq, Clone)]
-struct DependencyVector {
-    map: HashMap<ComponentId, Timestamp>,
+///
+/// Tracks which states of other components the state of a component depends on: for every
+/// component, the latest LVT of that component from which a chain of messages led to the
+/// current state. The component's own entry is its LVT.
+///
+/// Messages carry the vector of their sender (see Message::dependencies) and receivers merge
+/// it into their own with update; components that were never heard of are added on the fly.
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct DependencyVector {
+    map: Dependencies,
     id: ComponentId,
 }
 
+#[derive(Debug, Eq, PartialEq)]
+pub enum Failure {
+    /// Occurs when there is an attempt to lower the component's own timestamp
+    TimeViolation,
+
+    /// Occurs when the received vector depends on a state of the local component that is
+    /// later than its current one, i.e. the rollback dependency is inconsistent
+    InconsistentDependency,
+}
+
 impl DependencyVector {
-    #[allow(dead_code)]
     pub fn new(self_id: ComponentId, components: Vec<ComponentId>) -> DependencyVector {
-        let mut map: HashMap<ComponentId, Timestamp> = HashMap::new();
-        map.insert(self_id.clone(), 0);
+        let mut map = Dependencies::new();
+        map.insert(self_id, 0);
         for c in components {
             map.insert(c, 0);
         }
-        DependencyVector {
-            id: self_id,
-            map: map,
-        }
+        DependencyVector { id: self_id, map }
     }
 
-    #[allow(dead_code)]
-    pub fn set_self_ts(&mut self, ts: Timestamp) -> Result<(), ()> {
+    pub fn set_self_ts(&mut self, ts: Timestamp) -> Result<(), Failure> {
         if ts < self.map[&self.id] {
-            return Err(());
+            return Err(Failure::TimeViolation);
         }
-        self.map.insert(self.id.clone(), ts);
-        return Ok(());
+        self.map.insert(self.id, ts);
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn update(&mut self, map: &HashMap<ComponentId, Timestamp>) -> Result<(), ()> {
-        let mut new_vals: HashMap<ComponentId, Timestamp> = HashMap::new();
-
-        // check if rollback dependency is inconsistent
-        if let Some(ts) = map.get(&self.id) {
-            if *ts > self.map[&self.id] {
-                return Err(());
-            }
-        }
+    /// Rebuilds the vector of a component from a copy of its map, e.g. the one saved in a
+    /// checkpoint
+    pub fn from_map(self_id: ComponentId, mut map: Dependencies) -> DependencyVector {
+        map.entry(self_id).or_insert(0);
+        DependencyVector { id: self_id, map }
+    }
 
-        for (id, self_ts) in self.map.iter() {
+    /// Merges a received vector into this one; nothing is merged if it fails (see check)
+    pub fn update(&mut self, map: &Dependencies) -> Result<(), Failure> {
+        self.check(map)?;
+        for (id, other_ts) in map {
             if *id != self.id {
-                if let Some(other_ts) = map.get(&id) {
-                    new_vals.insert(id.clone(), max(*self_ts, *other_ts));
-                }
+                let self_ts = self.map.entry(*id).or_insert(0);
+                *self_ts = max(*self_ts, *other_ts);
             }
         }
+        Ok(())
+    }
 
-        for (id, ts) in new_vals {
-            self.map.insert(id, ts);
+    /// Fails if the received vector depends on a state of the local component that is later
+    /// than its current one, which means the local component was rolled back since and the
+    /// sender's state is an orphan
+    pub fn check(&self, map: &Dependencies) -> Result<(), Failure> {
+        match map.get(&self.id) {
+            Some(ts) if *ts > self.map[&self.id] => Err(Failure::InconsistentDependency),
+            _ => Ok(()),
         }
+    }
 
-        return Ok(());
+    /// Whether merging the received vector would change the dependencies of this component on
+    /// other components, i.e. whether it brings a dependency this one does not know of yet
+    pub fn is_new(&self, map: &Dependencies) -> bool {
+        map.iter()
+            .any(|(id, ts)| *id != self.id && *ts > self.map.get(id).copied().unwrap_or(0))
     }
 
-    #[allow(dead_code)]
-    pub fn get_map(&self) -> &HashMap<ComponentId, Timestamp> {
+    pub fn get_map(&self) -> &Dependencies {
         &self.map
     }
 }
@@ -74,11 +94,11 @@ mod test {
         let self_id = 1;
         let components = vec![1, 2, 3];
         let manager = DependencyVector::new(self_id, components);
-        let mut map: HashMap<ComponentId, Timestamp> = HashMap::new();
+        let mut map = Dependencies::new();
         map.insert(1, 0);
         map.insert(2, 0);
         map.insert(3, 0);
-        assert_eq!(manager, DependencyVector { id: 1, map: map });
+        assert_eq!(manager, DependencyVector { id: 1, map });
     }
 
     #[test]
@@ -86,8 +106,8 @@ mod test {
         let mut manager = DependencyVector::new(1, vec![1, 2]);
         manager.set_self_ts(10).unwrap();
         match manager.set_self_ts(5) {
-            Err(()) => (),
-            Ok(()) => panic!(),
+            Err(Failure::TimeViolation) => (),
+            _ => panic!(),
         }
     }
 
@@ -110,12 +130,12 @@ mod test {
     #[test]
     fn update_returns_err_if_rollback_dependency_is_inconsistent() {
         let mut manager = DependencyVector::new(1, vec![1, 2]);
-        let mut map: HashMap<ComponentId, Timestamp> = HashMap::new();
+        let mut map = Dependencies::new();
         map.insert(1, 10);
         map.insert(2, 0);
         match manager.update(&map) {
-            Err(()) => (),
-            Ok(()) => panic!(),
+            Err(Failure::InconsistentDependency) => (),
+            _ => panic!(),
         }
     }
 
@@ -123,7 +143,7 @@ mod test {
     fn update_changes_values_correctly() {
         let mut manager = DependencyVector::new(1, vec![1, 2, 3]);
         manager.set_self_ts(10).unwrap();
-        let mut map: HashMap<ComponentId, Timestamp> = HashMap::new();
+        let mut map = Dependencies::new();
         map.insert(1, 0);
         map.insert(2, 10);
         map.insert(3, 20);
@@ -146,4 +166,34 @@ mod test {
         assert_eq!(manager.map, map);
         assert_eq!(manager.id, 1);
     }
+
+    #[test]
+    fn update_adds_components_that_were_never_heard_of() {
+        let mut manager = DependencyVector::new(1, vec![]);
+        let mut map = Dependencies::new();
+        map.insert(2, 10);
+        map.insert(3, 20);
+        manager.update(&map).unwrap();
+        map.insert(1, 0);
+        assert_eq!(manager.get_map(), &map);
+    }
+
+    #[test]
+    fn isnew_ignores_known_dependencies_and_the_own_entry() {
+        let mut manager = DependencyVector::new(1, vec![2]);
+        let mut map = Dependencies::new();
+        map.insert(2, 10);
+        manager.update(&map).unwrap();
+        assert!(!manager.is_new(&map));
+
+        map.insert(1, 0);
+        map.insert(3, 0);
+        assert!(!manager.is_new(&map));
+
+        map.insert(2, 11);
+        assert!(manager.is_new(&map));
+        map.insert(2, 10);
+        map.insert(3, 1);
+        assert!(manager.is_new(&map));
+    }
 }
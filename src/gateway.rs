@@ -1,7 +1,29 @@
-use crate::models::Message;
+use crate::models::{CommitAction, Message, Timestamp};
 
 pub trait Gateway<State> {
-    fn init(&self) -> (State, Vec<Message>);
+    fn init(&self) -> (State, Vec<Message>, Vec<CommitAction>);
 
-    fn on_message(&self, state: State, message: Message) -> (State, Vec<Message>);
+    fn on_message(
+        &self,
+        state: State,
+        message: Message,
+    ) -> (State, Vec<Message>, Vec<CommitAction>);
+
+    /// Same as on_message, but for a message handled again while coasting forward after a
+    /// rollback: what it emits was emitted already, so only the new state is returned
+    fn replay(&self, state: State, message: Message) -> State {
+        self.on_message(state, message).0
+    }
+
+    /// Called once GVT passes the action's timestamp; actions are executed in timestamp order
+    fn execute(&self, _action: &CommitAction) {}
+
+    /// Called with the restored state after a rollback to ts
+    fn on_rollback(&self, _state: &State, _ts: Timestamp) {}
+
+    /// Called with the current state whenever GVT advances, after committed actions are executed
+    fn on_commit(&self, _state: &State, _gvt: Timestamp) {}
+
+    /// Called with the final state once the simulation is over
+    fn on_end(&self, _state: &State) {}
 }
@@ -0,0 +1,256 @@
+use crate::models::{ComponentId, Message, Timestamp};
+use crate::recovery_line::RecoveryLine;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Latest Global Virtual Time known by the node
+///
+/// No component will ever be rolled back to a timestamp lower than GVT, so everything that
+/// happened before it (e.g. commit actions) is final.
+///
+/// Gvt also does the node's share of the GVT algorithm: every message is tagged with the epoch
+/// in which it was sent, and the node counts how many messages of each epoch it sent and
+/// received (see models::Control).
+///
+/// If the federation can be recovered, Gvt also holds the latest recovery line (see
+/// recovery_line), which tells components what they can free along with GVT.
+#[derive(Debug)]
+pub struct Gvt {
+    value: AtomicU64,
+    terminated: AtomicBool,
+    accounting: Mutex<Accounting>,
+    line: Mutex<Option<RecoveryLine>>,
+}
+
+#[derive(Debug)]
+struct Accounting {
+    epoch: u32,
+    sent: HashMap<u32, u64>,
+    received: HashMap<u32, u64>,
+
+    // lowest exec_ts among the messages sent in the current epoch
+    red_min: Timestamp,
+
+    // messages of earlier epochs were sent before the latest recovery
+    stale_before: u32,
+}
+
+impl Default for Gvt {
+    fn default() -> Self {
+        Gvt::new()
+    }
+}
+
+impl Gvt {
+    pub fn new() -> Gvt {
+        Gvt {
+            value: AtomicU64::new(0),
+            terminated: AtomicBool::new(false),
+            accounting: Mutex::new(Accounting {
+                epoch: 0,
+                sent: HashMap::new(),
+                received: HashMap::new(),
+                red_min: Timestamp::MAX,
+                stale_before: 0,
+            }),
+            line: Mutex::new(None),
+        }
+    }
+
+    pub fn get(&self) -> Timestamp {
+        self.value.load(Ordering::SeqCst)
+    }
+
+    /// GVT never decreases, so older estimates are ignored
+    pub fn advance(&self, ts: Timestamp) {
+        self.value.fetch_max(ts, Ordering::SeqCst);
+    }
+
+    /// Marks the simulation as finished on this node
+    pub fn terminate(&self) {
+        self.terminated.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.terminated.load(Ordering::SeqCst)
+    }
+
+    /// Must be called right before a message is sent; tags the message with the current epoch
+    pub fn on_send(&self, msg: &mut Message) {
+        let mut accounting = self.accounting.lock().unwrap();
+        msg.epoch = accounting.epoch;
+        *accounting.sent.entry(msg.epoch).or_insert(0) += 1;
+        accounting.red_min = accounting.red_min.min(msg.exec_ts);
+    }
+
+    /// Must be called once a received message has been pushed into its queue
+    ///
+    /// epoch is the epoch the message was tagged with
+    pub fn on_receive(&self, epoch: u32) {
+        let mut accounting = self.accounting.lock().unwrap();
+        *accounting.received.entry(epoch).or_insert(0) += 1;
+    }
+
+    /// Moves the node to a new epoch
+    ///
+    /// Returns how many messages were sent in the previous epoch. Epochs older than the
+    /// current one are ignored, e.g. that of a Cut retried to a node that crashed.
+    pub fn cut(&self, epoch: u32) -> u64 {
+        let mut accounting = self.accounting.lock().unwrap();
+        if epoch < accounting.epoch {
+            return 0;
+        }
+        accounting.epoch = epoch;
+        accounting.red_min = Timestamp::MAX;
+
+        // rounds are sequential, so messages older than the previous epoch were all received
+        let previous = epoch.saturating_sub(1);
+        accounting.sent.retain(|e, _| *e >= previous);
+        accounting.received.retain(|e, _| *e >= previous);
+        accounting.sent.get(&previous).cloned().unwrap_or(0)
+    }
+
+    /// Number of messages sent in the given epoch that were received by the node
+    pub fn received(&self, epoch: u32) -> u64 {
+        let accounting = self.accounting.lock().unwrap();
+        accounting.received.get(&epoch).cloned().unwrap_or(0)
+    }
+
+    /// Lowest exec_ts among the messages sent in the current epoch
+    pub fn red_min(&self) -> Timestamp {
+        self.accounting.lock().unwrap().red_min
+    }
+
+    /// Latest epoch the node moved to
+    pub fn epoch(&self) -> u32 {
+        self.accounting.lock().unwrap().epoch
+    }
+
+    /// Moves the node to the epoch in which the federation resumes after a recovery; messages
+    /// of earlier epochs are stale from then on
+    ///
+    /// Messages of that epoch may already have been received, from nodes that resumed first,
+    /// so they are still counted.
+    pub fn restart(&self, epoch: u32) {
+        let mut accounting = self.accounting.lock().unwrap();
+        accounting.epoch = epoch;
+        accounting.stale_before = epoch;
+        accounting.red_min = Timestamp::MAX;
+        accounting.sent.retain(|e, _| *e >= epoch);
+        accounting.received.retain(|e, _| *e >= epoch);
+    }
+
+    /// Whether a message tagged with epoch was sent before the latest recovery, in which case
+    /// it must be dropped: whatever it carried was sent again if it was still needed
+    pub fn is_stale(&self, epoch: u32) -> bool {
+        epoch < self.accounting.lock().unwrap().stale_before
+    }
+
+    /// Replaces the latest recovery line; Gvt holds none until it is first set, which must be
+    /// done before GVT first advances on nodes that persist their state
+    pub fn set_line(&self, line: RecoveryLine) {
+        *self.line.lock().unwrap() = Some(line);
+    }
+
+    /// Timestamp of the checkpoint of the given component in the latest recovery line, 0 if
+    /// the line does not include it yet; None if the federation cannot be recovered (see
+    /// RollbackManager::fossil_collect)
+    pub fn line(&self, id: ComponentId) -> Option<Timestamp> {
+        let line = self.line.lock().unwrap();
+        line.as_ref()
+            .map(|line| line.get(&id).copied().unwrap_or(0))
+    }
+}
+
+/// Writes a GVT the federation reached to path, so that the node can be recovered from it
+/// after a crash
+pub fn save(path: &Path, gvt: Timestamp) -> io::Result<()> {
+    // the file is renamed once complete, so a crash leaves either GVT intact
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, gvt.to_string())?;
+    fs::rename(tmp, path)
+}
+
+/// Reads the GVT saved to path, 0 if none was saved
+pub fn load(path: &Path) -> io::Result<Timestamp> {
+    match fs::read_to_string(path) {
+        Ok(gvt) => gvt
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Dependencies;
+
+    fn get_message(exec_ts: Timestamp) -> Message {
+        Message {
+            id: 0,
+            payload: String::default(),
+            route: String::default(),
+            exec_ts,
+            sent_ts: 0,
+            is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
+            from: 1,
+            to: 2,
+        }
+    }
+
+    #[test]
+    fn advance_never_decreases_gvt() {
+        let gvt = Gvt::new();
+        gvt.advance(10);
+        gvt.advance(5);
+        assert_eq!(gvt.get(), 10);
+    }
+
+    #[test]
+    fn messages_are_counted_by_epoch() {
+        let gvt = Gvt::new();
+        let mut white = get_message(10);
+        gvt.on_send(&mut white);
+        gvt.on_send(&mut get_message(20));
+
+        assert_eq!(gvt.cut(1), 2);
+        let mut red = get_message(15);
+        gvt.on_send(&mut red);
+        assert_eq!(white.epoch, 0);
+        assert_eq!(red.epoch, 1);
+        assert_eq!(gvt.red_min(), 15);
+
+        assert_eq!(gvt.received(0), 0);
+        gvt.on_receive(white.epoch);
+        gvt.on_receive(red.epoch);
+        assert_eq!(gvt.received(0), 1);
+        assert_eq!(gvt.received(1), 1);
+
+        assert_eq!(gvt.cut(2), 1);
+        assert_eq!(gvt.red_min(), Timestamp::MAX);
+    }
+
+    #[test]
+    fn restart_makes_earlier_epochs_stale() {
+        let gvt = Gvt::new();
+        gvt.cut(3);
+        gvt.on_receive(2);
+        gvt.on_receive(5);
+        gvt.restart(5);
+        assert!(gvt.is_stale(3) && !gvt.is_stale(5));
+        assert_eq!((gvt.received(2), gvt.received(5)), (0, 1));
+
+        // a Cut of the coordinator that was retried since is ignored
+        assert_eq!(gvt.cut(4), 0);
+        assert_eq!(gvt.epoch(), 5);
+    }
+}
@@ -1,51 +1,746 @@
+use crate::component_manager::Runnable;
+use crate::consume_msg_queue::consume_msg_queue;
+use crate::control::NodeControl;
+use crate::coordinator::run_coordinator;
+use crate::gvt::{self, Gvt};
 use crate::messenger::Messenger;
-use crate::models::{ComponentCfg, ComponentId, Message};
+use crate::models::{ComponentId, Control, Packet, Timestamp};
 use crate::network::{run_client, run_server};
-use std::collections::HashMap;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use crate::recovery_line::RecoveryLine;
+use crate::scheduler::Scheduler;
+use crate::snapshot::Recorder;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
 use std::thread;
 
-#[allow(dead_code)]
+/// Where a node persists its state, and whether it recovers from it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Persistence {
+    /// The node's directory, where it persists every GVT; its components persist their
+    /// checkpoints and messages on their own (see registry::Registry::build_persistent)
+    pub dir: PathBuf,
+
+    /// Whether the node restarts from its persisted state instead of starting over
+    pub recover: bool,
+}
+
+/// Runs a node until the simulation is over
+///
+/// Local components are multiplexed over a pool of `workers` threads (see Scheduler).
+///
+/// The node with the lowest address coordinates GVT rounds and termination; the simulation
+/// ends once GVT passes end_ts, or once there are no messages left if end_ts is None.
+///
+/// Nodes synchronize conservatively if lookahead is set (see config::SyncMode); it must then
+/// be the same on every node.
+///
+/// If persistence is set, the node persists every GVT so that it can be recovered after a
+/// crash. A crashed node is recovered by restarting it alone with recover set and components
+/// reopened from their persisted state: it asks the coordinator to be recovered, then every
+/// node restores its components to the latest recovery line (see the recovery_line module and
+/// Runnable::recover) and the simulation resumes. Recovery assumes components are
+/// deterministic. Only the commit actions executed after the last `Executed` entry was
+/// written to the component's message log (see RollbackManager::mark_executed) may run again
+/// once the simulation resumes.
+///
+/// A consistent snapshot of the running federation can be taken at any time for offline
+/// inspection (see the snapshot module).
+///
+/// Fails if the node cannot listen on addr or cannot recover, or if one of its components
+/// fails, in which case the node stops without waiting for the simulation to be over.
 pub fn init(
     addr: String,
     remote_addrs: HashMap<ComponentId, String>,
-    local_components: Vec<ComponentCfg>,
-) {
-    let (net_sender, net_receiver) = channel::<Message>();
+    local_components: Vec<Box<dyn Runnable>>,
+    end_ts: Option<Timestamp>,
+    workers: usize,
+    lookahead: Option<Timestamp>,
+    persistence: Option<Persistence>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(&addr)?;
+    let (net_sender, net_receiver) = channel::<Packet>();
+    let gvt = Arc::new(Gvt::new());
+    // GVT starts at 0
+    let window = lookahead.unwrap_or(Timestamp::MAX);
+    let scheduler = Scheduler::new(local_components, window);
+    let scheduler = Arc::new(match persistence {
+        Some(_) => scheduler.with_history(),
+        None => scheduler,
+    });
+
+    let messenger = Messenger {
+        scheduler: scheduler.clone(),
+        network_sender: net_sender.clone(),
+        gvt: gvt.clone(),
+    };
+
+    let recovering = persistence.as_ref().is_some_and(|p| p.recover);
+    let gvt_path = match &persistence {
+        Some(persistence) => {
+            fs::create_dir_all(&persistence.dir)?;
+            let path = persistence.dir.join("gvt");
+            if persistence.recover {
+                gvt.advance(gvt::load(&path)?);
+            } else {
+                gvt::save(&path, 0)?;
+            }
+            // nothing can be freed until the first recovery line is known
+            gvt.set_line(RecoveryLine::new());
+            Some(path)
+        }
+        None => None,
+    };
+
+    // components are started before the node takes part in any GVT round, otherwise their
+    // initial messages could be missed; recovering components are halted until they are
+    // restored to the recovery line instead (see Control::RecoveryLine)
+    if recovering {
+        scheduler.halt();
+    } else {
+        scheduler.start(&messenger);
+    }
+
+    let nodes: BTreeSet<String> = remote_addrs
+        .values()
+        .cloned()
+        .chain(Some(addr.clone()))
+        .collect();
+    let coordinator = nodes.iter().next().unwrap().clone();
+    let is_coordinator = coordinator == addr;
+
+    let (ack_sender, ack_receiver) = channel();
+    let control = NodeControl {
+        address: addr.clone(),
+        gvt: gvt.clone(),
+        scheduler: scheduler.clone(),
+        network_sender: net_sender.clone(),
+        coordinator: if is_coordinator {
+            Some(ack_sender)
+        } else {
+            None
+        },
+        gvt_path: gvt_path.clone(),
+        snapshots: Arc::new(Recorder::new(addr.clone(), remote_addrs.clone())),
+    };
+
+    let mut handles = Vec::new();
+    if is_coordinator {
+        let addr = addr.clone();
+        let net_sender = net_sender.clone();
+        handles.push(thread::spawn(move || {
+            run_coordinator(
+                addr,
+                nodes.into_iter().collect(),
+                end_ts,
+                lookahead,
+                net_sender,
+                ack_receiver,
+            )
+        }));
+    }
+    drop(net_sender);
+
+    let messenger_clone = messenger.clone();
+    handles.push(thread::spawn(move || {
+        run_server(listener, messenger_clone, control)
+    }));
+    let gvt_clone = gvt.clone();
+    handles.push(thread::spawn(move || {
+        run_client(&remote_addrs, net_receiver, gvt_clone)
+    }));
+
+    if recovering {
+        messenger
+            .network_sender
+            .send(Packet::Control {
+                from: addr.clone(),
+                to: coordinator,
+                control: Control::Recover,
+            })
+            .unwrap();
+    }
+
+    let mut workers_handles = Vec::new();
+    for _ in 0..workers.max(1) {
+        let scheduler = scheduler.clone();
+        let messenger = messenger.clone();
+        let gvt = gvt.clone();
+        let addr = addr.clone();
+        workers_handles.push(thread::spawn(move || {
+            consume_msg_queue(&scheduler, &messenger, &gvt, end_ts, &addr)
+        }));
+    }
+
+    drop(messenger);
+    let mut failed = Ok(());
+    for handle in workers_handles {
+        failed = failed.and(handle.join().unwrap());
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    // the node was shut down, its components may be left in any state
+    failed?;
+
+    // every worker is done, so the components can be finished here
+    let scheduler = Arc::try_unwrap(scheduler).ok().unwrap();
+    for mut component in scheduler.into_components() {
+        component.commit(gvt.get(), gvt.line(component.id()))?;
+        component.end();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::component::Component;
+    use crate::component_manager::ComponentManager;
+    use crate::config::{ComponentCfg, PersistenceCfg};
+    use crate::context::Context;
+    use crate::dead_letter::LogSink;
+    use crate::message_log::SyncPolicy;
+    use crate::models::{Message, MsgCore};
+    use crate::registry::Registry;
+    use crate::translator::{Destination, Translator};
+    use crate::{savepoint, snapshot};
+    use serde::{Deserialize, Serialize};
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    static HANDLED: Mutex<Vec<(ComponentId, u32)>> = Mutex::new(Vec::new());
+    static ROLLED_BACK: Mutex<Vec<ComponentId>> = Mutex::new(Vec::new());
+
+    // the player that hangs forever once it handled that many balls, as if its node crashed
+    static HANG: Mutex<Option<(ComponentId, u32)>> = Mutex::new(None);
+
+    // players that take a millisecond to handle every ball
+    static SLOW: Mutex<Vec<ComponentId>> = Mutex::new(Vec::new());
+
+    /// Bounces a ball back to its peer, one time unit later
+    #[derive(Clone, Serialize, Deserialize)]
+    struct Player {
+        id: ComponentId,
+        handled: u32,
+    }
+
+    impl Component for Player {
+        type Params = bool;
+
+        fn init(serves: bool, ctx: &mut Context) -> Self {
+            if serves {
+                ctx.send("out", "ball", 1);
+            }
+            Player {
+                id: ctx.id(),
+                handled: 0,
+            }
+        }
+
+        fn on_message(self, _msg: &MsgCore, ctx: &mut Context) -> Self {
+            let hang = HANG
+                .lock()
+                .unwrap()
+                .take_if(|h| *h == (self.id, self.handled));
+            if hang.is_some() {
+                loop {
+                    thread::park();
+                }
+            }
+            if SLOW.lock().unwrap().contains(&self.id) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            ctx.send("out", "ball", 1);
+            Player {
+                id: self.id,
+                handled: self.handled + 1,
+            }
+        }
 
-    let local_components: Vec<(ComponentCfg, Sender<Message>, Receiver<Message>)> =
-        local_components
+        fn on_rollback(&self, _ts: Timestamp) {
+            ROLLED_BACK.lock().unwrap().push(self.id);
+        }
+
+        fn on_end(&self) {
+            HANDLED.lock().unwrap().push((self.id, self.handled));
+        }
+    }
+
+    fn get_player(
+        id: ComponentId,
+        peer: ComponentId,
+        serves: bool,
+        delay: Timestamp,
+    ) -> Box<dyn Runnable> {
+        let dest = Destination {
+            to: peer,
+            route: String::from("in"),
+            delay,
+            transform: Vec::new(),
+        };
+        let mut route_to_dest = HashMap::new();
+        route_to_dest.insert(String::from("out"), vec![dest]);
+        let translator = Translator::new(
+            id,
+            route_to_dest,
+            serde_json::json!(serves),
+            Arc::new(LogSink),
+        )
+        .unwrap();
+
+        Box::new(
+            ComponentManager::<Player>::new(id, Box::new(translator), |_, _| true).serializable(),
+        )
+    }
+
+    // nodes listen on ports below the ephemeral range (32768 and up on Linux), otherwise an
+    // outgoing connection may take a node's port before the node listens on it
+    fn run(f: impl FnOnce() + Send + 'static) {
+        let (done_sender, done_receiver) = channel();
+        thread::spawn(move || {
+            f();
+            done_sender.send(()).unwrap();
+        });
+        done_receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn init_returns_once_there_is_nothing_left_to_handle() {
+        run(|| {
+            init(
+                String::from("127.0.0.1:28401"),
+                HashMap::new(),
+                vec![],
+                None,
+                1,
+                None,
+                None,
+            )
+            .unwrap()
+        });
+    }
+
+    #[test]
+    fn init_runs_local_components_until_end_ts() {
+        run(|| {
+            init(
+                String::from("127.0.0.1:28402"),
+                HashMap::new(),
+                vec![get_player(1, 2, true, 0), get_player(2, 1, false, 0)],
+                Some(100),
+                2,
+                None,
+                None,
+            )
+            .unwrap()
+        });
+        let handled = HANDLED.lock().unwrap().clone();
+        assert!(handled.contains(&(1, 50)));
+        assert!(handled.contains(&(2, 50)));
+    }
+
+    #[test]
+    fn init_runs_components_spread_over_several_nodes() {
+        let a = String::from("127.0.0.1:28403");
+        let b = String::from("127.0.0.1:28404");
+        let mut remote_a = HashMap::new();
+        remote_a.insert(4, b.clone());
+        let mut remote_b = HashMap::new();
+        remote_b.insert(3, a.clone());
+
+        let node_b = thread::spawn(move || {
+            init(
+                b,
+                remote_b,
+                vec![get_player(4, 3, false, 0)],
+                Some(60),
+                1,
+                None,
+                None,
+            )
+            .unwrap()
+        });
+        run(move || {
+            init(
+                a,
+                remote_a,
+                vec![get_player(3, 4, true, 0)],
+                Some(60),
+                1,
+                None,
+                None,
+            )
+            .unwrap()
+        });
+        node_b.join().unwrap();
+
+        let handled = HANDLED.lock().unwrap().clone();
+        assert!(handled.contains(&(3, 30)));
+        assert!(handled.contains(&(4, 30)));
+    }
+
+    #[test]
+    fn init_skips_malformed_packets() {
+        let address = String::from("127.0.0.1:28414");
+        SLOW.lock().unwrap().extend([13, 14]);
+        let node = thread::spawn({
+            let address = address.clone();
+            move || {
+                init(
+                    address,
+                    HashMap::new(),
+                    vec![get_player(13, 14, true, 0), get_player(14, 13, false, 0)],
+                    Some(200),
+                    2,
+                    None,
+                    None,
+                )
+            }
+        });
+        let started = Instant::now();
+        loop {
+            match std::net::TcpStream::connect(&address) {
+                Ok(mut stream) => {
+                    std::io::Write::write_all(&mut stream, b"{\"Message\": {\"id\"").unwrap();
+                    break;
+                }
+                Err(_) => {
+                    assert!(started.elapsed() < Duration::from_secs(10));
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+        node.join().unwrap().unwrap();
+
+        let handled = HANDLED.lock().unwrap().clone();
+        assert!(handled.contains(&(13, 100)));
+        assert!(handled.contains(&(14, 100)));
+    }
+
+    #[test]
+    fn init_runs_conservatively_within_the_lookahead_window() {
+        run(|| {
+            init(
+                String::from("127.0.0.1:28405"),
+                HashMap::new(),
+                vec![get_player(5, 6, true, 9), get_player(6, 5, false, 9)],
+                Some(100),
+                2,
+                Some(9),
+                None,
+            )
+            .unwrap()
+        });
+        let handled = HANDLED.lock().unwrap().clone();
+        assert!(handled.contains(&(5, 5)));
+        assert!(handled.contains(&(6, 5)));
+        let rolled_back = ROLLED_BACK.lock().unwrap().clone();
+        assert!(!rolled_back.contains(&5) && !rolled_back.contains(&6));
+    }
+
+    // a component whose history cannot be written
+    struct Broken(ComponentId);
+
+    impl Runnable for Broken {
+        fn id(&self) -> ComponentId {
+            self.0
+        }
+        fn start(&mut self, messenger: &Messenger) {
+            let msg = Message {
+                id: 0,
+                is_anti: false,
+                epoch: 0,
+                dependencies: Default::default(),
+                from: self.0,
+                to: self.0,
+                sent_ts: 0,
+                exec_ts: 10,
+                route: String::from("in"),
+                payload: String::default(),
+            };
+            messenger.send(msg).unwrap();
+        }
+        fn handle(&mut self, _msg: Message, _messenger: &Messenger) -> io::Result<()> {
+            Err(io::Error::other("disk full"))
+        }
+        fn commit(&mut self, _gvt: Timestamp, _line: Option<Timestamp>) -> io::Result<()> {
+            Ok(())
+        }
+        fn end(&mut self) {}
+    }
+
+    #[test]
+    fn init_stops_the_node_once_a_component_fails() {
+        run(|| {
+            let result = init(
+                String::from("127.0.0.1:28416"),
+                HashMap::new(),
+                vec![Box::new(Broken(15)), get_player(16, 16, false, 0)],
+                None,
+                2,
+                None,
+                None,
+            );
+            assert_eq!(result.unwrap_err().to_string(), "disk full");
+        });
+    }
+
+    #[test]
+    fn init_recovers_a_crashed_node_from_its_persisted_state() {
+        let mut registry = Registry::new();
+        registry.register_persistent::<Player>("player");
+        let get_cfg = |id: ComponentId, peer: ComponentId, serves: bool| ComponentCfg {
+            id,
+            type_name: String::from("player"),
+            node: String::from("A"),
+            params: serde_json::json!(serves),
+            routes: vec![(
+                String::from("out"),
+                vec![Destination {
+                    to: peer,
+                    route: String::from("in"),
+                    delay: 0,
+                    transform: Vec::new(),
+                }],
+            )]
             .into_iter()
-            .map(|c| {
-                let (s, r) = channel();
-                (c, s, r)
+            .collect(),
+        };
+        let dir = std::env::temp_dir().join("dcb_init_recover_test");
+        let _ = fs::remove_dir_all(&dir);
+        let build = |cfg: &ComponentCfg, dir: &Path, recover: bool| -> Vec<Box<dyn Runnable>> {
+            let persistence = PersistenceCfg {
+                dir: dir.to_path_buf(),
+                in_memory: 2,
+                sync: SyncPolicy::Flush,
+            };
+            vec![registry
+                .build_persistent(cfg, Arc::new(LogSink), &persistence, recover)
+                .unwrap()]
+        };
+        let start = |addr: &str, peer: (ComponentId, &str), components, dir: PathBuf, recover| {
+            let (addr, remote) = (String::from(addr), HashMap::from([(peer.0, peer.1.into())]));
+            thread::spawn(move || {
+                init(
+                    addr,
+                    remote,
+                    components,
+                    Some(100),
+                    1,
+                    None,
+                    Some(Persistence { dir, recover }),
+                )
+                .unwrap()
             })
+        };
+        let (a, b) = ("127.0.0.1:28406", "127.0.0.1:28407");
+        let (cfg_a, cfg_b) = (get_cfg(7, 8, true), get_cfg(8, 7, false));
+        let (dir_a, dir_b) = (dir.join("a"), dir.join("b"));
+
+        // 8 hangs on the ball at 19, so GVT gets stuck there; its node is then crashed
+        *HANG.lock().unwrap() = Some((8, 9));
+        let node_a = start(
+            a,
+            (8, b),
+            build(&cfg_a, &dir_a, false),
+            dir_a.clone(),
+            false,
+        );
+        start(
+            b,
+            (7, a),
+            build(&cfg_b, &dir_b, false),
+            dir_b.clone(),
+            false,
+        );
+        // once 7 freed its initial checkpoint, it can no longer start over
+        let initial = dir_a.join("7/checkpoints/0.checkpoint");
+        let started = Instant::now();
+        while gvt::load(&dir_b.join("gvt")).unwrap() != 19 || initial.exists() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        let terminate = Packet::Control {
+            from: String::from(a),
+            to: String::from(b),
+            control: Control::Terminate,
+        };
+        let mut stream = std::net::TcpStream::connect(b).unwrap();
+        std::io::Write::write_all(&mut stream, &serde_json::to_vec(&terminate).unwrap()).unwrap();
+        drop(stream);
+        while std::net::TcpStream::connect(b).is_ok() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // only the crashed node restarts; the other one rolls back to the recovery line
+        let node_b = start(b, (7, a), build(&cfg_b, &dir_b, true), dir_b, true);
+        run(move || {
+            node_a.join().unwrap();
+            node_b.join().unwrap();
+        });
+        let handled = HANDLED.lock().unwrap().clone();
+        assert!(handled.contains(&(7, 50)));
+        assert!(handled.contains(&(8, 50)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn init_takes_consistent_snapshots_of_the_running_federation() {
+        let a = String::from("127.0.0.1:28408");
+        let b = String::from("127.0.0.1:28409");
+        let mut remote_a = HashMap::new();
+        remote_a.insert(10, b.clone());
+        let mut remote_b = HashMap::new();
+        remote_b.insert(9, a.clone());
+        SLOW.lock().unwrap().extend([9, 10]);
+        let dir = std::env::temp_dir().join("dcb_init_snapshot_test");
+        let _ = fs::remove_dir_all(&dir);
+
+        let node_b = thread::spawn(move || {
+            init(
+                b,
+                remote_b,
+                vec![get_player(10, 9, false, 1)],
+                Some(300),
+                1,
+                None,
+                None,
+            )
+            .unwrap()
+        });
+        let trigger_a = a.clone();
+        let node_a = thread::spawn(move || {
+            init(
+                a,
+                remote_a,
+                vec![get_player(9, 10, true, 1)],
+                Some(300),
+                1,
+                None,
+                None,
+            )
+            .unwrap()
+        });
+        let started = Instant::now();
+        while snapshot::trigger(&trigger_a, &dir).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        node_a.join().unwrap();
+        node_b.join().unwrap();
+
+        // exactly one ball is on its way, and it is the one that follows every ball handled;
+        // every hop takes 2, the delay the player asks for plus that of the route
+        let snapshots = snapshot::load(&dir).unwrap();
+        assert_eq!(
+            snapshots.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![9, 10]
+        );
+        let handled: u64 = snapshots
+            .iter()
+            .map(|s| s.state["handled"].as_u64().unwrap())
+            .sum();
+        let balls: Vec<&Message> = snapshots
+            .iter()
+            .flat_map(|s| s.queued.iter().chain(&s.in_transit))
             .collect();
+        assert_eq!(balls.len(), 1);
+        assert_eq!(balls[0].exec_ts, 2 * (handled + 1));
+        assert!(handled < 150);
+        fs::remove_dir_all(dir).unwrap();
+    }
 
-    let mut local_senders = HashMap::new();
-    local_components.iter().for_each(|tuple| {
-        local_senders.insert(tuple.0.id, tuple.1.clone());
-    });
+    #[test]
+    fn init_boots_a_federation_from_a_savepoint() {
+        let mut registry = Registry::new();
+        registry.register_persistent::<Player>("player");
+        let get_cfg = |id: ComponentId, peer: ComponentId, serves: bool| ComponentCfg {
+            id,
+            type_name: String::from("player"),
+            node: String::from("A"),
+            params: serde_json::json!(serves),
+            routes: vec![(
+                String::from("out"),
+                vec![Destination {
+                    to: peer,
+                    route: String::from("in"),
+                    delay: 0,
+                    transform: Vec::new(),
+                }],
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let cfgs = [get_cfg(11, 12, true), get_cfg(12, 11, false)];
+        SLOW.lock().unwrap().extend([11, 12]);
+        let dir = std::env::temp_dir().join("dcb_init_savepoint_test");
+        let _ = fs::remove_dir_all(&dir);
 
-    let messenger = Messenger {
-        local_senders: local_senders.clone(),
-        network_sender: net_sender,
-    };
+        let address = String::from("127.0.0.1:28412");
+        let components = cfgs
+            .iter()
+            .map(|cfg| registry.build(cfg, Arc::new(LogSink)).unwrap())
+            .collect();
+        let node = thread::spawn({
+            let address = address.clone();
+            move || {
+                init(
+                    address,
+                    HashMap::new(),
+                    components,
+                    Some(300),
+                    2,
+                    None,
+                    None,
+                )
+                .unwrap()
+            }
+        });
+        let started = Instant::now();
+        while let Err(e) = savepoint::request(&address, 150, &dir) {
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        node.join().unwrap();
+        for cfg in &cfgs {
+            let saved = savepoint::load(&dir, cfg.id).unwrap();
+            assert_eq!(saved.at, 150);
+            assert!(saved.checkpoint.timestamp <= 150);
+        }
 
-    let messenger_clone = messenger.clone();
-    let server_handle = thread::spawn(move || run_server(addr, messenger_clone));
-    let client_handle = thread::spawn(move || run_client(&remote_addrs, net_receiver));
-
-    // let mut handles = Vec::new();
-    // for tuple in local_components {
-    //     let messenger_clone = messenger.clone();
-    // let handle = thread::spawn(move || run_comp_manager(tuple.0, messenger_clone, tuple.2));
-    // handles.push(handle);
-    // }
-
-    // for handle in handles {
-    // handle.join().unwrap();
-    // }
-    server_handle.join().unwrap();
-    client_handle.join().unwrap();
+        // the branched run ends exactly like the original one
+        let components = cfgs
+            .iter()
+            .map(|cfg| {
+                registry
+                    .build_restored(cfg, Arc::new(LogSink), &dir)
+                    .unwrap()
+            })
+            .collect();
+        run(move || {
+            init(
+                String::from("127.0.0.1:28413"),
+                HashMap::new(),
+                components,
+                Some(300),
+                2,
+                None,
+                None,
+            )
+            .unwrap()
+        });
+        let handled = HANDLED.lock().unwrap().clone();
+        for id in [11, 12] {
+            assert_eq!(handled.iter().filter(|h| **h == (id, 150)).count(), 2);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,65 @@
+//! DCB runs distributed co-simulations with optimistic (Time Warp) synchronization.
+//!
+//! Components implement the Component trait and are registered by name in a Registry; a
+//! FederationCfg describes which node runs each component and how their messages are routed.
+//! Every node of the federation is then started with init, or through the cli module.
+//!
+//! The dcb binary runs federations of the built-in component types (see the builtin module).
+//! An application with its own types ships its own binary instead:
+//!
+//! ```no_run
+//! // in the application's main
+//! let mut registry = dcb::Registry::new();
+//! dcb::builtin::register(&mut registry);
+//! // registry.register::<Vehicle>("vehicle");
+//! let args: Vec<String> = std::env::args().skip(1).collect();
+//! std::process::exit(dcb::cli::execute(&args, &registry));
+//! ```
+
+pub mod builtin;
+pub mod checkpoint_store;
+pub mod cli;
+pub mod component;
+pub mod component_manager;
+pub mod config;
+pub mod context;
+pub mod dead_letter;
+pub mod dependency_vector;
+pub mod gateway;
+pub mod init;
+pub mod message_log;
+pub mod messenger;
+pub mod models;
+pub mod recovery_line;
+pub mod registry;
+pub mod rollback_manager;
+pub mod savepoint;
+pub mod snapshot;
+pub mod transform;
+pub mod translator;
+
+mod consume_msg_queue;
+mod control;
+mod coordinator;
+mod gvt;
+mod msg_queue;
+mod network;
+mod route_pattern;
+mod scheduler;
+
+pub use checkpoint_store::{BoxedStore, CheckpointStore, FileStore};
+pub use component::Component;
+pub use component_manager::{ComponentManager, Runnable};
+pub use config::{ComponentCfg, ConfigError, FederationCfg, NodeSetup, PersistenceCfg};
+pub use context::Context;
+pub use dead_letter::DeadLetterSink;
+pub use dependency_vector::DependencyVector;
+pub use gateway::Gateway;
+pub use init::{init, Persistence};
+pub use message_log::{MessageLog, SyncPolicy};
+pub use messenger::Messenger;
+pub use models::{CommitAction, ComponentId, Dependencies, Message, MsgCore, Timestamp};
+pub use registry::{Registry, RegistryError};
+pub use rollback_manager::RollbackManager;
+pub use transform::Transform;
+pub use translator::{TranslateError, Translator};
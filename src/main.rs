@@ -1,13 +1,12 @@
-mod component;
-mod consume_msg_queue;
-mod dependency_vector;
-mod gateway;
-mod init;
-mod messenger;
-mod models;
-mod msg_queue;
-mod network;
-mod rollback_manager;
-mod translator;
+use dcb::{builtin, cli, Registry};
+use std::env;
+use std::process;
 
-fn main() {}
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // applications with their own component types call cli::execute from their own main
+    let mut registry = Registry::new();
+    builtin::register(&mut registry);
+    process::exit(cli::execute(&args, &registry));
+}
@@ -0,0 +1,456 @@
+use crate::models::{CommitAction, Message, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, LinkedList, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// When the entries of a MessageLog are forced to disk
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncPolicy {
+    /// Every entry is handed to the OS right away, so it survives a crash of the node but
+    /// not of the machine
+    #[default]
+    Flush,
+
+    /// Every entry is synced to disk before the message is handled any further
+    Always,
+
+    /// Every entry is handed to the OS right away and synced to disk every n entries
+    Every(usize),
+}
+
+/// A line of a MessageLog
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogEntry {
+    Received(Message),
+    Sent(Message),
+
+    /// The component was rolled back to the given timestamp: what it received for, or sent at,
+    /// that timestamp or later was undone
+    Rollback(Timestamp),
+
+    /// What the component received for, or sent at, ts or earlier was freed, except the
+    /// messages it sent for keep_from or later
+    Free {
+        ts: Timestamp,
+        keep_from: Timestamp,
+    },
+
+    /// The component emitted the given commit action
+    Action(CommitAction),
+
+    /// The commit actions earlier than the given GVT were executed
+    Executed(Timestamp),
+}
+
+impl LogEntry {
+    // identifies the message of the entry, if any; collisions only delay truncation
+    fn key(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            LogEntry::Received(msg) => (true, msg).hash(&mut hasher),
+            LogEntry::Sent(msg) => (false, msg).hash(&mut hasher),
+            LogEntry::Action(action) => action.hash(&mut hasher),
+            LogEntry::Rollback(_) | LogEntry::Free { .. } | LogEntry::Executed(_) => return None,
+        }
+        Some(hasher.finish())
+    }
+}
+
+/// Write-ahead log of the messages a component received and sent, and of the commit actions it
+/// emitted, one JSON entry per line
+///
+/// Along with the component's checkpoints, it is enough to rebuild the component's history
+/// after a crash (see history). Freeing older messages only appends an entry, and the entries
+/// that precede every remaining message and action are dropped once they take more room than
+/// the rest of the log, so that it does not grow forever.
+///
+/// Clones write to the same file.
+#[derive(Debug, Clone)]
+pub struct MessageLog {
+    path: PathBuf,
+    sync: SyncPolicy,
+    writer: Arc<Mutex<Writer>>,
+}
+
+#[derive(Debug)]
+struct Writer {
+    file: BufWriter<File>,
+
+    // entries written since the last sync
+    unsynced: usize,
+
+    // size in bytes and key (see LogEntry::key) of every entry of the file, in order
+    entries: VecDeque<(u64, Option<u64>)>,
+
+    // latest Executed entry of the file, written again whenever the entries before it are
+    // dropped
+    executed: Timestamp,
+}
+
+/// What a component's history was when its log was last written (see MessageLog::history)
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LoggedHistory {
+    pub received: LinkedList<Message>,
+    pub sent: LinkedList<Message>,
+
+    /// actions that were not executed yet
+    pub actions: LinkedList<CommitAction>,
+
+    /// the actions earlier than it were executed
+    pub executed: Timestamp,
+}
+
+/// Two logs are equal if they write to the same file in the same way
+impl PartialEq for MessageLog {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.sync == other.sync
+    }
+}
+
+impl Eq for MessageLog {}
+
+impl MessageLog {
+    /// Opens the log at path, creating it if needed; new entries are appended to the file
+    pub fn open(path: impl AsRef<Path>, sync: SyncPolicy) -> io::Result<MessageLog> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut entries = VecDeque::new();
+        let mut executed = 0;
+        for line in BufReader::new(File::open(&path)?).split(b'\n') {
+            let line = line?;
+            let entry: Option<LogEntry> = serde_json::from_slice(&line).ok();
+            if let Some(LogEntry::Executed(gvt)) = entry {
+                executed = gvt;
+            }
+            entries.push_back((line.len() as u64 + 1, entry.and_then(|e| e.key())));
+        }
+        Ok(MessageLog {
+            path,
+            sync,
+            writer: Arc::new(Mutex::new(Writer {
+                file: BufWriter::new(file),
+                unsynced: 0,
+                entries,
+                executed,
+            })),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, entry: &LogEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap();
+        writer.file.write_all(&line)?;
+        writer.file.flush()?;
+        writer.unsynced += 1;
+        writer.entries.push_back((line.len() as u64, entry.key()));
+        if let LogEntry::Executed(gvt) = entry {
+            writer.executed = *gvt;
+        }
+        let sync = match self.sync {
+            SyncPolicy::Flush => false,
+            SyncPolicy::Always => true,
+            SyncPolicy::Every(n) => writer.unsynced >= n,
+        };
+        if sync {
+            writer.file.get_ref().sync_data()?;
+            writer.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// Replaces the content of the log with the given history, e.g. after messages other than
+    /// the oldest ones were removed from it
+    pub fn rewrite<'a>(
+        &self,
+        received: impl IntoIterator<Item = &'a Message>,
+        sent: impl IntoIterator<Item = &'a Message>,
+        actions: impl IntoIterator<Item = &'a CommitAction>,
+        executed: Timestamp,
+    ) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+
+        // the new log is renamed once complete, so a crash leaves either log intact
+        let tmp = self.path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        let mut entries = VecDeque::new();
+        let history = Some(LogEntry::Executed(executed))
+            .into_iter()
+            .chain(received.into_iter().map(|m| LogEntry::Received(m.clone())))
+            .chain(sent.into_iter().map(|m| LogEntry::Sent(m.clone())))
+            .chain(actions.into_iter().map(|a| LogEntry::Action(a.clone())));
+        for entry in history {
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+            entries.push_back((line.len() as u64, entry.key()));
+        }
+        file.flush()?;
+        file.get_ref().sync_data()?;
+        fs::rename(&tmp, &self.path)?;
+
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        *writer = Writer {
+            file: BufWriter::new(file),
+            unsynced: 0,
+            entries,
+            executed,
+        };
+        Ok(())
+    }
+
+    /// Records that older messages were freed (see LogEntry::Free); received, sent and actions
+    /// are the remaining history
+    ///
+    /// The entries that precede every remaining message and action are no longer needed. Since
+    /// a file cannot shrink from the front, they are only dropped, by copying the entries that
+    /// follow them as they are, once they take more room than those; the latest Executed entry
+    /// is written first, so that what was executed is never forgotten.
+    pub fn free<'a>(
+        &self,
+        ts: Timestamp,
+        keep_from: Timestamp,
+        received: impl IntoIterator<Item = &'a Message>,
+        sent: impl IntoIterator<Item = &'a Message>,
+        actions: impl IntoIterator<Item = &'a CommitAction>,
+    ) -> io::Result<()> {
+        self.append(&LogEntry::Free { ts, keep_from })?;
+
+        let remaining: HashSet<u64> = received
+            .into_iter()
+            .filter_map(|m| LogEntry::Received(m.clone()).key())
+            .chain(
+                sent.into_iter()
+                    .filter_map(|m| LogEntry::Sent(m.clone()).key()),
+            )
+            .chain(
+                actions
+                    .into_iter()
+                    .filter_map(|a| LogEntry::Action(a.clone()).key()),
+            )
+            .collect();
+        let mut writer = self.writer.lock().unwrap();
+        let freed = writer
+            .entries
+            .iter()
+            .take_while(|(_, key)| !key.is_some_and(|k| remaining.contains(&k)))
+            .count();
+        let freed_size: u64 = writer
+            .entries
+            .iter()
+            .take(freed)
+            .map(|(size, _)| size)
+            .sum();
+        let size: u64 = writer.entries.iter().map(|(size, _)| size).sum();
+        if freed_size <= size - freed_size {
+            return Ok(());
+        }
+
+        // the new log is renamed once complete, so a crash leaves either log intact
+        let tmp = self.path.with_extension("tmp");
+        let mut old = File::open(&self.path)?;
+        old.seek(SeekFrom::Start(freed_size))?;
+        let mut file = File::create(&tmp)?;
+        let mut executed = serde_json::to_vec(&LogEntry::Executed(writer.executed))?;
+        executed.push(b'\n');
+        file.write_all(&executed)?;
+        io::copy(&mut old, &mut file)?;
+        file.sync_data()?;
+        fs::rename(&tmp, &self.path)?;
+
+        writer.file = BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        writer.unsynced = 0;
+        writer.entries.drain(..freed);
+        writer.entries.push_front((executed.len() as u64, None));
+        Ok(())
+    }
+
+    /// Reads every entry of the log at path
+    ///
+    /// A last line that is not a valid entry was being written when the node crashed, so it
+    /// is ignored.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<LogEntry>> {
+        let lines = BufReader::new(File::open(path)?)
+            .lines()
+            .collect::<io::Result<Vec<String>>>()?;
+        let mut entries = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) if i + 1 == lines.len() => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Rebuilds the history of a component from the log at path: its received and sent
+    /// messages, in the order they were saved, and the actions it did not execute, with what
+    /// was rolled back removed
+    pub fn history(path: impl AsRef<Path>) -> io::Result<LoggedHistory> {
+        let mut history = LoggedHistory::default();
+        for entry in MessageLog::read(path)? {
+            let LoggedHistory {
+                received,
+                sent,
+                actions,
+                executed,
+            } = &mut history;
+            match entry {
+                LogEntry::Received(msg) => received.push_back(msg),
+                LogEntry::Sent(msg) => sent.push_back(msg),
+                LogEntry::Action(action) => actions.push_back(action),
+                LogEntry::Rollback(lvt) => {
+                    // same as RollbackManager::rollback
+                    while received.back().is_some_and(|m: &Message| m.exec_ts >= lvt) {
+                        received.pop_back();
+                    }
+                    while sent.back().is_some_and(|m: &Message| m.sent_ts >= lvt) {
+                        sent.pop_back();
+                    }
+                    while actions.back().is_some_and(|a| a.timestamp >= lvt) {
+                        actions.pop_back();
+                    }
+                }
+                LogEntry::Free { ts, keep_from } => {
+                    // same as RollbackManager::free_except
+                    *received = std::mem::take(received)
+                        .into_iter()
+                        .filter(|m| m.exec_ts > ts)
+                        .collect();
+                    *sent = std::mem::take(sent)
+                        .into_iter()
+                        .filter(|m| m.sent_ts > ts || m.exec_ts >= keep_from)
+                        .collect();
+                }
+                LogEntry::Executed(gvt) => {
+                    // same as RollbackManager::commit
+                    while actions.front().is_some_and(|a| a.timestamp < gvt) {
+                        actions.pop_front();
+                    }
+                    *executed = (*executed).max(gvt);
+                }
+            }
+        }
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Dependencies;
+    use crate::rollback_manager::RollbackManager;
+
+    fn get_message(from: u16, to: u16, sent_ts: Timestamp, exec_ts: Timestamp) -> Message {
+        Message {
+            id: exec_ts as u32,
+            is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
+            from,
+            to,
+            sent_ts,
+            exec_ts,
+            route: String::from("in"),
+            payload: String::default(),
+        }
+    }
+
+    #[test]
+    fn history_replays_rollbacks_and_ignores_a_torn_last_line() {
+        let path = std::env::temp_dir().join("dcb_message_log_test.log");
+        let _ = fs::remove_file(&path);
+        let log = MessageLog::open(&path, SyncPolicy::Every(2)).unwrap();
+        for entry in [
+            LogEntry::Received(get_message(2, 1, 5, 10)),
+            LogEntry::Sent(get_message(1, 2, 10, 15)),
+            LogEntry::Received(get_message(2, 1, 12, 20)),
+            LogEntry::Sent(get_message(1, 2, 20, 25)),
+            LogEntry::Rollback(12),
+            LogEntry::Received(get_message(2, 1, 8, 12)),
+        ] {
+            log.append(&entry).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"sent":{"sent_ts":"#).unwrap();
+
+        let history = MessageLog::history(&path).unwrap();
+        let exec_ts: Vec<_> = history.received.iter().map(|m| m.exec_ts).collect();
+        assert_eq!(exec_ts, vec![10, 12]);
+        let exec_ts: Vec<_> = history.sent.iter().map(|m| m.exec_ts).collect();
+        assert_eq!(exec_ts, vec![15]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rollbackmanager_logs_its_history_and_drops_what_it_freed() {
+        let path = std::env::temp_dir().join("dcb_message_log_manager_test.log");
+        let _ = fs::remove_file(&path);
+        let mut manager = RollbackManager::new(1, 0);
+        manager
+            .set_log(MessageLog::open(&path, SyncPolicy::Always).unwrap())
+            .unwrap();
+
+        manager.save_message(get_message(2, 1, 5, 10)).unwrap();
+        manager.update(1, 10).unwrap();
+        manager.save_message(get_message(1, 2, 10, 15)).unwrap();
+        manager.take_checkpoint().unwrap();
+        manager.save_message(get_message(2, 1, 9, 20)).unwrap();
+        manager.update(2, 20).unwrap();
+        manager.take_checkpoint().unwrap();
+        manager.save_message(get_message(1, 2, 21, 25)).unwrap();
+        manager.rollback(11).unwrap();
+
+        let history = MessageLog::history(&path).unwrap();
+        assert_eq!(&history.received, manager.received_messages());
+        assert_eq!(&history.sent, manager.sent_messages());
+        // the log starts with what was executed when it was set
+        assert_eq!(MessageLog::read(&path).unwrap().len(), 6);
+
+        // nothing is freed, so the log is left as it is
+        manager.free(5).unwrap();
+        assert_eq!(MessageLog::read(&path).unwrap().len(), 6);
+
+        manager.save_message(get_message(2, 1, 12, 30)).unwrap();
+        manager.free(10).unwrap();
+        let history = MessageLog::history(&path).unwrap();
+        assert_eq!(&history.received, manager.received_messages());
+        assert_eq!(&history.sent, manager.sent_messages());
+        let entries = MessageLog::read(&path).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                LogEntry::Executed(0),
+                LogEntry::Received(get_message(2, 1, 12, 30)),
+                LogEntry::Free {
+                    ts: 10,
+                    keep_from: Timestamp::MAX
+                },
+            ]
+        );
+
+        // the log is reopened with what it knows of its entries
+        let log = MessageLog::open(&path, SyncPolicy::Always).unwrap();
+        log.append(&LogEntry::Sent(get_message(1, 2, 30, 35)))
+            .unwrap();
+        log.free(30, 35, &[], &[get_message(1, 2, 30, 35)], &[])
+            .unwrap();
+        assert_eq!(MessageLog::read(&path).unwrap().len(), 3);
+        let history = MessageLog::history(&path).unwrap();
+        assert!(history.received.is_empty());
+        assert_eq!(history.sent.len(), 1);
+        fs::remove_file(path).unwrap();
+    }
+}
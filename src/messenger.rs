@@ -1,30 +1,38 @@
-use crate::models::{ComponentId, Message};
-use std::collections::HashMap;
+use crate::gvt::Gvt;
+use crate::models::{Message, Packet};
+use crate::scheduler::Scheduler;
 use std::sync::mpsc::{SendError, Sender};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct Messenger {
-    pub local_senders: HashMap<ComponentId, Sender<Message>>,
-    pub network_sender: Sender<Message>,
+    pub(crate) scheduler: Arc<Scheduler>,
+    pub(crate) network_sender: Sender<Packet>,
+    pub(crate) gvt: Arc<Gvt>,
 }
 
 impl Messenger {
-    #[allow(dead_code)]
-    pub fn send(&self, msg: Message) -> Result<(), SendError<Message>> {
-        if let Some(sender) = self.local_senders.get(&msg.to) {
-            sender.send(msg)?;
+    /// Sends a message on behalf of a local component
+    pub fn send(&self, mut msg: Message) -> Result<(), SendError<Message>> {
+        self.gvt.on_send(&mut msg);
+        if self.scheduler.contains(msg.to) {
+            self.send_local(msg)?;
         } else {
-            self.network_sender.send(msg)?;
+            self.network_sender.send(Packet::Message(msg)).map_err(
+                |SendError(packet)| match packet {
+                    Packet::Message(msg) => SendError(msg),
+                    Packet::Control { .. } => unreachable!(),
+                },
+            )?;
         }
         Ok(())
     }
 
-    #[allow(dead_code)]
+    /// Delivers a message to a local component
     pub fn send_local(&self, msg: Message) -> Result<(), SendError<Message>> {
-        if let Some(sender) = self.local_senders.get(&msg.to) {
-            sender.send(msg)?;
-            return Ok(());
-        }
-        Err(SendError(msg))
+        let epoch = msg.epoch;
+        self.scheduler.push(msg).map_err(SendError)?;
+        self.gvt.on_receive(epoch);
+        Ok(())
     }
 }
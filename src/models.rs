@@ -1,8 +1,15 @@
+use crate::recovery_line::{History, RecoveryLine};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
 pub type Timestamp = u64;
 pub type ComponentId = u16;
 
+/// For every component, the latest LVT of that component the sender's state depends on (see
+/// dependency_vector::DependencyVector)
+pub type Dependencies = BTreeMap<ComponentId, Timestamp>;
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Message {
     pub sent_ts: Timestamp,
@@ -13,6 +20,11 @@ pub struct Message {
     pub route: String,
     pub id: u32,
     pub is_anti: bool,
+    /// GVT epoch in which the message was sent; set by the Messenger
+    pub epoch: u32,
+    /// dependency vector of the sender when the message was sent; set by the ComponentManager
+    #[serde(default)]
+    pub dependencies: Dependencies,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -33,7 +45,6 @@ impl From<Message> for MsgCore {
 }
 
 impl Message {
-    #[allow(dead_code)]
     pub fn is_inverse_of(&self, other: &Self) -> bool {
         self.sent_ts == other.sent_ts
             && self.exec_ts == other.exec_ts
@@ -43,14 +54,14 @@ impl Message {
             && self.is_anti != other.is_anti
     }
 
-    #[allow(dead_code)]
-    pub fn get_anti(&self) -> Result<Message, ()> {
+    /// Returns None if the message is already an anti-message
+    pub fn get_anti(&self) -> Option<Message> {
         if self.is_anti {
-            return Err(());
+            return None;
         }
         let mut msg = self.clone();
         msg.is_anti = true;
-        Ok(msg)
+        Some(msg)
     }
 }
 
@@ -58,9 +69,137 @@ impl Message {
 pub struct Checkpoint<State> {
     pub timestamp: Timestamp,
     pub state: State,
+    /// dependency vector of the component when the checkpoint was taken
+    #[serde(default)]
+    pub dependencies: Dependencies,
+}
+
+/// An irreversible side effect requested by a component at a given timestamp.
+///
+/// Actions are only executed once GVT passes their timestamp, which means they can no longer
+/// be rolled back.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CommitAction {
+    pub timestamp: Timestamp,
+    pub payload: String,
 }
 
-#[derive(Clone, Copy)]
-pub struct ComponentCfg {
-    pub id: ComponentId,
+/// Signals exchanged between nodes to compute GVT and detect termination
+///
+/// A GVT round is started by the coordinator with a Cut, which moves every node to a new epoch,
+/// and ends once a Poll shows that every message sent in the previous epoch was received.
+///
+/// A node restarted after a crash sends Recover; the coordinator then halts every node with
+/// Halt and Halted, and gives them a recovery line to restore their components to with
+/// RecoveryLine and Recovered, before GVT rounds resume (see coordinator::run_coordinator).
+///
+/// Snapshot and Marker take consistent snapshots of the federation (see the snapshot module),
+/// and RequestSave, Save, SaveAck and Saved take savepoints (see the savepoint module).
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Control {
+    Cut {
+        epoch: u32,
+    },
+    CutAck {
+        epoch: u32,
+        /// number of messages the node sent in the previous epoch
+        sent: u64,
+        /// checkpoints of every local component that are not later than the node's GVT (see
+        /// recovery_line); None unless the node persists its state
+        checkpoints: Option<BTreeMap<ComponentId, History>>,
+    },
+    Poll {
+        epoch: u32,
+    },
+    PollAck {
+        epoch: u32,
+        /// number of messages of the previous epoch the node received so far
+        received: u64,
+        /// lowest timestamp among the messages pending in the node's queues
+        local_min: Timestamp,
+        /// lowest exec_ts among the messages the node sent in the current epoch
+        red_min: Timestamp,
+    },
+    Gvt {
+        value: Timestamp,
+        /// only messages earlier than window may be handled (see config::SyncMode); it is
+        /// Timestamp::MAX in optimistic mode
+        window: Timestamp,
+        /// latest recovery line, made of checkpoints that are not later than the previous
+        /// GVT; None unless nodes persist their state
+        line: Option<RecoveryLine>,
+    },
+    Terminate,
+    /// sent to the coordinator by a node restarted from its persisted state
+    Recover,
+    /// every node must stop handling messages until it is given a recovery line
+    Halt {
+        epoch: u32,
+    },
+    /// sent to the coordinator once the node no longer handles messages
+    Halted {
+        /// that of the Halt
+        epoch: u32,
+        /// latest epoch the node moved to
+        last_epoch: u32,
+        /// latest GVT the node knows of, which it persisted if it was restarted
+        gvt: Timestamp,
+        /// every checkpoint of every local component; None if the node could not read them
+        checkpoints: Option<BTreeMap<ComponentId, History>>,
+    },
+    /// every node must recover its components to line (see Runnable::recover), then resume in
+    /// epoch, in which messages of earlier epochs are dropped
+    RecoveryLine {
+        line: RecoveryLine,
+        /// latest GVT a node knew of
+        gvt: Timestamp,
+        window: Timestamp,
+        epoch: u32,
+    },
+    /// sent to the coordinator once the node recovered its components
+    Recovered {
+        epoch: u32,
+        error: Option<String>,
+    },
+    /// asks the node to take a snapshot of the federation into dir (see snapshot::trigger)
+    Snapshot {
+        dir: PathBuf,
+    },
+    /// sent to every other node once the node recorded its share of the snapshot into dir
+    Marker {
+        dir: PathBuf,
+    },
+    /// asks the coordinator for a savepoint at `at` into dir (see savepoint::request)
+    RequestSave {
+        at: Timestamp,
+        dir: PathBuf,
+        /// where Saved is sent
+        reply_to: String,
+    },
+    /// every node must save its components at `at` into dir (see Runnable::save)
+    Save {
+        at: Timestamp,
+        dir: PathBuf,
+    },
+    /// sent to the coordinator once the node saved its components
+    SaveAck {
+        at: Timestamp,
+        error: Option<String>,
+    },
+    /// sent by the coordinator once the requested savepoint is complete, or failed
+    Saved {
+        at: Timestamp,
+        error: Option<String>,
+    },
+}
+
+/// Everything that travels between nodes
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Packet {
+    Message(Message),
+    Control {
+        from: String,
+        to: String,
+        control: Control,
+    },
 }
@@ -1,4 +1,3 @@
-mod msg_queue;
 mod msg_queue_base;
 
-pub use msg_queue::MsgQueue;
+pub use msg_queue_base::MsgQueueBase;
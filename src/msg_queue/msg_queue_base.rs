@@ -14,25 +14,22 @@ impl PartialOrd for Message {
     }
 }
 
-#[allow(dead_code)]
 #[derive(Clone)]
 pub struct MsgQueueBase {
     vec: Vec<Message>,
 }
 
 impl MsgQueueBase {
-    #[allow(dead_code)]
     pub fn new() -> MsgQueueBase {
         MsgQueueBase { vec: Vec::new() }
     }
 
-    #[allow(dead_code)]
     pub fn push(&mut self, msg: Message) {
         if let Some((index, _inverse_msg)) = self
             .vec
             .iter()
             .enumerate()
-            .find(|&t| msg.is_inverse_of(&t.1))
+            .find(|&t| msg.is_inverse_of(t.1))
         {
             self.vec.remove(index);
             return;
@@ -45,14 +42,23 @@ impl MsgQueueBase {
         self.vec.insert(index, msg);
     }
 
-    #[allow(dead_code)]
     pub fn pop(&mut self) -> Option<Message> {
         self.vec.pop()
     }
 
-    #[allow(dead_code)]
-    pub fn size(&self) -> usize {
-        self.vec.len()
+    /// Returns the message that would be popped next
+    pub fn peek(&self) -> Option<&Message> {
+        self.vec.last()
+    }
+
+    /// Only keeps the messages for which keep returns true
+    pub fn retain(&mut self, keep: impl FnMut(&Message) -> bool) {
+        self.vec.retain(keep);
+    }
+
+    /// Iterates over the messages in the order they would be popped
+    pub fn iter(&self) -> impl Iterator<Item = &Message> {
+        self.vec.iter().rev()
     }
 }
 
@@ -71,6 +77,8 @@ mod test {
             to: 2,
             id: 123,
             is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
             sent_ts: 1,
         }
     }
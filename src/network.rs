@@ -1,33 +1,106 @@
+use crate::control::NodeControl;
+use crate::gvt::Gvt;
 use crate::messenger::Messenger;
-use crate::models::{ComponentId, Message};
+use crate::models::{ComponentId, Control, Packet};
 use std::collections::HashMap;
 use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
 use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-const BUFFER_SIZE: usize = 1024;
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
 
-#[allow(dead_code)]
-pub fn run_server(address: String, messenger: Messenger) {
-    let listener = TcpListener::bind(address).unwrap();
+/// Receives packets until the node is told to terminate
+///
+/// Connections that fail and packets that cannot be parsed, e.g. truncated ones, are reported
+/// and skipped; connections that carry nothing are only checking that the node is running (see
+/// savepoint::request). Messages sent before the latest recovery are dropped. While the node
+/// records its share of a snapshot, some packets are held back and handled once it is done
+/// (see snapshot::Recorder::hold).
+pub fn run_server(listener: TcpListener, messenger: Messenger, control: NodeControl) {
     for stream in listener.incoming() {
-        let mut stream = stream.unwrap();
-        let mut buffer = [0u8; BUFFER_SIZE];
-        stream.read(&mut buffer).unwrap();
-        let msg = String::from_utf8(buffer.to_vec()).unwrap();
-        let msg = msg.trim_matches(char::from(0));
-        let msg = msg.replace('\n', "");
-        let msg = serde_json::from_str(&msg.to_owned()).unwrap();
-        messenger.send_local(msg).unwrap();
+        let mut buffer = Vec::new();
+        if let Err(e) = stream.and_then(|mut stream| stream.read_to_end(&mut buffer)) {
+            eprintln!("warning: dropped a connection: {}", e);
+            continue;
+        }
+        if buffer.is_empty() {
+            continue;
+        }
+        let packet = match serde_json::from_slice(&buffer) {
+            Ok(packet) => packet,
+            Err(e) => {
+                eprintln!("warning: dropped a malformed packet: {}", e);
+                continue;
+            }
+        };
+        match packet {
+            Packet::Control {
+                from,
+                control: Control::Terminate,
+                ..
+            } => {
+                control.handle(from, Control::Terminate);
+                break;
+            }
+            // held back while the node records its share of a snapshot, see Recorder::hold
+            packet => {
+                if let Some(packet) = control.snapshots.hold(packet) {
+                    deliver(&messenger, &control, packet);
+                }
+            }
+        }
     }
 }
 
-#[allow(dead_code)]
-pub fn run_client(addresses: &HashMap<ComponentId, String>, receiver: Receiver<Message>) {
-    for msg in receiver {
-        let addr = addresses.get(&msg.to).unwrap();
-        let msg = serde_json::to_string(&msg).unwrap();
-        let mut stream = TcpStream::connect(addr).unwrap();
-        stream.write(msg.as_bytes()).unwrap();
+/// Hands a packet the node received over to its destination, or to the node's control
+pub(crate) fn deliver(messenger: &Messenger, control: &NodeControl, packet: Packet) {
+    match packet {
+        // sent before the latest recovery, see Gvt::is_stale
+        Packet::Message(msg) if control.gvt.is_stale(msg.epoch) => (),
+        Packet::Message(msg) => {
+            control.snapshots.on_message(&msg);
+            // the destination may have finished already, in which case the message is dropped
+            let _ = messenger.send_local(msg);
+        }
+        Packet::Control {
+            from, control: c, ..
+        } => control.handle(from, c),
+    }
+}
+
+/// Sends packets until every sender is dropped
+///
+/// Peers that are not listening yet, or that reset the connection before the whole packet was
+/// written, are retried until they receive it, unless the simulation is over, in which case
+/// the packet is dropped. Messages to components of unknown nodes are reported and dropped.
+pub fn run_client(
+    addresses: &HashMap<ComponentId, String>,
+    receiver: Receiver<Packet>,
+    gvt: Arc<Gvt>,
+) {
+    for packet in receiver {
+        let addr = match &packet {
+            Packet::Message(msg) => match addresses.get(&msg.to) {
+                Some(addr) => addr,
+                None => {
+                    eprintln!("warning: dropped a message to unknown component {}", msg.to);
+                    continue;
+                }
+            },
+            Packet::Control { to, .. } => to,
+        };
+        let packet = serde_json::to_string(&packet).unwrap();
+        loop {
+            let sent =
+                TcpStream::connect(addr).and_then(|mut stream| stream.write_all(packet.as_bytes()));
+            match sent {
+                Ok(()) => break,
+                Err(_) if gvt.is_terminated() => break,
+                Err(_) => thread::sleep(RETRY_INTERVAL),
+            }
+        }
     }
 }
@@ -0,0 +1,158 @@
+//! Recovery lines: a checkpoint of every component such that together they form a consistent
+//! global checkpoint, i.e. no checkpoint reflects the receipt of a message whose sending is
+//! not reflected by the checkpoint of its sender.
+//!
+//! The dependency vector saved with a checkpoint tells, for every other component, the latest
+//! LVT of that component the checkpointed state depends on. A checkpoint of that component
+//! reflects that LVT if and only if its timestamp is greater, since a checkpoint taken at ts
+//! is the state of the component before it handled anything at ts. A set of checkpoints is
+//! then consistent if and only if none of them depends on a state that the checkpoint of
+//! another component does not reflect.
+//!
+//! Checkpoints earlier than a recovery line can never be part of a later one, as long as the
+//! checkpoints of the line cannot be rolled back anymore, i.e. are not later than GVT. The
+//! coordinator therefore computes the latest line every GVT round, from the checkpoints nodes
+//! report, and components free the checkpoints earlier than it (see
+//! RollbackManager::fossil_collect); a crashed node is recovered to the latest line the
+//! federation still has the checkpoints of (see coordinator::run_coordinator).
+
+use crate::models::{ComponentId, Dependencies, Timestamp};
+use std::collections::BTreeMap;
+
+/// Timestamp and dependency vector of every checkpoint of a component, in ascending timestamp
+/// order (see CheckpointStore::dependencies)
+pub type History = Vec<(Timestamp, Dependencies)>;
+
+/// For every component, the timestamp of the checkpoint it is restored to
+pub type RecoveryLine = BTreeMap<ComponentId, Timestamp>;
+
+/// Most recent recovery line made of checkpoints that are not later than until, None if there
+/// is none, e.g. because some component has no such checkpoint
+///
+/// Every component starts from its latest checkpoint; as long as a checkpoint depends on a
+/// state that is not reflected by the checkpoint of another component, it is replaced by the
+/// previous one. Checkpoints are only ever replaced by earlier ones, so the first consistent
+/// line found is the most recent one.
+pub fn latest(
+    histories: &BTreeMap<ComponentId, History>,
+    until: Timestamp,
+) -> Option<RecoveryLine> {
+    let mut positions = BTreeMap::new();
+    for (id, history) in histories {
+        let count = history.iter().take_while(|(ts, _)| *ts <= until).count();
+        positions.insert(*id, count.checked_sub(1)?);
+    }
+
+    loop {
+        let line: RecoveryLine = positions
+            .iter()
+            .map(|(id, position)| (*id, histories[id][*position].0))
+            .collect();
+        let mut consistent = true;
+        for (id, position) in positions.iter_mut() {
+            let (_, dependencies) = &histories[id][*position];
+            if !is_reflected_by(*id, dependencies, &line) {
+                *position = position.checked_sub(1)?;
+                consistent = false;
+            }
+        }
+        if consistent {
+            return Some(line);
+        }
+    }
+}
+
+// whether every state the checkpoint of component id depends on is reflected by the line
+fn is_reflected_by(id: ComponentId, dependencies: &Dependencies, line: &RecoveryLine) -> bool {
+    dependencies
+        .iter()
+        .filter(|(other, _)| **other != id)
+        .all(|(other, lvt)| line.get(other).is_none_or(|ts| lvt < ts))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checkpoint_store::CheckpointStore;
+    use crate::rollback_manager::RollbackManager;
+
+    fn checkpoint(
+        timestamp: Timestamp,
+        dependencies: &[(ComponentId, Timestamp)],
+    ) -> (Timestamp, Dependencies) {
+        (timestamp, dependencies.iter().copied().collect())
+    }
+
+    #[test]
+    fn latest_rolls_back_checkpoints_that_depend_on_unreflected_states() {
+        // 2 handled a message 1 sent at 15, and 3 one that 2 sent at 25
+        let mut histories = BTreeMap::new();
+        histories.insert(1, vec![checkpoint(0, &[]), checkpoint(11, &[])]);
+        histories.insert(2, vec![checkpoint(0, &[]), checkpoint(21, &[(1, 15)])]);
+        histories.insert(
+            3,
+            vec![checkpoint(0, &[]), checkpoint(31, &[(1, 15), (2, 25)])],
+        );
+
+        let line = latest(&histories, Timestamp::MAX).unwrap();
+        assert_eq!(line, vec![(1, 11), (2, 0), (3, 0)].into_iter().collect());
+
+        // once 1 checkpoints after 15, the dependency of 2 is reflected
+        histories.get_mut(&1).unwrap().push(checkpoint(16, &[]));
+        let line = latest(&histories, Timestamp::MAX).unwrap();
+        assert_eq!(line, vec![(1, 16), (2, 21), (3, 0)].into_iter().collect());
+
+        let line = latest(&histories, 20).unwrap();
+        assert_eq!(line, vec![(1, 16), (2, 0), (3, 0)].into_iter().collect());
+    }
+
+    #[test]
+    fn latest_is_none_without_a_consistent_line() {
+        let mut histories = BTreeMap::new();
+        histories.insert(1, vec![checkpoint(5, &[(2, 7)])]);
+        histories.insert(2, vec![checkpoint(3, &[])]);
+        assert_eq!(latest(&histories, Timestamp::MAX), None);
+
+        histories.insert(2, vec![checkpoint(8, &[])]);
+        assert_eq!(latest(&histories, 6), None);
+        assert!(latest(&histories, 8).is_some());
+    }
+
+    #[test]
+    fn checkpoints_earlier_than_the_line_can_be_freed() {
+        let mut first = RollbackManager::new(1, 0);
+        let mut second = RollbackManager::new(2, 0);
+        first.update(1, 10).unwrap();
+        first.take_checkpoint().unwrap();
+        first.update(2, 15).unwrap();
+        second.merge_dependencies(first.dependencies().get_map());
+        second.update(1, 16).unwrap();
+        second.take_checkpoint().unwrap();
+        first.take_checkpoint().unwrap();
+
+        let mut managers = [&mut first, &mut second];
+        let histories: BTreeMap<ComponentId, History> = managers
+            .iter()
+            .map(|m| (m.id(), m.checkpoints().dependencies().unwrap()))
+            .collect();
+        let earlier = latest(&histories, 15).unwrap();
+        assert_eq!(earlier, vec![(1, 11), (2, 0)].into_iter().collect());
+        let line = latest(&histories, 17).unwrap();
+        assert_eq!(line, vec![(1, 16), (2, 17)].into_iter().collect());
+
+        // GVT alone would let 2 free its initial checkpoint, which the earlier line needs
+        for (line, first_kept) in [(earlier, [11, 0]), (line, [16, 17])] {
+            for manager in managers.iter_mut() {
+                let id = manager.id();
+                manager.fossil_collect(17, Some(line[&id])).unwrap();
+            }
+            let first_timestamps: Vec<_> = managers
+                .iter()
+                .map(|m| m.checkpoints().first_timestamp().unwrap())
+                .collect();
+            assert_eq!(first_timestamps, first_kept);
+        }
+        assert_eq!(first.checkpoints().timestamps(), vec![16]);
+        assert_eq!(second.checkpoints().timestamps(), vec![17]);
+    }
+}
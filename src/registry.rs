@@ -0,0 +1,615 @@
+use crate::checkpoint_store::{BoxedStore, FileStore};
+use crate::component::Component;
+use crate::component_manager::{ComponentManager, Runnable};
+use crate::config::{ComponentCfg, FederationCfg, PersistenceCfg};
+use crate::dead_letter::DeadLetterSink;
+use crate::message_log::MessageLog;
+use crate::models::{Checkpoint, ComponentId};
+use crate::rollback_manager::RollbackManager;
+use crate::route_pattern;
+use crate::savepoint::{self, SavedComponent};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, LinkedList};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Knows how to instantiate every component type of an application from its name
+///
+/// Applications register their component types under the names used in the config file's
+/// "type" fields, then the launcher builds one instance per configured component.
+#[derive(Default)]
+pub struct Registry {
+    entries: HashMap<String, Entry>,
+}
+
+type CheckParams = dyn Fn(&serde_json::Value) -> Result<(), serde_json::Error>;
+type Build =
+    dyn Fn(&ComponentCfg, Arc<dyn DeadLetterSink>) -> Result<Box<dyn Runnable>, serde_json::Error>;
+type BuildPersistent = dyn Fn(
+    &ComponentCfg,
+    Arc<dyn DeadLetterSink>,
+    &PersistenceCfg,
+    bool,
+) -> io::Result<Box<dyn Runnable>>;
+type BuildRestored =
+    dyn Fn(&ComponentCfg, Arc<dyn DeadLetterSink>, SavedComponent) -> io::Result<Box<dyn Runnable>>;
+
+struct Entry {
+    check_params: Box<CheckParams>,
+    build: Box<Build>,
+
+    // None if the type's state cannot be persisted
+    build_persistent: Option<Box<BuildPersistent>>,
+
+    // None if the type's state cannot be saved
+    build_restored: Option<Box<BuildRestored>>,
+
+    // routes declared by the component type, see Component::routes
+    routes: Vec<&'static str>,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    /// No component type is registered under the component's type name
+    UnknownType {
+        component: ComponentId,
+        type_name: String,
+    },
+
+    /// The component's params cannot be deserialized into its type's Params
+    InvalidParams {
+        component: ComponentId,
+        error: serde_json::Error,
+    },
+
+    /// A route the component's type declares is missing from the component's route table
+    UnroutedRoute {
+        component: ComponentId,
+        route: String,
+    },
+
+    /// The component's route table has a route its type does not declare
+    UndeclaredRoute {
+        component: ComponentId,
+        route: String,
+    },
+
+    /// The federation persists its state but the component's type was not registered with
+    /// register_persistent
+    NotPersistent {
+        component: ComponentId,
+        type_name: String,
+    },
+
+    /// The component's persisted state could not be created or reopened
+    Storage {
+        component: ComponentId,
+        error: io::Error,
+    },
+
+    /// The component could not be restored from what the savepoint holds of it
+    Savepoint {
+        component: ComponentId,
+        error: io::Error,
+    },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::UnknownType {
+                component,
+                type_name,
+            } => write!(f, "component {} has unknown type {}", component, type_name),
+            RegistryError::InvalidParams { component, error } => {
+                write!(f, "component {} has invalid params: {}", component, error)
+            }
+            RegistryError::UnroutedRoute { component, route } => {
+                write!(
+                    f,
+                    "route {} of component {} is not routed",
+                    route, component
+                )
+            }
+            RegistryError::UndeclaredRoute { component, route } => write!(
+                f,
+                "component {} does not declare route {} it is routed from",
+                component, route
+            ),
+            RegistryError::NotPersistent {
+                component,
+                type_name,
+            } => write!(
+                f,
+                "component {} has type {} whose state cannot be persisted",
+                component, type_name
+            ),
+            RegistryError::Storage { component, error } => write!(
+                f,
+                "cannot open the persisted state of component {}: {}",
+                component, error
+            ),
+            RegistryError::Savepoint { component, error } => write!(
+                f,
+                "cannot restore component {} from the savepoint: {}",
+                component, error
+            ),
+        }
+    }
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Registers a component type whose instances take a checkpoint before every new timestamp
+    pub fn register<State>(&mut self, type_name: impl Into<String>) -> &mut Registry
+    where
+        State: Component + Clone + Send + 'static,
+    {
+        self.register_with_policy::<State>(type_name, |_, _| true)
+    }
+
+    /// Registers a component type along with its checkpoint policy (see ComponentManager)
+    ///
+    /// A type registered twice replaces the previous registration
+    pub fn register_with_policy<State>(
+        &mut self,
+        type_name: impl Into<String>,
+        should_take_checkpoint: fn(&State, &RollbackManager<State>) -> bool,
+    ) -> &mut Registry
+    where
+        State: Component + Clone + Send + 'static,
+    {
+        let entry = Entry {
+            check_params: Box::new(|params| State::Params::deserialize(params).map(|_| ())),
+            build: Box::new(move |cfg, dead_letters| {
+                Ok(Box::new(ComponentManager::<State>::new(
+                    cfg.id,
+                    Box::new(cfg.translator(dead_letters)?),
+                    should_take_checkpoint,
+                )))
+            }),
+            build_persistent: None,
+            build_restored: None,
+            routes: State::routes(),
+        };
+        self.entries.insert(type_name.into(), entry);
+        self
+    }
+
+    /// Same as register, but the state of the type's instances can also be persisted (see
+    /// build_persistent), recorded by snapshots (see the snapshot module) and saved (see the
+    /// savepoint module)
+    pub fn register_persistent<State>(&mut self, type_name: impl Into<String>) -> &mut Registry
+    where
+        State: Component + Clone + Send + Serialize + DeserializeOwned + 'static,
+    {
+        self.register_persistent_with_policy::<State>(type_name, |_, _| true)
+    }
+
+    /// Same as register_with_policy, but the state of the type's instances can also be
+    /// persisted (see build_persistent)
+    ///
+    /// Whether they are built, persisted or restored, the type's instances keep their
+    /// checkpoints in a BoxedStore, so that they all share the same policy.
+    pub fn register_persistent_with_policy<State>(
+        &mut self,
+        type_name: impl Into<String>,
+        should_take_checkpoint: fn(&State, &RollbackManager<State, BoxedStore<State>>) -> bool,
+    ) -> &mut Registry
+    where
+        State: Component + Clone + Send + Serialize + DeserializeOwned + 'static,
+    {
+        let type_name = type_name.into();
+        self.register::<State>(type_name.clone());
+        let entry = self.entries.get_mut(&type_name).unwrap();
+        entry.build = Box::new(move |cfg, dead_letters| {
+            let manager = ComponentManager::with_store(
+                cfg.id,
+                Box::new(cfg.translator(dead_letters)?),
+                should_take_checkpoint,
+                Box::new(LinkedList::new()) as BoxedStore<State>,
+            )
+            .expect("in-memory checkpoints cannot fail");
+            Ok(Box::new(manager.serializable()) as Box<dyn Runnable>)
+        });
+        entry.build_persistent = Some(Box::new(move |cfg, dead_letters, persistence, recover| {
+            let dir = persistence.component_dir(cfg.id);
+            let gateway = Box::new(cfg.translator(dead_letters)?);
+            let log_path = dir.join("messages.log");
+            let checkpoints = dir.join("checkpoints");
+            let manager = if recover {
+                ComponentManager::reopen(
+                    cfg.id,
+                    gateway,
+                    should_take_checkpoint,
+                    Box::new(FileStore::open(checkpoints, persistence.in_memory)?),
+                    MessageLog::open(log_path, persistence.sync)?,
+                )?
+            } else {
+                let mut manager = ComponentManager::with_store(
+                    cfg.id,
+                    gateway,
+                    should_take_checkpoint,
+                    Box::new(FileStore::create(checkpoints, persistence.in_memory)?),
+                )?;
+                manager.set_log(MessageLog::open(log_path, persistence.sync)?)?;
+                manager
+            };
+            Ok(Box::new(manager.serializable()) as Box<dyn Runnable>)
+        }));
+        entry.build_restored = Some(Box::new(move |cfg, dead_letters, saved| {
+            let checkpoint = Checkpoint {
+                timestamp: saved.checkpoint.timestamp,
+                state: State::deserialize(saved.checkpoint.state)?,
+                dependencies: saved.checkpoint.dependencies,
+            };
+            let manager = ComponentManager::restore(
+                cfg.id,
+                Box::new(cfg.translator(dead_letters)?),
+                should_take_checkpoint,
+                Box::new(LinkedList::new()) as BoxedStore<State>,
+                (saved.at, checkpoint, saved.pending),
+            )?;
+            Ok(Box::new(manager.serializable()) as Box<dyn Runnable>)
+        }));
+        self
+    }
+
+    pub fn contains(&self, type_name: &str) -> bool {
+        self.entries.contains_key(type_name)
+    }
+
+    /// Checks that the component's type is registered, that its params are valid and that its
+    /// route table matches the routes its type declares
+    ///
+    /// A route pattern of the table (see the route_pattern module) routes every declared route
+    /// it matches, and must match at least one of them.
+    pub fn check(&self, cfg: &ComponentCfg) -> Result<(), RegistryError> {
+        let entry = self.entry(cfg)?;
+        (entry.check_params)(&cfg.params).map_err(|error| RegistryError::InvalidParams {
+            component: cfg.id,
+            error,
+        })?;
+
+        if entry.routes.is_empty() {
+            return Ok(());
+        }
+        let matches = |key: &str, route: &str| route_pattern::captures(key, route).is_some();
+        let unrouted = entry
+            .routes
+            .iter()
+            .find(|r| !cfg.routes.keys().any(|key| matches(key, r)));
+        if let Some(route) = unrouted {
+            return Err(RegistryError::UnroutedRoute {
+                component: cfg.id,
+                route: String::from(*route),
+            });
+        }
+        let mut undeclared: Vec<&String> = cfg
+            .routes
+            .keys()
+            .filter(|key| !entry.routes.iter().any(|r| matches(key, r)))
+            .collect();
+        undeclared.sort();
+        match undeclared.first() {
+            Some(route) => Err(RegistryError::UndeclaredRoute {
+                component: cfg.id,
+                route: String::clone(route),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks every component of the federation (see check), and that their state can be
+    /// persisted if the federation persists its state
+    pub fn check_all(&self, cfg: &FederationCfg) -> Result<(), RegistryError> {
+        match self.check_registered(cfg)?.first() {
+            Some(c) => Err(RegistryError::UnknownType {
+                component: c.id,
+                type_name: c.type_name.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Same as check_all, but components whose type is not registered are skipped instead;
+    /// returns the skipped components
+    pub fn check_registered<'a>(
+        &self,
+        cfg: &'a FederationCfg,
+    ) -> Result<Vec<&'a ComponentCfg>, RegistryError> {
+        let (registered, unknown): (Vec<_>, Vec<_>) = cfg
+            .components
+            .iter()
+            .partition(|c| self.contains(&c.type_name));
+        registered.into_iter().try_for_each(|c| {
+            self.check(c)?;
+            if cfg.persistence.is_some() {
+                self.persistent_entry(c)?;
+            }
+            Ok(())
+        })?;
+        Ok(unknown)
+    }
+
+    /// Instantiates a configured component; the instance is initialized right away
+    ///
+    /// Messages the instance emits through unknown routes go to dead_letters
+    pub fn build(
+        &self,
+        cfg: &ComponentCfg,
+        dead_letters: Arc<dyn DeadLetterSink>,
+    ) -> Result<Box<dyn Runnable>, RegistryError> {
+        self.check(cfg)?;
+        (self.entry(cfg)?.build)(cfg, dead_letters).map_err(|error| RegistryError::InvalidParams {
+            component: cfg.id,
+            error,
+        })
+    }
+
+    /// Same as build, but the instance persists its checkpoints and messages in its own
+    /// directory of persistence.dir; if recover is set, the instance is reopened from what it
+    /// persisted instead, and it must be recovered before it is started (see init::init)
+    pub fn build_persistent(
+        &self,
+        cfg: &ComponentCfg,
+        dead_letters: Arc<dyn DeadLetterSink>,
+        persistence: &PersistenceCfg,
+        recover: bool,
+    ) -> Result<Box<dyn Runnable>, RegistryError> {
+        self.check(cfg)?;
+        (self.persistent_entry(cfg)?)(cfg, dead_letters, persistence, recover).map_err(|error| {
+            RegistryError::Storage {
+                component: cfg.id,
+                error,
+            }
+        })
+    }
+
+    /// Same as build, but the instance resumes from what the savepoint in dir holds of it
+    /// instead of being initialized (see the savepoint module)
+    pub fn build_restored(
+        &self,
+        cfg: &ComponentCfg,
+        dead_letters: Arc<dyn DeadLetterSink>,
+        dir: &Path,
+    ) -> Result<Box<dyn Runnable>, RegistryError> {
+        self.check(cfg)?;
+        let build = self.entry(cfg)?.build_restored.as_deref().ok_or_else(|| {
+            RegistryError::NotPersistent {
+                component: cfg.id,
+                type_name: cfg.type_name.clone(),
+            }
+        })?;
+        savepoint::load(dir, cfg.id)
+            .and_then(|saved| build(cfg, dead_letters, saved))
+            .map_err(|error| RegistryError::Savepoint {
+                component: cfg.id,
+                error,
+            })
+    }
+
+    fn persistent_entry(&self, cfg: &ComponentCfg) -> Result<&BuildPersistent, RegistryError> {
+        self.entry(cfg)?
+            .build_persistent
+            .as_deref()
+            .ok_or_else(|| RegistryError::NotPersistent {
+                component: cfg.id,
+                type_name: cfg.type_name.clone(),
+            })
+    }
+
+    fn entry(&self, cfg: &ComponentCfg) -> Result<&Entry, RegistryError> {
+        self.entries
+            .get(&cfg.type_name)
+            .ok_or_else(|| RegistryError::UnknownType {
+                component: cfg.id,
+                type_name: cfg.type_name.clone(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RouteCfg;
+    use crate::context::Context;
+    use crate::dead_letter::DiscardSink;
+    use crate::gvt::Gvt;
+    use crate::messenger::Messenger;
+    use crate::models::{Message, MsgCore, Timestamp};
+    use crate::scheduler::Scheduler;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct Vehicle {
+        mass: u32,
+    }
+
+    #[derive(Clone, Deserialize)]
+    struct VehicleParams {
+        mass: u32,
+    }
+
+    impl Component for Vehicle {
+        type Params = VehicleParams;
+
+        fn init(params: VehicleParams, _ctx: &mut Context) -> Self {
+            Vehicle { mass: params.mass }
+        }
+
+        fn routes() -> Vec<&'static str> {
+            vec!["position"]
+        }
+
+        fn on_message(self, _msg: &MsgCore, _ctx: &mut Context) -> Self {
+            self
+        }
+    }
+
+    fn get_cfg(type_name: &str, params: serde_json::Value) -> ComponentCfg {
+        ComponentCfg {
+            id: 7,
+            type_name: String::from(type_name),
+            node: String::from("A"),
+            params,
+            routes: vec![(
+                String::from("position"),
+                vec![RouteCfg {
+                    to: 8,
+                    route: String::from("in"),
+                    delay: 0,
+                    transform: Vec::new(),
+                }],
+            )]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn build_instantiates_registered_types() {
+        let mut registry = Registry::new();
+        registry.register::<Vehicle>("vehicle");
+        assert!(registry.contains("vehicle"));
+
+        let cfg = get_cfg("vehicle", serde_json::json!({ "mass": 1200 }));
+        let component = registry.build(&cfg, Arc::new(DiscardSink)).unwrap();
+        assert_eq!(component.id(), 7);
+    }
+
+    #[test]
+    fn build_rejects_unknown_types_and_invalid_params() {
+        let mut registry = Registry::new();
+        registry.register_with_policy::<Vehicle>("vehicle", |state, _| state.mass > 0);
+
+        match registry.check(&get_cfg("truck", serde_json::json!({ "mass": 1200 }))) {
+            Err(RegistryError::UnknownType { component: 7, .. }) => (),
+            _ => panic!(),
+        }
+        match registry.check(&get_cfg("vehicle", serde_json::json!({ "weight": 1200 }))) {
+            Err(RegistryError::InvalidParams { component: 7, .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn check_compares_route_tables_with_declared_routes() {
+        let mut registry = Registry::new();
+        registry.register::<Vehicle>("vehicle");
+        let mut cfg = get_cfg("vehicle", serde_json::json!({ "mass": 1200 }));
+        assert!(registry.check(&cfg).is_ok());
+
+        let dest = cfg.routes.remove("position").unwrap();
+        cfg.routes.insert(String::from("postion"), dest);
+        match registry.check(&cfg) {
+            Err(RegistryError::UnroutedRoute { route, .. }) => assert_eq!(route, "position"),
+            _ => panic!(),
+        }
+
+        let dest = cfg.routes["postion"].clone();
+        cfg.routes.insert(String::from("position"), dest);
+        match registry.check(&cfg) {
+            Err(RegistryError::UndeclaredRoute { route, .. }) => assert_eq!(route, "postion"),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn check_matches_route_patterns_with_declared_routes() {
+        let mut registry = Registry::new();
+        registry.register::<Vehicle>("vehicle");
+        let mut cfg = get_cfg("vehicle", serde_json::json!({ "mass": 1200 }));
+        let dest = cfg.routes.remove("position").unwrap();
+        cfg.routes.insert(String::from("pos*"), dest.clone());
+        assert!(registry.check(&cfg).is_ok());
+
+        cfg.routes.insert(String::from("*/speed"), dest);
+        match registry.check(&cfg) {
+            Err(RegistryError::UndeclaredRoute { route, .. }) => assert_eq!(route, "*/speed"),
+            _ => panic!(),
+        }
+
+        cfg.routes.remove("pos*");
+        match registry.check(&cfg) {
+            Err(RegistryError::UnroutedRoute { route, .. }) => assert_eq!(route, "position"),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn persistent_types_keep_their_policy_when_built_in_memory() {
+        static ASKED: AtomicBool = AtomicBool::new(false);
+        let mut registry = Registry::new();
+        registry.register_persistent_with_policy::<Vehicle>("vehicle", |_, _| {
+            ASKED.store(true, Ordering::SeqCst);
+            false
+        });
+        let cfg = get_cfg("vehicle", serde_json::json!({ "mass": 1200 }));
+        let mut component = registry.build(&cfg, Arc::new(DiscardSink)).unwrap();
+
+        let (network_sender, _network_receiver) = channel();
+        let messenger = Messenger {
+            scheduler: Arc::new(Scheduler::new(Vec::new(), Timestamp::MAX)),
+            network_sender,
+            gvt: Arc::new(Gvt::new()),
+        };
+        component
+            .handle(
+                Message {
+                    id: 0,
+                    is_anti: false,
+                    epoch: 0,
+                    dependencies: Default::default(),
+                    from: 8,
+                    to: 7,
+                    sent_ts: 0,
+                    exec_ts: 10,
+                    route: String::from("in"),
+                    payload: String::default(),
+                },
+                &messenger,
+            )
+            .unwrap();
+        assert!(ASKED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn only_persistent_types_can_be_built_persistent() {
+        let dir = std::env::temp_dir().join("dcb_registry_persistent_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let persistence = PersistenceCfg {
+            dir: dir.clone(),
+            in_memory: 1,
+            sync: Default::default(),
+        };
+        let cfg = get_cfg("vehicle", serde_json::json!({ "mass": 1200 }));
+        let build = |registry: &Registry, recover| {
+            registry.build_persistent(&cfg, Arc::new(DiscardSink), &persistence, recover)
+        };
+
+        let mut registry = Registry::new();
+        registry.register::<Vehicle>("vehicle");
+        match build(&registry, false) {
+            Err(RegistryError::NotPersistent { component: 7, .. }) => (),
+            _ => panic!(),
+        }
+
+        registry.register_persistent::<Vehicle>("vehicle");
+        match build(&registry, true) {
+            Err(RegistryError::Storage { component: 7, .. }) => (),
+            _ => panic!(),
+        }
+        assert_eq!(build(&registry, false).unwrap().id(), 7);
+        assert!(persistence.component_dir(7).join("messages.log").exists());
+        assert_eq!(build(&registry, true).unwrap().id(), 7);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,10 @@
-use crate::models::{Checkpoint, ComponentId, Message, Timestamp};
+use crate::checkpoint_store::CheckpointStore;
+use crate::dependency_vector::DependencyVector;
+use crate::message_log::{LogEntry, MessageLog};
+use crate::models::{Checkpoint, CommitAction, ComponentId, Dependencies, Message, Timestamp};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashSet, LinkedList};
+use std::io;
 
 /// This must ONLY be used in the DCB, NOT IN THE COMPONENT.
 ///
@@ -17,24 +22,48 @@ use std::collections::{HashSet, LinkedList};
 ///
 /// A SINGLE message is ALWAYS saved when:
 ///     1) The save_message method is called;
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub struct RollbackManager<State> {
+///
+/// Commit actions are buffered until GVT passes their timestamp (see the commit method) and
+/// are discarded when they are rolled back. The manager remembers until when they were
+/// executed (see mark_executed), so that they are not executed again after a recovery.
+///
+/// Checkpoints are kept in memory unless another CheckpointStore is given to with_store.
+/// Saved messages and rollbacks can also be written ahead to a MessageLog (see set_log).
+///
+/// The component's DependencyVector is saved along with every checkpoint and restored with it.
+///
+/// A manager can be serialized whenever its State and its Store can, e.g. to move a component
+/// to another node or to dump it while debugging. The MessageLog is not part of it: a
+/// deserialized manager writes nothing ahead until set_log is called.
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RollbackManager<State, Store = LinkedList<Checkpoint<State>>> {
     state: State,
     lvt: Timestamp,
     id: ComponentId,
 
     // checkpoints must be in ascending timestamp order
-    checkpoints: LinkedList<Checkpoint<State>>,
+    checkpoints: Store,
 
     // received_messages must be in ascending exec_ts order
     received_messages: LinkedList<Message>,
 
     // sent_messages must be in ascending sent_ts order
     sent_messages: LinkedList<Message>,
+
+    // actions must be in ascending timestamp order
+    actions: LinkedList<CommitAction>,
+
+    // the actions earlier than it were executed
+    #[serde(default)]
+    executed: Timestamp,
+
+    dependencies: DependencyVector,
+
+    #[serde(skip)]
+    log: Option<MessageLog>,
 }
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum Failure {
     /// Occurs when an operation is inconsistent with the current LVT or breaks the ordering of messages
     TimeViolation,
@@ -46,92 +75,242 @@ pub enum Failure {
     /// A message is invalid when the component is neither the destiny nor the destination
     /// or if it is an antimessage
     InvalidMessage,
+
+    /// The checkpoint store could not write, read or remove a checkpoint
+    Storage(io::Error),
+}
+
+/// Lets failures be reported like any other I/O error, e.g. when a component fails
+impl From<Failure> for io::Error {
+    fn from(failure: Failure) -> io::Error {
+        match failure {
+            Failure::Storage(e) => e,
+            Failure::InsufficientCheckpoints => {
+                io::Error::new(io::ErrorKind::NotFound, "no checkpoint to restore")
+            }
+            failure => io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", failure)),
+        }
+    }
 }
 
 impl<State> RollbackManager<State>
 where
     State: Clone,
 {
-    /// Constructor
-    #[allow(dead_code)]
+    /// Constructor; checkpoints are kept in memory
     pub fn new(id: ComponentId, initial_state: State) -> RollbackManager<State> {
-        let mut checkpoints = LinkedList::new();
-        checkpoints.push_back(Checkpoint {
-            state: initial_state.clone(),
-            timestamp: 0,
-        });
-        RollbackManager {
+        RollbackManager::with_store(id, initial_state, LinkedList::new())
+            .expect("in-memory checkpoints cannot fail")
+    }
+}
+
+impl<State, Store> RollbackManager<State, Store>
+where
+    State: Clone,
+    Store: CheckpointStore<State>,
+{
+    /// Constructor; the first checkpoint is pushed to the given store, which should be empty
+    pub fn with_store(
+        id: ComponentId,
+        initial_state: State,
+        checkpoints: Store,
+    ) -> Result<RollbackManager<State, Store>, Failure> {
+        let checkpoint = Checkpoint {
             state: initial_state,
-            lvt: 0,
-            id: id,
-            checkpoints: checkpoints,
+            timestamp: 0,
+            dependencies: DependencyVector::new(id, Vec::new()).get_map().clone(),
+        };
+        RollbackManager::from_checkpoint(id, checkpoint, checkpoints)
+    }
+
+    /// Same as with_store, but the component starts from the given checkpoint, e.g. one saved
+    /// in a savepoint (see savepoint), instead of starting at 0
+    pub fn from_checkpoint(
+        id: ComponentId,
+        checkpoint: Checkpoint<State>,
+        mut checkpoints: Store,
+    ) -> Result<RollbackManager<State, Store>, Failure> {
+        checkpoints
+            .push(checkpoint.clone())
+            .map_err(Failure::Storage)?;
+        Ok(RollbackManager {
+            state: checkpoint.state,
+            lvt: checkpoint.timestamp,
+            id,
+            checkpoints,
             received_messages: LinkedList::new(),
             sent_messages: LinkedList::new(),
+            actions: LinkedList::new(),
+            executed: 0,
+            dependencies: DependencyVector::from_map(id, checkpoint.dependencies),
+            log: None,
+        })
+    }
+
+    /// Reopens the history a component persisted to the given store and log before its node
+    /// stopped, including the commit actions it did not execute; the state and the LVT are
+    /// those of the latest checkpoint
+    ///
+    /// The history may go past that checkpoint, so recover must be called before the
+    /// component handles anything.
+    pub fn reopen(
+        id: ComponentId,
+        mut checkpoints: Store,
+        log: MessageLog,
+    ) -> Result<RollbackManager<State, Store>, Failure> {
+        let latest = checkpoints
+            .restore(Timestamp::MAX)
+            .map_err(Failure::Storage)?
+            .ok_or(Failure::InsufficientCheckpoints)?;
+        let history = MessageLog::history(log.path()).map_err(Failure::Storage)?;
+        Ok(RollbackManager {
+            state: latest.state,
+            lvt: latest.timestamp,
+            id,
+            checkpoints,
+            received_messages: history.received,
+            sent_messages: history.sent,
+            actions: history.actions,
+            executed: history.executed,
+            dependencies: DependencyVector::from_map(id, latest.dependencies),
+            log: Some(log),
+        })
+    }
+
+    /// Writes every message and action saved and every rollback from now on to the given log,
+    /// which is first rewritten with the history saved so far
+    pub fn set_log(&mut self, log: MessageLog) -> Result<(), Failure> {
+        self.log = Some(log);
+        self.rewrite_log()
+    }
+
+    fn write_ahead(&self, entry: LogEntry) -> Result<(), Failure> {
+        match &self.log {
+            Some(log) => log.append(&entry).map_err(Failure::Storage),
+            None => Ok(()),
         }
     }
 
     /// This function must be called whenever the component sends or receives a message
-    #[allow(dead_code)]
     pub fn save_message(&mut self, msg: Message) -> Result<(), Failure> {
         if msg.from != self.id && msg.to != self.id || msg.is_anti {
             return Err(Failure::InvalidMessage);
         }
 
         if msg.from == self.id {
-            if let Some(last) = self.sent_messages.back() {
-                if last.sent_ts > msg.sent_ts {
-                    return Err(Failure::TimeViolation);
-                }
-            }
-            self.sent_messages.push_back(msg);
+            self.save_sent_message(msg)
         } else {
-            if let Some(last) = self.received_messages.back() {
-                if last.exec_ts > msg.exec_ts {
-                    return Err(Failure::TimeViolation);
-                }
+            self.save_received_message(msg)
+        }
+    }
+
+    /// Same as save_message, but the message is always saved as sent
+    ///
+    /// This is needed for messages that the component sends to itself
+    pub fn save_sent_message(&mut self, msg: Message) -> Result<(), Failure> {
+        if msg.from != self.id || msg.is_anti {
+            return Err(Failure::InvalidMessage);
+        }
+        if let Some(last) = self.sent_messages.back() {
+            if last.sent_ts > msg.sent_ts {
+                return Err(Failure::TimeViolation);
             }
-            self.received_messages.push_back(msg);
         }
+        self.write_ahead(LogEntry::Sent(msg.clone()))?;
+        self.sent_messages.push_back(msg);
+        Ok(())
+    }
 
-        return Ok(());
+    /// Same as save_message, but the message is always saved as received
+    ///
+    /// This is needed for messages that the component sends to itself
+    pub fn save_received_message(&mut self, msg: Message) -> Result<(), Failure> {
+        if msg.to != self.id || msg.is_anti {
+            return Err(Failure::InvalidMessage);
+        }
+        if let Some(last) = self.received_messages.back() {
+            if last.exec_ts > msg.exec_ts {
+                return Err(Failure::TimeViolation);
+            }
+        }
+        self.write_ahead(LogEntry::Received(msg.clone()))?;
+        self.received_messages.push_back(msg);
+        Ok(())
+    }
+
+    /// This function must be called whenever the component emits a commit action
+    pub fn save_action(&mut self, action: CommitAction) -> Result<(), Failure> {
+        if let Some(last) = self.actions.back() {
+            if last.timestamp > action.timestamp {
+                return Err(Failure::TimeViolation);
+            }
+        }
+        self.write_ahead(LogEntry::Action(action.clone()))?;
+        self.actions.push_back(action);
+        Ok(())
+    }
+
+    /// Removes and returns all commit actions whose timestamp is less than gvt
+    ///
+    /// These actions can no longer be rolled back, so they must be executed
+    pub fn commit(&mut self, gvt: Timestamp) -> Vec<CommitAction> {
+        let mut committed = Vec::new();
+        while let Some(first) = self.actions.front() {
+            if first.timestamp >= gvt {
+                break;
+            }
+            committed.push(self.actions.pop_front().unwrap());
+        }
+        committed
+    }
+
+    /// Must be called once the actions commit returned for gvt were executed, so that they are
+    /// not executed again if the component is recovered (see executed)
+    pub fn mark_executed(&mut self, gvt: Timestamp) -> Result<(), Failure> {
+        self.write_ahead(LogEntry::Executed(gvt))?;
+        self.executed = self.executed.max(gvt);
+        Ok(())
     }
 
     /// Removes all checkpoints that were rolled back and resets the current state
     ///
-    /// A checkpoint is rolled back if its timestamp is greater than or equal to rollback_ts
+    /// A checkpoint is rolled back if its timestamp is greater than ts; the state and the LVT
+    /// are reset to the latest checkpoint c that was not rolled back
     ///
-    /// Returns the messages that must be sent as a consequence of the rollback
-    #[allow(dead_code)]
-    pub fn rollback(&mut self, ts: Timestamp) -> Result<HashSet<Message>, Failure> {
+    /// Everything that happened at ts or later is undone: received messages whose exec_ts is
+    /// not lower than ts must be handled again, sent messages whose sent_ts is not lower than ts
+    /// must be cancelled and commit actions are discarded. What happened between c and ts is
+    /// kept, as c may be far older than ts when checkpoints are sparse, even older than GVT.
+    ///
+    /// Returns the messages that must be sent as a consequence of the rollback, and the
+    /// received messages whose exec_ts is in [c, ts), in exec_ts order: the component must
+    /// coast forward by handling them again, without sending anything nor emitting any
+    /// action, before it handles anything else (see coast).
+    pub fn rollback(&mut self, ts: Timestamp) -> Result<(HashSet<Message>, Vec<Message>), Failure> {
         let mut to_be_sent: HashSet<Message> = HashSet::new();
 
         if ts > self.lvt {
             return Err(Failure::TimeViolation);
         }
 
-        match self.checkpoints.front() {
+        match self.checkpoints.first_timestamp() {
             Some(first) => {
-                if first.timestamp > ts {
+                if first > ts {
                     return Err(Failure::InsufficientCheckpoints);
                 }
             }
             None => return Err(Failure::InsufficientCheckpoints),
         }
 
-        loop {
-            match self.checkpoints.back() {
-                None => panic!(),
-                Some(last) => {
-                    if last.timestamp > ts {
-                        self.checkpoints.pop_back().unwrap();
-                    } else {
-                        self.lvt = last.timestamp;
-                        self.state = last.state.clone();
-                        break;
-                    }
-                }
-            }
-        }
+        let last = self
+            .checkpoints
+            .restore(ts)
+            .map_err(Failure::Storage)?
+            .expect("the first checkpoint is not rolled back");
+        self.lvt = last.timestamp;
+        self.state = last.state;
+        self.dependencies = DependencyVector::from_map(self.id, last.dependencies);
+        self.write_ahead(LogEntry::Rollback(ts))?;
 
         while let Some(last) = self.received_messages.back() {
             if last.exec_ts < ts {
@@ -149,7 +328,28 @@ where
             to_be_sent.insert(msg);
         }
 
-        return Ok(to_be_sent);
+        while let Some(last) = self.actions.back() {
+            if last.timestamp < ts {
+                break;
+            }
+            self.actions.pop_back();
+        }
+
+        let to_coast_through = self
+            .received_messages
+            .iter()
+            .skip_while(|msg| msg.exec_ts < self.lvt)
+            .cloned()
+            .collect();
+        Ok((to_be_sent, to_coast_through))
+    }
+
+    /// Must be called for every message rollback returned to coast through, once it was
+    /// handled again: the state and the LVT are updated, but nothing is saved since the
+    /// message, and whatever it caused, is already part of the history
+    pub fn coast(&mut self, msg: &Message, state: State) -> Result<(), Failure> {
+        self.merge_dependencies(&msg.dependencies);
+        self.update(state, msg.exec_ts)
     }
 
     /// Deletes all checkpoints whose timestamp is not greater than ts
@@ -157,15 +357,17 @@ where
     /// Deletes all sent messages whose sent_ts is not greater than ts
     ///
     /// Deletes all received messages whose exec_ts is not greater than ts
-    #[allow(dead_code)]
-    pub fn free(&mut self, ts: Timestamp) {
-        while let Some(first) = self.checkpoints.front() {
-            if first.timestamp > ts {
-                break;
-            }
-            self.checkpoints.pop_front();
-        }
+    ///
+    /// The message log, if any, records what was freed (see MessageLog::free)
+    pub fn free(&mut self, ts: Timestamp) -> Result<(), Failure> {
+        self.free_except(ts, Timestamp::MAX)
+    }
+
+    // same as free, but sent messages executed at keep_from or later are not deleted
+    fn free_except(&mut self, ts: Timestamp, keep_from: Timestamp) -> Result<(), Failure> {
+        self.checkpoints.free(ts).map_err(Failure::Storage)?;
 
+        let count = self.received_messages.len() + self.sent_messages.len();
         while let Some(first) = self.received_messages.front() {
             if first.exec_ts > ts {
                 break;
@@ -173,22 +375,165 @@ where
             self.received_messages.pop_front();
         }
 
-        while let Some(first) = self.sent_messages.front() {
-            if first.sent_ts > ts {
-                break;
+        self.sent_messages = std::mem::take(&mut self.sent_messages)
+            .into_iter()
+            .filter(|msg| msg.sent_ts > ts || msg.exec_ts >= keep_from)
+            .collect();
+
+        match &self.log {
+            Some(log) if self.received_messages.len() + self.sent_messages.len() < count => log
+                .free(
+                    ts,
+                    keep_from,
+                    &self.received_messages,
+                    &self.sent_messages,
+                    &self.actions,
+                )
+                .map_err(Failure::Storage),
+            _ => Ok(()),
+        }
+    }
+
+    fn rewrite_log(&self) -> Result<(), Failure> {
+        match &self.log {
+            Some(log) => log
+                .rewrite(
+                    &self.received_messages,
+                    &self.sent_messages,
+                    &self.actions,
+                    self.executed,
+                )
+                .map_err(Failure::Storage),
+            None => Ok(()),
+        }
+    }
+
+    /// Frees everything that is no longer needed now that GVT reached gvt, i.e. everything
+    /// older than the latest checkpoint not later than gvt, which is the earliest checkpoint
+    /// a rollback can still restore
+    ///
+    /// If the simulation can be recovered, line is the timestamp of the component's checkpoint
+    /// in the latest recovery line (see recovery_line), which is never later than gvt: only
+    /// what is older than that checkpoint is freed, since the component may have to be
+    /// recovered to it. Checkpoints earlier than a recovery line can never be part of a later
+    /// one.
+    ///
+    /// Sent messages that are not executed before gvt are kept, so that they can be sent
+    /// again if the simulation is recovered (see recover).
+    pub fn fossil_collect(
+        &mut self,
+        gvt: Timestamp,
+        line: Option<Timestamp>,
+    ) -> Result<(), Failure> {
+        let oldest_needed = self
+            .checkpoints
+            .timestamps()
+            .into_iter()
+            .take_while(|ts| *ts <= gvt)
+            .last();
+        match oldest_needed.map(|ts| line.map_or(ts, |line| line.min(ts))) {
+            Some(ts) if ts > 0 => self.free_except(ts - 1, gvt),
+            _ => Ok(()),
+        }
+    }
+
+    /// Restores the component to its checkpoint in a recovery line (see recovery_line), i.e.
+    /// to the latest checkpoint c not later than line, e.g. after it was reopened or because
+    /// another component was
+    ///
+    /// gvt is the latest GVT the simulation reached, which is not earlier than the line:
+    /// nothing earlier than it can be rolled back anymore, so the components only have to
+    /// agree on the messages that cross it, and what they send earlier than it while they
+    /// catch up was sent already.
+    ///
+    /// Returns the messages that must be sent again: the received messages whose exec_ts is
+    /// in [c, gvt), which must be handled again to reach gvt, and the sent messages that
+    /// were sent before c but are executed after gvt, which their destination forgot. The
+    /// messages the component sent or received after c are removed from its history, as
+    /// well as the commit actions it emitted after c, which it emits again while it catches
+    /// up; those it emitted before c are still executed once GVT passes them.
+    pub fn recover(&mut self, line: Timestamp, gvt: Timestamp) -> Result<Vec<Message>, Failure> {
+        let checkpoint = self
+            .checkpoints
+            .restore(line)
+            .map_err(Failure::Storage)?
+            .ok_or(Failure::InsufficientCheckpoints)?;
+        self.lvt = checkpoint.timestamp;
+        self.state = checkpoint.state;
+        self.dependencies = DependencyVector::from_map(self.id, checkpoint.dependencies);
+
+        let mut to_be_sent = Vec::new();
+        let mut received = LinkedList::new();
+        for msg in std::mem::take(&mut self.received_messages) {
+            if msg.exec_ts < self.lvt {
+                received.push_back(msg);
+            } else if msg.exec_ts < gvt {
+                to_be_sent.push(msg);
+            }
+        }
+        self.received_messages = received;
+
+        let mut sent = LinkedList::new();
+        for msg in std::mem::take(&mut self.sent_messages) {
+            if msg.sent_ts < self.lvt {
+                if msg.exec_ts >= gvt {
+                    to_be_sent.push(msg.clone());
+                }
+                sent.push_back(msg);
             }
-            self.sent_messages.pop_front();
         }
+        self.sent_messages = sent;
+
+        while self
+            .actions
+            .back()
+            .is_some_and(|action| action.timestamp >= self.lvt)
+        {
+            self.actions.pop_back();
+        }
+
+        self.rewrite_log()?;
+        Ok(to_be_sent)
+    }
+
+    /// Returns what the component must be restored from to resume the simulation from at,
+    /// once GVT reached it, without changing anything: the latest checkpoint c not later than
+    /// at, and the messages that must be sent again, which are the same as those recover
+    /// returns for a line and a GVT at at
+    ///
+    /// The history must not have been freed past at, i.e. fossil_collect must not have been
+    /// called with a later GVT.
+    pub fn savepoint(&self, at: Timestamp) -> Result<(Checkpoint<State>, Vec<Message>), Failure> {
+        let checkpoint = self
+            .checkpoints
+            .latest(at)
+            .map_err(Failure::Storage)?
+            .ok_or(Failure::InsufficientCheckpoints)?;
+        let c = checkpoint.timestamp;
+        let received = self
+            .received_messages
+            .iter()
+            .filter(|msg| msg.exec_ts >= c && msg.exec_ts < at);
+        let sent = self
+            .sent_messages
+            .iter()
+            .filter(|msg| msg.sent_ts < c && msg.exec_ts >= at);
+        Ok((checkpoint, received.chain(sent).cloned().collect()))
     }
 
     /// Saves the current state and the LVT in a Checkpoint
-    #[allow(dead_code)]
-    pub fn take_checkpoint(&mut self) {
+    pub fn take_checkpoint(&mut self) -> Result<(), Failure> {
         self.lvt += 1;
-        self.checkpoints.push_back(Checkpoint {
-            state: self.state.clone(),
-            timestamp: self.lvt,
-        });
+        self.dependencies
+            .set_self_ts(self.lvt)
+            .map_err(|_| Failure::TimeViolation)?;
+        self.checkpoints
+            .push(Checkpoint {
+                state: self.state.clone(),
+                timestamp: self.lvt,
+                dependencies: self.dependencies.get_map().clone(),
+            })
+            .map_err(Failure::Storage)
     }
 
     /// This function must be called whenever the component's state changes
@@ -196,40 +541,74 @@ where
     /// Simply updates state & LVT; does not take a checkpoint
     ///
     /// Returns Err if timestamp < LVT
-    #[allow(dead_code)]
     pub fn update(&mut self, state: State, lvt: Timestamp) -> Result<(), Failure> {
         if lvt < self.lvt {
             return Err(Failure::TimeViolation);
         }
+        self.dependencies
+            .set_self_ts(lvt)
+            .map_err(|_| Failure::TimeViolation)?;
         self.state = state;
         self.lvt = lvt;
-        return Ok(());
+        Ok(())
+    }
+
+    /// Merges the dependency vector of a received message into the component's
+    ///
+    /// The component's own entry is left as it is: if the message depends on a later state of
+    /// the component, that state was rolled back and the message will be cancelled anyway.
+    pub fn merge_dependencies(&mut self, dependencies: &Dependencies) {
+        let mut dependencies = dependencies.clone();
+        dependencies.remove(&self.id);
+        self.dependencies
+            .update(&dependencies)
+            .expect("only the component's own entry can be inconsistent");
+    }
+
+    /// Whether the component sent a message since its latest checkpoint
+    pub fn sent_since_checkpoint(&self) -> bool {
+        match (self.sent_messages.back(), self.checkpoints.last_timestamp()) {
+            (Some(msg), Some(ts)) => msg.sent_ts >= ts,
+            (sent, _) => sent.is_some(),
+        }
+    }
+
+    pub fn id(&self) -> ComponentId {
+        self.id
     }
 
-    #[allow(dead_code)]
     pub fn state(&self) -> &State {
         &self.state
     }
 
-    #[allow(dead_code)]
     pub fn lvt(&self) -> Timestamp {
         self.lvt
     }
 
-    #[allow(dead_code)]
     pub fn sent_messages(&self) -> &LinkedList<Message> {
         &self.sent_messages
     }
 
-    #[allow(dead_code)]
     pub fn received_messages(&self) -> &LinkedList<Message> {
-        &self.sent_messages
+        &self.received_messages
     }
 
-    #[allow(dead_code)]
-    pub fn checkpoints(&self) -> &LinkedList<Checkpoint<State>> {
+    pub fn checkpoints(&self) -> &Store {
         &self.checkpoints
     }
+
+    pub fn actions(&self) -> &LinkedList<CommitAction> {
+        &self.actions
+    }
+
+    /// The commit actions earlier than it were executed (see mark_executed)
+    pub fn executed(&self) -> Timestamp {
+        self.executed
+    }
+
+    pub fn dependencies(&self) -> &DependencyVector {
+        &self.dependencies
+    }
 }
 
 #[cfg(test)]
@@ -245,6 +624,10 @@ mod test {
             checkpoints: LinkedList::new(),
             received_messages: LinkedList::new(),
             sent_messages: LinkedList::new(),
+            actions: LinkedList::new(),
+            executed: 0,
+            dependencies: DependencyVector::new(1, Vec::new()),
+            log: None,
         }
     }
 
@@ -255,6 +638,8 @@ mod test {
             route: String::from(""),
             exec_ts: 200,
             is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
             sent_ts: 100,
             from: 10,
             to: 100,
@@ -265,12 +650,13 @@ mod test {
     fn new_creates_and_takes_a_checkpoint() {
         let id = 4;
         let initial_state = String::from("hello");
-        let manager = RollbackManager::new(id.clone(), initial_state.clone());
+        let manager = RollbackManager::new(id, initial_state.clone());
 
         let mut checkpoints = LinkedList::new();
         checkpoints.push_back(Checkpoint {
             timestamp: 0,
             state: initial_state.clone(),
+            dependencies: DependencyVector::new(id, Vec::new()).get_map().clone(),
         });
 
         assert_eq!(
@@ -278,10 +664,14 @@ mod test {
             RollbackManager {
                 state: initial_state,
                 lvt: 0,
-                id: id.clone(),
-                checkpoints: checkpoints,
+                id,
+                checkpoints,
                 sent_messages: LinkedList::new(),
                 received_messages: LinkedList::new(),
+                actions: LinkedList::new(),
+                dependencies: DependencyVector::new(id, Vec::new()),
+                log: None,
+                executed: 0,
             }
         );
     }
@@ -290,14 +680,16 @@ mod test {
     fn takecheckpoint_increments_lvt_then_adds_a_checkpoint() {
         fn test(a: RollbackManager<i32>) {
             let mut b = a.clone();
-            b.take_checkpoint();
+            b.take_checkpoint().unwrap();
 
             let last_checkpoint = b.checkpoints.back().unwrap();
             assert_eq!(a.state, last_checkpoint.state);
             assert_eq!(a.lvt + 1, last_checkpoint.timestamp);
+            assert_eq!(last_checkpoint.dependencies[&a.id], a.lvt + 1);
 
             b.checkpoints.pop_back();
             b.lvt -= 1;
+            b.dependencies = a.dependencies.clone();
             assert_eq!(a, b);
         }
 
@@ -305,7 +697,7 @@ mod test {
 
         for _ in 0..10 {
             test(a.clone());
-            a.take_checkpoint();
+            a.take_checkpoint().unwrap();
         }
     }
 
@@ -348,10 +740,10 @@ mod test {
     fn savemessage_appends_received_message_to_correct_list() {
         let self_id = 1;
         let other_id = 2;
-        let mut manager = RollbackManager::new(self_id.clone(), 123);
+        let mut manager = RollbackManager::new(self_id, 123);
         let mut msg = get_message();
-        msg.from = other_id.clone();
-        msg.to = self_id.clone();
+        msg.from = other_id;
+        msg.to = self_id;
         let mut clone = manager.clone();
         manager.save_message(msg.clone()).unwrap();
         assert_ne!(manager, clone);
@@ -363,10 +755,10 @@ mod test {
     fn savemessage_appends_sent_message_to_correct_list() {
         let self_id = 1;
         let other_id = 2;
-        let mut manager = RollbackManager::new(self_id.clone(), 123);
+        let mut manager = RollbackManager::new(self_id, 123);
         let mut msg = get_message();
-        msg.from = self_id.clone();
-        msg.to = other_id.clone();
+        msg.from = self_id;
+        msg.to = other_id;
         let mut clone = manager.clone();
         manager.save_message(msg.clone()).unwrap();
         assert_ne!(manager, clone);
@@ -374,13 +766,28 @@ mod test {
         assert_eq!(manager, clone);
     }
 
+    #[test]
+    fn messages_sent_to_self_can_be_saved_as_sent_and_received() {
+        let self_id = 1;
+        let mut manager = RollbackManager::new(self_id, 123);
+        let mut msg = get_message();
+        msg.from = self_id;
+        msg.to = self_id;
+        let mut clone = manager.clone();
+        manager.save_sent_message(msg.clone()).unwrap();
+        manager.save_received_message(msg.clone()).unwrap();
+        clone.sent_messages.push_back(msg.clone());
+        clone.received_messages.push_back(msg);
+        assert_eq!(manager, clone);
+    }
+
     #[test]
     fn savemessage_returns_invalidmessage_if_new_message_is_neither_sent_or_received_by_self() {
         let self_id = 1;
         let other_id1 = 2;
         let other_id2 = 3;
         let mut manager = get_manager();
-        manager.id = self_id.clone();
+        manager.id = self_id;
 
         let mut msg = get_message();
         msg.from = other_id1;
@@ -398,10 +805,10 @@ mod test {
         let other_id = 2;
         let mut msg = get_message();
         msg.is_anti = true;
-        msg.from = self_id.clone();
-        msg.to = other_id.clone();
+        msg.from = self_id;
+        msg.to = other_id;
         let mut manager = get_manager();
-        manager.id = self_id.clone();
+        manager.id = self_id;
         match manager.save_message(msg) {
             Err(Failure::InvalidMessage) => (),
             _ => panic!(),
@@ -412,7 +819,7 @@ mod test {
     fn savemessage_returns_timeviolation_if_new_sent_message_breaks_order() {
         let self_id = 1;
         let other_id = 2;
-        let mut manager = RollbackManager::new(self_id.clone(), 123);
+        let mut manager = RollbackManager::new(self_id, 123);
         let mut msg1 = get_message();
         msg1.from = self_id;
         msg1.to = other_id;
@@ -434,7 +841,7 @@ mod test {
     fn savemessage_return_timeviolation_if_new_received_message_breaks_order() {
         let self_id = 1;
         let other_id = 2;
-        let mut manager = RollbackManager::new(self_id.clone(), 123);
+        let mut manager = RollbackManager::new(self_id, 123);
         let mut msg1 = get_message();
         msg1.from = other_id;
         msg1.to = self_id;
@@ -455,7 +862,7 @@ mod test {
     fn free_removes_correct_sent_messages() {
         let self_id = 1;
         let other_id = 2;
-        let mut manager = RollbackManager::new(self_id.clone(), 123);
+        let mut manager = RollbackManager::new(self_id, 123);
         let mut msg1 = get_message();
         msg1.from = self_id;
         msg1.to = other_id;
@@ -476,7 +883,7 @@ mod test {
 
         let mut clone = manager.clone();
 
-        manager.free(20);
+        manager.free(20).unwrap();
         assert_ne!(manager, clone);
         clone.sent_messages.pop_front();
         clone.sent_messages.pop_front();
@@ -488,7 +895,7 @@ mod test {
     fn free_removes_correct_received_messages() {
         let self_id = 1;
         let other_id = 2;
-        let mut manager = RollbackManager::new(self_id.clone(), 123);
+        let mut manager = RollbackManager::new(self_id, 123);
         let mut msg1 = get_message();
         msg1.from = other_id;
         msg1.to = self_id;
@@ -509,7 +916,7 @@ mod test {
 
         let mut clone = manager.clone();
 
-        manager.free(20);
+        manager.free(20).unwrap();
         assert_ne!(manager, clone);
         clone.sent_messages.pop_front();
         clone.sent_messages.pop_front();
@@ -521,16 +928,16 @@ mod test {
     fn free_removes_correct_checkpoints() {
         let mut manager = RollbackManager::new(1, 123);
         manager.update(11, 10).unwrap();
-        manager.take_checkpoint();
+        manager.take_checkpoint().unwrap();
         manager.update(22, 20).unwrap();
-        manager.take_checkpoint();
+        manager.take_checkpoint().unwrap();
         manager.update(33, 30).unwrap();
-        manager.take_checkpoint();
+        manager.take_checkpoint().unwrap();
 
         println!("manager before {:#?}", manager);
 
         let mut clone = manager.clone();
-        manager.free(21);
+        manager.free(21).unwrap();
 
         println!("manager after {:#?}", manager);
         assert_ne!(manager, clone);
@@ -540,6 +947,163 @@ mod test {
         assert_eq!(manager, clone);
     }
 
+    #[test]
+    fn fossilcollect_keeps_what_a_rollback_to_gvt_needs() {
+        let mut manager = RollbackManager::new(1, 0);
+        for lvt in [10, 20, 30] {
+            let mut msg = get_message();
+            msg.to = 1;
+            msg.exec_ts = lvt;
+            manager.save_message(msg).unwrap();
+            manager.update(lvt as i32, lvt).unwrap();
+            manager.take_checkpoint().unwrap();
+        }
+
+        manager.fossil_collect(25, None).unwrap();
+        assert_eq!(manager.checkpoints().timestamps(), vec![21, 31]);
+        let exec_ts: Vec<_> = manager
+            .received_messages()
+            .iter()
+            .map(|m| m.exec_ts)
+            .collect();
+        assert_eq!(exec_ts, vec![30]);
+
+        manager.rollback(25).unwrap();
+        assert_eq!((manager.lvt(), *manager.state()), (21, 20));
+    }
+
+    #[test]
+    fn fossilcollect_keeps_sent_messages_executed_after_gvt() {
+        let mut manager = RollbackManager::new(1, 0);
+        for (sent_ts, exec_ts) in [(10, 15), (10, 40)] {
+            let mut msg = get_message();
+            msg.from = 1;
+            msg.sent_ts = sent_ts;
+            msg.exec_ts = exec_ts;
+            manager.save_message(msg).unwrap();
+        }
+        manager.update(20, 20).unwrap();
+        manager.take_checkpoint().unwrap();
+
+        manager.fossil_collect(25, None).unwrap();
+        let exec_ts: Vec<_> = manager.sent_messages().iter().map(|m| m.exec_ts).collect();
+        assert_eq!(exec_ts, vec![40]);
+    }
+
+    #[test]
+    fn reopen_and_recover_restore_the_persisted_history_up_to_the_line() {
+        use crate::checkpoint_store::FileStore;
+        use crate::message_log::SyncPolicy;
+
+        let dir = std::env::temp_dir().join("dcb_rollback_manager_recover_test");
+        let log_path = dir.join("messages.log");
+        let store = FileStore::create(dir.join("checkpoints"), 1).unwrap();
+        let mut manager = RollbackManager::with_store(1, 0, store).unwrap();
+        manager
+            .set_log(MessageLog::open(&log_path, SyncPolicy::Flush).unwrap())
+            .unwrap();
+        for (lvt, exec_ts) in [(10, 50), (20, 25), (30, 35)] {
+            let mut received = get_message();
+            received.to = 1;
+            received.exec_ts = lvt;
+            manager.save_message(received).unwrap();
+            manager.update(lvt as i32, lvt).unwrap();
+            let mut sent = get_message();
+            sent.from = 1;
+            sent.sent_ts = lvt;
+            sent.exec_ts = exec_ts;
+            manager.save_message(sent).unwrap();
+            if lvt < 30 {
+                manager.take_checkpoint().unwrap();
+            }
+        }
+        drop(manager);
+
+        let store = FileStore::open(dir.join("checkpoints"), 1).unwrap();
+        let log = MessageLog::open(&log_path, SyncPolicy::Flush).unwrap();
+        let mut manager = RollbackManager::<i32, _>::reopen(1, store, log).unwrap();
+        assert_eq!((manager.lvt(), *manager.state()), (21, 20));
+
+        let to_be_sent = manager.recover(35, 35).unwrap();
+        let resent: Vec<_> = to_be_sent.iter().map(|m| (m.from, m.exec_ts)).collect();
+        assert_eq!(resent, vec![(10, 30), (1, 50)]);
+        assert_eq!((manager.lvt(), *manager.state()), (21, 20));
+        let exec_ts: Vec<_> = manager
+            .received_messages()
+            .iter()
+            .map(|m| m.exec_ts)
+            .collect();
+        assert_eq!(exec_ts, vec![10, 20]);
+        let exec_ts: Vec<_> = manager.sent_messages().iter().map(|m| m.exec_ts).collect();
+        assert_eq!(exec_ts, vec![50, 25]);
+
+        let history = MessageLog::history(&log_path).unwrap();
+        assert_eq!(&history.received, manager.received_messages());
+        assert_eq!(&history.sent, manager.sent_messages());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn savepoint_returns_what_recover_would_without_changing_anything() {
+        let mut manager = RollbackManager::new(1, 0);
+        for (lvt, exec_ts) in [(10, 50), (20, 25), (30, 35)] {
+            let mut received = get_message();
+            received.to = 1;
+            received.exec_ts = lvt;
+            manager.save_message(received).unwrap();
+            manager.update(lvt as i32, lvt).unwrap();
+            let mut sent = get_message();
+            sent.from = 1;
+            sent.sent_ts = lvt;
+            sent.exec_ts = exec_ts;
+            manager.save_message(sent).unwrap();
+            manager.take_checkpoint().unwrap();
+        }
+        let original = manager.clone();
+
+        let (checkpoint, pending) = manager.savepoint(35).unwrap();
+        assert_eq!(manager, original);
+        assert_eq!((checkpoint.timestamp, checkpoint.state), (31, 30));
+        let expected: Vec<_> = manager.clone().recover(35, 35).unwrap();
+        assert_eq!(pending, expected);
+
+        let (checkpoint, pending) = manager.savepoint(30).unwrap();
+        assert_eq!((checkpoint.timestamp, checkpoint.state), (21, 20));
+        let pending: Vec<_> = pending.iter().map(|m| (m.from, m.exec_ts)).collect();
+        assert_eq!(pending, vec![(1, 50)]);
+    }
+
+    #[test]
+    fn serialized_managers_resume_where_they_left_off() {
+        let mut manager = RollbackManager::new(1, 0);
+        let mut dependencies = Dependencies::new();
+        dependencies.insert(2, 5);
+        manager.merge_dependencies(&dependencies);
+        for lvt in [10, 20, 30] {
+            let mut received = get_message();
+            received.to = 1;
+            received.exec_ts = lvt;
+            manager.save_message(received).unwrap();
+            manager.update(lvt as i32, lvt).unwrap();
+            let mut sent = get_message();
+            sent.from = 1;
+            sent.sent_ts = lvt;
+            sent.exec_ts = lvt + 5;
+            manager.save_message(sent).unwrap();
+            manager.take_checkpoint().unwrap();
+        }
+        manager.save_action(get_action(31)).unwrap();
+
+        let json = serde_json::to_string(&manager).unwrap();
+        let mut deserialized: RollbackManager<i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, manager);
+
+        let to_be_sent = deserialized.rollback(25).unwrap();
+        assert_eq!(to_be_sent, manager.rollback(25).unwrap());
+        assert_eq!((deserialized.lvt(), *deserialized.state()), (21, 20));
+        assert_eq!(deserialized, manager);
+    }
+
     /// The checkpoints are insufficient when there is no checkpoint whose timestamp is less than
     /// or equal to the timestamp of the rollback.
     #[test]
@@ -570,6 +1134,7 @@ mod test {
         manager.checkpoints.push_back(Checkpoint {
             state: 123,
             timestamp: 5,
+            dependencies: Dependencies::new(),
         });
         let clone = manager.clone();
         match manager.rollback(20) {
@@ -588,11 +1153,13 @@ mod test {
             payload: String::default(),
             route: String::default(),
             exec_ts: 10,
-            from: other_id.clone(),
-            to: self_id.clone(),
+            from: other_id,
+            to: self_id,
             sent_ts: 1,
             id: 123,
             is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
         };
         let mut rec2 = rec1.clone();
         rec2.exec_ts = 20;
@@ -603,18 +1170,20 @@ mod test {
             payload: String::default(),
             route: String::default(),
             exec_ts: 1000,
-            from: self_id.clone(),
-            to: other_id.clone(),
+            from: self_id,
+            to: other_id,
             sent_ts: 10,
             id: 321,
             is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
         };
         let mut sent2 = sent1.clone();
         sent2.sent_ts = 20;
         let mut sent3 = sent1.clone();
         sent3.sent_ts = 30;
 
-        let mut manager = RollbackManager::new(self_id.clone(), 123);
+        let mut manager = RollbackManager::new(self_id, 123);
         manager.save_message(rec1.clone()).unwrap();
         manager.save_message(rec2.clone()).unwrap();
         manager.save_message(rec3.clone()).unwrap();
@@ -623,13 +1192,13 @@ mod test {
         manager.save_message(sent3.clone()).unwrap();
 
         manager.update(222, 9).unwrap();
-        manager.take_checkpoint();
+        manager.take_checkpoint().unwrap();
         manager.update(999, 19).unwrap();
-        manager.take_checkpoint();
+        manager.take_checkpoint().unwrap();
         manager.update(777, 49).unwrap();
-        manager.take_checkpoint();
+        manager.take_checkpoint().unwrap();
         manager.update(888, 200).unwrap();
-        manager.take_checkpoint();
+        manager.take_checkpoint().unwrap();
 
         let mut clone: RollbackManager<i32> = manager.clone();
 
@@ -645,6 +1214,8 @@ mod test {
         clone.sent_messages.pop_back();
         clone.received_messages.pop_back();
         clone.received_messages.pop_back();
+        clone.dependencies = DependencyVector::new(self_id, Vec::new());
+        clone.dependencies.set_self_ts(20).unwrap();
         assert_eq!(manager, clone);
 
         let mut expected: HashSet<Message> = HashSet::new();
@@ -660,6 +1231,166 @@ mod test {
         println!("result {:#?}", result);
         println!("expected {:#?}", expected);
 
-        assert_eq!(result, expected);
+        assert_eq!(result, (expected, Vec::new()));
+    }
+
+    /// Messages handled between the restored checkpoint and the timestamp of the rollback are
+    /// kept, and must be coasted through instead of being handled again
+    #[test]
+    fn rollback_keeps_what_happened_between_the_restored_checkpoint_and_ts() {
+        let self_id = 1;
+        let other_id = 2;
+        let mut rec1 = get_message();
+        rec1.from = other_id;
+        rec1.to = self_id;
+        rec1.exec_ts = 12;
+        let mut rec2 = rec1.clone();
+        rec2.exec_ts = 15;
+        let mut sent1 = get_message();
+        sent1.from = self_id;
+        sent1.to = other_id;
+        sent1.sent_ts = 12;
+
+        let mut manager = RollbackManager::new(self_id, 0);
+        manager.update(1, 10).unwrap();
+        manager.take_checkpoint().unwrap();
+        manager.save_message(rec1.clone()).unwrap();
+        manager.update(2, 12).unwrap();
+        manager.save_message(sent1.clone()).unwrap();
+        manager.save_message(rec2.clone()).unwrap();
+        manager.update(3, 15).unwrap();
+
+        let (to_be_sent, to_coast_through) = manager.rollback(14).unwrap();
+        assert_eq!(manager.lvt, 11);
+        assert_eq!(manager.state, 1);
+        assert_eq!(to_be_sent, vec![rec2].into_iter().collect());
+        assert_eq!(to_coast_through, vec![rec1.clone()]);
+        assert_eq!(
+            manager.received_messages,
+            vec![rec1.clone()].into_iter().collect()
+        );
+        assert_eq!(manager.sent_messages, vec![sent1].into_iter().collect());
+
+        manager.coast(&rec1, 2).unwrap();
+        assert_eq!((manager.lvt, manager.state), (12, 2));
+    }
+
+    fn get_action(timestamp: Timestamp) -> CommitAction {
+        CommitAction {
+            timestamp,
+            payload: timestamp.to_string(),
+        }
+    }
+
+    #[test]
+    fn saveaction_returns_timeviolation_if_new_action_breaks_order() {
+        let mut manager = get_manager();
+        manager.save_action(get_action(10)).unwrap();
+        manager.save_action(get_action(10)).unwrap();
+        match manager.save_action(get_action(5)) {
+            Err(Failure::TimeViolation) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn commit_returns_actions_older_than_gvt_in_order() {
+        let mut manager = get_manager();
+        manager.save_action(get_action(10)).unwrap();
+        manager.save_action(get_action(20)).unwrap();
+        manager.save_action(get_action(30)).unwrap();
+
+        assert_eq!(manager.commit(10), vec![]);
+        assert_eq!(manager.commit(21), vec![get_action(10), get_action(20)]);
+        assert_eq!(manager.commit(21), vec![]);
+
+        let mut expected = LinkedList::new();
+        expected.push_back(get_action(30));
+        assert_eq!(manager.actions, expected);
+    }
+
+    #[test]
+    fn reopen_keeps_the_actions_that_were_not_executed() {
+        use crate::checkpoint_store::FileStore;
+        use crate::message_log::SyncPolicy;
+
+        let dir = std::env::temp_dir().join("dcb_rollback_manager_actions_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let log_path = dir.join("messages.log");
+        let store = FileStore::create(dir.join("checkpoints"), 1).unwrap();
+        let mut manager = RollbackManager::with_store(1, 0, store).unwrap();
+        manager
+            .set_log(MessageLog::open(&log_path, SyncPolicy::Flush).unwrap())
+            .unwrap();
+        for lvt in 1..=5 {
+            let mut received = get_message();
+            received.to = 1;
+            received.exec_ts = lvt;
+            manager.save_message(received).unwrap();
+            manager.update(lvt as i32, lvt).unwrap();
+        }
+        manager.take_checkpoint().unwrap();
+        for ts in [10, 20, 30] {
+            manager.save_action(get_action(ts)).unwrap();
+        }
+        assert_eq!(manager.commit(21), vec![get_action(10), get_action(20)]);
+        manager.mark_executed(21).unwrap();
+
+        // the freed messages outgrow the rest of the log, which is compacted
+        manager.free(5).unwrap();
+        assert_eq!(
+            MessageLog::read(&log_path).unwrap()[0],
+            LogEntry::Executed(21)
+        );
+        drop(manager);
+
+        let store = FileStore::open(dir.join("checkpoints"), 1).unwrap();
+        let log = MessageLog::open(&log_path, SyncPolicy::Flush).unwrap();
+        let manager = RollbackManager::<i32, _>::reopen(1, store, log).unwrap();
+        assert_eq!(manager.executed(), 21);
+        let mut expected = LinkedList::new();
+        expected.push_back(get_action(30));
+        assert_eq!(manager.actions(), &expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rollback_discards_rolled_back_actions() {
+        let mut manager = RollbackManager::new(1, 123);
+        manager.update(11, 10).unwrap();
+        manager.take_checkpoint().unwrap();
+        manager.save_action(get_action(10)).unwrap();
+        manager.update(22, 20).unwrap();
+        manager.save_action(get_action(20)).unwrap();
+        manager.update(33, 30).unwrap();
+        manager.save_action(get_action(30)).unwrap();
+
+        manager.rollback(20).unwrap();
+
+        let mut expected = LinkedList::new();
+        expected.push_back(get_action(10));
+        assert_eq!(manager.actions, expected);
+    }
+
+    #[test]
+    fn rollback_restores_the_dependencies_of_the_checkpoint() {
+        let mut manager = RollbackManager::new(1, 0);
+        let mut dependencies = Dependencies::new();
+        dependencies.insert(2, 5);
+        manager.merge_dependencies(&dependencies);
+        manager.update(1, 10).unwrap();
+        manager.take_checkpoint().unwrap();
+
+        dependencies.insert(1, 30);
+        dependencies.insert(2, 15);
+        dependencies.insert(3, 25);
+        manager.merge_dependencies(&dependencies);
+        manager.update(2, 20).unwrap();
+        let map = manager.dependencies().get_map().clone();
+        assert_eq!(map, vec![(1, 20), (2, 15), (3, 25)].into_iter().collect());
+
+        manager.rollback(15).unwrap();
+        let map = manager.dependencies().get_map().clone();
+        assert_eq!(map, vec![(1, 11), (2, 5)].into_iter().collect());
     }
 }
@@ -0,0 +1,196 @@
+//! Route patterns: the route names of a route table may contain `*` wildcards, each matching
+//! any sequence of characters (possibly empty, possibly containing `/`), e.g. `sensor/*` or
+//! `*/position`.
+//!
+//! The text matched by the n-th wildcard can be inserted in the route of a destination as
+//! `$n`, e.g. `sensor/*` routed to `reading_$1` turns `sensor/7` into `reading_7`.
+//!
+//! When several routes match the same name, the one without wildcards wins, then the one with
+//! the most characters outside wildcards, i.e. the most specific one. Patterns that can match
+//! the same name with the same specificity are ambiguous and rejected by config validation;
+//! if such a table is built anyway, the lowest pattern in lexicographic order wins.
+
+use std::cmp::{Ordering, Reverse};
+
+pub const WILDCARD: char = '*';
+
+pub fn is_pattern(route: &str) -> bool {
+    route.contains(WILDCARD)
+}
+
+/// Returns the text matched by every wildcard of the pattern, or None if the route does not
+/// match it
+///
+/// Wildcards match as few characters as possible, from left to right.
+pub fn captures<'a>(pattern: &str, route: &'a str) -> Option<Vec<&'a str>> {
+    let mut spans = Vec::new();
+    if match_from(pattern.as_bytes(), route.as_bytes(), 0, &mut spans) {
+        Some(spans.into_iter().map(|(a, b)| &route[a..b]).collect())
+    } else {
+        None
+    }
+}
+
+fn match_from(
+    pattern: &[u8],
+    route: &[u8],
+    offset: usize,
+    spans: &mut Vec<(usize, usize)>,
+) -> bool {
+    match pattern.split_first() {
+        None => route.is_empty(),
+        Some((b'*', rest)) => {
+            for len in 0..=route.len() {
+                // captures never split a character
+                if route.get(len).is_some_and(|b| b & 0xC0 == 0x80) {
+                    continue;
+                }
+                spans.push((offset, offset + len));
+                if match_from(rest, &route[len..], offset + len, spans) {
+                    return true;
+                }
+                spans.pop();
+            }
+            false
+        }
+        Some((c, rest)) => {
+            route.first() == Some(c) && match_from(rest, &route[1..], offset + 1, spans)
+        }
+    }
+}
+
+/// Orders the routes of a table by precedence: the route that should be used first when
+/// several match the same name is the lowest
+pub fn precedence(a: &str, b: &str) -> Ordering {
+    let key = |p: &str| {
+        (
+            is_pattern(p),
+            Reverse(p.len() - p.matches(WILDCARD).count()),
+        )
+    };
+    key(a).cmp(&key(b)).then_with(|| a.cmp(b))
+}
+
+/// Whether neither pattern takes precedence over the other for some route name both match
+pub fn ambiguous(a: &str, b: &str) -> bool {
+    let literals = |p: &str| p.len() - p.matches(WILDCARD).count();
+    a != b
+        && is_pattern(a)
+        && is_pattern(b)
+        && literals(a) == literals(b)
+        && overlap(a.as_bytes(), b.as_bytes())
+}
+
+/// Whether some route name matches both patterns
+fn overlap(a: &[u8], b: &[u8]) -> bool {
+    // explores the pairs of positions in a and b that can be reached by generating the same
+    // name from both patterns
+    let mut seen = vec![vec![false; b.len() + 1]; a.len() + 1];
+    let mut stack = vec![(0, 0)];
+    while let Some((i, j)) = stack.pop() {
+        if seen[i][j] {
+            continue;
+        }
+        seen[i][j] = true;
+        if i == a.len() && j == b.len() {
+            return true;
+        }
+        let (x, y) = (a.get(i), b.get(j));
+        if x == Some(&b'*') {
+            // the wildcard of a stops, or it matches the next character of b
+            stack.push((i + 1, j));
+            if y.is_some() {
+                stack.push((i, j + 1));
+            }
+        }
+        if y == Some(&b'*') {
+            stack.push((i, j + 1));
+            if x.is_some() {
+                stack.push((i + 1, j));
+            }
+        }
+        if x.is_some() && x == y {
+            stack.push((i + 1, j + 1));
+        }
+    }
+    false
+}
+
+/// Replaces every `$n` of a destination route by the n-th capture, counting from 1
+///
+/// A `$` that is not followed by the number of a capture is kept as it is.
+pub fn expand(route: &str, captures: &[&str]) -> String {
+    let mut expanded = String::with_capacity(route.len());
+    let mut rest = route;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let digits = rest[start + 1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest.len(), |end| start + 1 + end);
+        match rest[start + 1..digits].parse::<usize>() {
+            Ok(n) if n >= 1 && n <= captures.len() => expanded.push_str(captures[n - 1]),
+            _ => expanded.push_str(&rest[start..digits]),
+        }
+        rest = &rest[digits..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Highest capture a destination route refers to, 0 if none
+pub fn max_capture(route: &str) -> usize {
+    route
+        .split('$')
+        .skip(1)
+        .filter_map(|s| {
+            let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            s[..end].parse().ok()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Number of captures a route provides
+pub fn capture_count(route: &str) -> usize {
+    route.matches(WILDCARD).count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn captures_are_substituted_in_destination_routes() {
+        assert_eq!(captures("sensor/*", "sensor/7"), Some(vec!["7"]));
+        assert_eq!(captures("sensor/*", "sensor/a/b"), Some(vec!["a/b"]));
+        assert_eq!(captures("*/*", "a/b/c"), Some(vec!["a", "b/c"]));
+        assert_eq!(captures("sensor/*", "actuator/7"), None);
+        assert_eq!(captures("out", "out"), Some(vec![]));
+
+        assert_eq!(expand("reading_$1", &["7"]), "reading_7");
+        assert_eq!(expand("$2/$1", &["a", "b"]), "b/a");
+        assert_eq!(expand("$3 and $", &["a"]), "$3 and $");
+        assert_eq!(max_capture("$2/$1"), 2);
+        assert_eq!(max_capture("in"), 0);
+    }
+
+    #[test]
+    fn the_most_specific_route_comes_first() {
+        let mut routes = vec!["*", "sensor/*", "sensor/7", "*/7", "sensor/*/raw"];
+        routes.sort_by(|a, b| precedence(a, b));
+        assert_eq!(
+            routes,
+            vec!["sensor/7", "sensor/*/raw", "sensor/*", "*/7", "*"]
+        );
+    }
+
+    #[test]
+    fn patterns_are_ambiguous_when_they_overlap_with_the_same_specificity() {
+        assert!(ambiguous("a*", "*a"));
+        assert!(ambiguous("sensor/*", "*/sensor"));
+        assert!(!ambiguous("a*", "b*"));
+        assert!(!ambiguous("sensor/*", "sensor/7"));
+        assert!(!ambiguous("ab*", "*c"));
+        assert!(!ambiguous("a*b", "a*b"));
+    }
+}
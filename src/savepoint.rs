@@ -0,0 +1,148 @@
+//! Savepoints: the committed state of the whole federation at a given time, from which new
+//! runs can be branched, e.g. to compare what-if scenarios.
+//!
+//! A savepoint at T is requested from the coordinator (see request), which waits for GVT to
+//! reach T. In the GVT round that reaches it, before any node learns the new GVT and frees
+//! its history past T, every node saves each of its components (see Runnable::save) to
+//! `<id>.json` in the savepoint's directory. Every node writes to the same path, so the
+//! directory must be shared to hold the whole savepoint.
+//!
+//! A federation is booted from a savepoint by building its components with
+//! Registry::build_restored. Every component then resumes from its latest checkpoint not
+//! later than T, handles again what it handled between that checkpoint and T, and only sends
+//! the messages and executes the commit actions that are not earlier than T, like a recovered
+//! component (see Runnable::recover). Components must therefore be deterministic.
+//!
+//! Only the types registered with Registry::register_persistent can be saved.
+
+use crate::models::{Checkpoint, ComponentId, Control, Message, Packet, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// how often request checks that the coordinator is still running while it waits for its reply
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What a savepoint holds of a component
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedComponent {
+    pub id: ComponentId,
+
+    /// time of the savepoint
+    pub at: Timestamp,
+
+    /// latest checkpoint of the component not later than at
+    pub checkpoint: Checkpoint<serde_json::Value>,
+
+    /// messages sent again once the component is restored: those it handled between its
+    /// checkpoint and at, and those it sent before its checkpoint to be handled after at (see
+    /// RollbackManager::savepoint)
+    pub pending: Vec<Message>,
+}
+
+/// Asks the coordinator of a running federation for a savepoint at `at` into dir, and waits
+/// until every node saved its components
+///
+/// Fails if the coordinator cannot be reached, if GVT already passed `at`, if the simulation
+/// ends before GVT reaches it, or if a node cannot save its components. Also fails if the
+/// coordinator stops listening without replying, e.g. because the request reached it after
+/// the simulation was over.
+pub fn request(coordinator: &str, at: Timestamp, dir: impl Into<PathBuf>) -> io::Result<()> {
+    let mut stream = TcpStream::connect(coordinator)?;
+    // the coordinator replies to the address it was reached from
+    let listener = TcpListener::bind((stream.local_addr()?.ip(), 0))?;
+    let packet = Packet::Control {
+        from: String::new(),
+        to: String::from(coordinator),
+        control: Control::RequestSave {
+            at,
+            dir: dir.into(),
+            reply_to: listener.local_addr()?.to_string(),
+        },
+    };
+    stream.write_all(serde_json::to_string(&packet)?.as_bytes())?;
+    drop(stream);
+
+    // waiting for GVT to reach `at` may take arbitrarily long, so the coordinator is checked
+    // on instead; once it stops listening, its reply is either received already or never sent
+    listener.set_nonblocking(true)?;
+    let mut stopped = false;
+    let mut stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock && !stopped => (),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                return Err(io::Error::new(
+                    ErrorKind::ConnectionAborted,
+                    "the coordinator stopped without replying",
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+        thread::sleep(PROBE_INTERVAL);
+        stopped = TcpStream::connect(coordinator).is_err();
+    };
+    stream.set_nonblocking(false)?;
+    let mut buffer = Vec::new();
+    stream.read_to_end(&mut buffer)?;
+    match serde_json::from_slice(&buffer)? {
+        Packet::Control {
+            control: Control::Saved { error: None, .. },
+            ..
+        } => Ok(()),
+        Packet::Control {
+            control: Control::Saved {
+                error: Some(error), ..
+            },
+            ..
+        } => Err(io::Error::other(error)),
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "unexpected reply from the coordinator",
+        )),
+    }
+}
+
+/// Writes every saved component to its own file in dir, creating dir if needed
+pub fn save(dir: &Path, saved: &[SavedComponent]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for component in saved {
+        let path = path(dir, component.id);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(component)?)?;
+        fs::rename(tmp, path)?;
+    }
+    Ok(())
+}
+
+/// Reads back what the savepoint in dir holds of the given component
+pub fn load(dir: &Path, id: ComponentId) -> io::Result<SavedComponent> {
+    Ok(serde_json::from_slice(&fs::read(path(dir, id))?)?)
+}
+
+fn path(dir: &Path, id: ComponentId) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_fails_once_the_coordinator_stops_without_replying() {
+        let address = "127.0.0.1:28410";
+        let listener = TcpListener::bind(address).unwrap();
+        let coordinator = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer).unwrap();
+        });
+        let error = request(address, 10, "unused").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+        coordinator.join().unwrap();
+    }
+}
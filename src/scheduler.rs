@@ -0,0 +1,593 @@
+use crate::component_manager::Runnable;
+use crate::messenger::Messenger;
+use crate::models::{ComponentId, Message, Timestamp};
+use crate::msg_queue::MsgQueueBase;
+use crate::recovery_line::{History, RecoveryLine};
+use crate::savepoint::SavedComponent;
+use crate::snapshot::ComponentSnapshot;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::io;
+use std::sync::{Condvar, Mutex};
+
+/// Multiplexes the local components over a pool of workers
+///
+/// Every component has its own queue. Workers always pick the component whose next message
+/// has the lowest exec_ts, which reduces rollbacks, and a component is never handed to more
+/// than one worker at a time, so each component's processing stays single-threaded.
+///
+/// Messages are only handed to workers if their exec_ts is lower than the window, which
+/// stays at Timestamp::MAX unless nodes synchronize conservatively.
+pub struct Scheduler {
+    ids: HashSet<ComponentId>,
+
+    // whether the checkpoints of the components are kept up to date, see with_history
+    track_history: bool,
+
+    inner: Mutex<Inner>,
+    cvar: Condvar,
+}
+
+/// Work handed to a worker; the component must be given back through Scheduler::done
+pub struct Task {
+    pub component: Box<dyn Runnable>,
+
+    /// None when the component only has to commit
+    pub msg: Option<Message>,
+}
+
+struct Slot {
+    queue: MsgQueueBase,
+
+    // None while the component is handed to a worker
+    component: Option<Box<dyn Runnable>>,
+
+    // exec_ts of the message being handled by a worker
+    processing: Option<Timestamp>,
+
+    // checkpoints of the component as of when it was last given back, None if it cannot be
+    // recovered or if they are not tracked; read while it is handed to a worker, since it may
+    // be busy for a long time
+    history: Option<History>,
+}
+
+struct Inner {
+    slots: HashMap<ComponentId, Slot>,
+
+    // idle components with pending messages, by the exec_ts of their next message;
+    // entries are not removed when they become outdated, they are skipped instead
+    ready: BinaryHeap<Reverse<(Timestamp, ComponentId)>>,
+
+    // components that must commit because GVT advanced
+    commits: VecDeque<ComponentId>,
+
+    window: Timestamp,
+    closed: bool,
+
+    // no task is handed out while a snapshot is taken
+    paused: bool,
+
+    // nor while the federation is being recovered
+    halted: bool,
+}
+
+impl Inner {
+    fn schedule(&mut self, id: ComponentId) {
+        let slot = &self.slots[&id];
+        if slot.component.is_some() {
+            if let Some(next) = slot.queue.peek() {
+                self.ready.push(Reverse((next.exec_ts, id)));
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<Task> {
+        if self.paused || self.halted {
+            return None;
+        }
+
+        while let Some(id) = self.commits.pop_front() {
+            if let Some(component) = self.slots.get_mut(&id).unwrap().component.take() {
+                return Some(Task {
+                    component,
+                    msg: None,
+                });
+            }
+        }
+
+        while let Some(Reverse((ts, id))) = self.ready.pop() {
+            let slot = self.slots.get_mut(&id).unwrap();
+            let is_current = slot.component.is_some()
+                && slot.queue.peek().is_some_and(|next| next.exec_ts == ts);
+            if !is_current {
+                continue;
+            }
+            if ts >= self.window {
+                // every other entry is later anyway
+                self.ready.push(Reverse((ts, id)));
+                break;
+            }
+            let msg = slot.queue.pop().unwrap();
+            slot.processing = Some(msg.exec_ts);
+            return Some(Task {
+                component: slot.component.take().unwrap(),
+                msg: Some(msg),
+            });
+        }
+
+        None
+    }
+}
+
+impl Scheduler {
+    pub fn new(components: Vec<Box<dyn Runnable>>, window: Timestamp) -> Scheduler {
+        let ids = components.iter().map(|c| c.id()).collect();
+        let slots = components
+            .into_iter()
+            .map(|component| {
+                let slot = Slot {
+                    queue: MsgQueueBase::new(),
+                    history: None,
+                    component: Some(component),
+                    processing: None,
+                };
+                (slot.component.as_ref().unwrap().id(), slot)
+            })
+            .collect();
+        Scheduler {
+            ids,
+            track_history: false,
+            inner: Mutex::new(Inner {
+                slots,
+                ready: BinaryHeap::new(),
+                commits: VecDeque::new(),
+                window,
+                closed: false,
+                paused: false,
+                halted: false,
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+
+    /// Keeps track of the checkpoints of every component, so that recovery lines can be
+    /// computed (see checkpoints); only needed if the node persists its state
+    pub fn with_history(mut self) -> Scheduler {
+        for slot in self.inner.get_mut().unwrap().slots.values_mut() {
+            slot.history = slot.component.as_ref().unwrap().checkpoints().ok();
+        }
+        self.track_history = true;
+        self
+    }
+
+    /// Starts every component; must be called before any worker runs
+    pub fn start(&self, messenger: &Messenger) {
+        let components: Vec<Box<dyn Runnable>> = {
+            let mut inner = self.inner.lock().unwrap();
+            inner
+                .slots
+                .values_mut()
+                .map(|slot| slot.component.take().unwrap())
+                .collect()
+        };
+        for mut component in components {
+            component.start(messenger);
+            self.done(component);
+        }
+    }
+
+    /// Stops handing out tasks until the components are recovered, once every component was
+    /// given back
+    pub fn halt(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.halted = true;
+        while inner.slots.values().any(|slot| slot.component.is_none()) {
+            inner = self.cvar.wait(inner).unwrap();
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.inner.lock().unwrap().halted
+    }
+
+    /// Recovers every component to its checkpoint in the given line (see Runnable::recover),
+    /// then hands out tasks again; the scheduler must be halted
+    ///
+    /// The messages queued before the recovery are dropped (see Gvt::is_stale), since they
+    /// were sent again if they are still needed.
+    pub fn recover(
+        &self,
+        line: &RecoveryLine,
+        gvt: Timestamp,
+        messenger: &Messenger,
+    ) -> io::Result<()> {
+        let components: Vec<Box<dyn Runnable>> = {
+            let mut inner = self.inner.lock().unwrap();
+            inner
+                .slots
+                .values_mut()
+                .map(|slot| {
+                    slot.queue.retain(|msg| !messenger.gvt.is_stale(msg.epoch));
+                    slot.component.take().unwrap()
+                })
+                .collect()
+        };
+        let mut result = Ok(());
+        for mut component in components {
+            if result.is_ok() {
+                result = match line.get(&component.id()) {
+                    Some(ts) => component.recover(*ts, gvt, messenger),
+                    None => Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("component {} is not part of the line", component.id()),
+                    )),
+                };
+            }
+            self.done(component);
+        }
+        self.inner.lock().unwrap().halted = false;
+        self.cvar.notify_all();
+        result
+    }
+
+    pub fn contains(&self, id: ComponentId) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// Pushes a message into its destination's queue
+    ///
+    /// Returns the message back if its destination is not a local component; messages pushed
+    /// after the scheduler was closed are dropped
+    pub fn push(&self, msg: Message) -> Result<(), Message> {
+        if !self.contains(msg.to) {
+            return Err(msg);
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return Ok(());
+        }
+        let id = msg.to;
+        inner.slots.get_mut(&id).unwrap().queue.push(msg);
+        inner.schedule(id);
+        self.cvar.notify_one();
+        Ok(())
+    }
+
+    /// Blocks until there is work to do
+    ///
+    /// Returns None once the scheduler is closed
+    pub fn next(&self) -> Option<Task> {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if inner.closed {
+                return None;
+            }
+            if let Some(task) = inner.next() {
+                return Some(task);
+            }
+            inner = self.cvar.wait(inner).unwrap();
+        }
+    }
+
+    /// Gives back a component taken through next
+    pub fn done(&self, mut component: Box<dyn Runnable>) {
+        // read before taking the lock, as it may have to read a checkpoint store
+        let history = (self.track_history && component.checkpoints_changed())
+            .then(|| component.checkpoints().ok());
+        let mut inner = self.inner.lock().unwrap();
+        let id = component.id();
+        let slot = inner.slots.get_mut(&id).unwrap();
+        if let Some(history) = history {
+            slot.history = history;
+        }
+        slot.component = Some(component);
+        slot.processing = None;
+        inner.schedule(id);
+        self.cvar.notify_all();
+    }
+
+    /// Lets workers handle messages earlier than window; the window never moves backwards
+    pub fn set_window(&self, window: Timestamp) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.window = inner.window.max(window);
+        self.cvar.notify_all();
+    }
+
+    /// Makes every idle component commit; busy ones commit once they are done anyway
+    pub fn commit_all(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let idle: Vec<ComponentId> = inner
+            .slots
+            .iter()
+            .filter(|(_, slot)| slot.component.is_some())
+            .map(|(id, _)| *id)
+            .collect();
+        inner.commits.extend(idle);
+        self.cvar.notify_all();
+    }
+
+    /// Records every component and the messages in its queue (see Runnable::snapshot) once
+    /// every component was given back, then runs then; no task is handed out until then
+    /// returns, so nothing happens on the node in between
+    pub fn snapshot(&self, then: impl FnOnce()) -> io::Result<Vec<ComponentSnapshot>> {
+        self.paused(|slots| {
+            let snapshots = slots
+                .values()
+                .map(|slot| {
+                    let mut snapshot = slot.component.as_ref().unwrap().snapshot()?;
+                    snapshot.queued.extend(slot.queue.iter().cloned());
+                    Ok(snapshot)
+                })
+                .collect();
+            then();
+            snapshots
+        })
+    }
+
+    /// Saves every component at `at` (see Runnable::save) once every component was given back
+    pub fn save(&self, at: Timestamp) -> io::Result<Vec<SavedComponent>> {
+        self.paused(|slots| {
+            slots
+                .values()
+                .map(|slot| slot.component.as_ref().unwrap().save(at))
+                .collect()
+        })
+    }
+
+    /// Timestamp and dependency vector of the checkpoints of every component that are not
+    /// later than until (see Runnable::checkpoints), as of when each component was last given
+    /// back; fails unless they are tracked (see with_history)
+    ///
+    /// A component that is being handled may have freed some of them since, but only those
+    /// earlier than its checkpoint in the latest recovery line it was told of, which the
+    /// latest line made of them is never earlier than.
+    pub fn checkpoints(&self, until: Timestamp) -> io::Result<BTreeMap<ComponentId, History>> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .slots
+            .iter()
+            .map(|(id, slot)| match &slot.history {
+                Some(history) => {
+                    let history = history.iter().filter(|(ts, _)| *ts <= until);
+                    Ok((*id, history.cloned().collect()))
+                }
+                None => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("component {} cannot be recovered", id),
+                )),
+            })
+            .collect()
+    }
+
+    // waits for every component to be given back, then runs f; no task is handed out until
+    // f returns
+    fn paused<T>(&self, f: impl FnOnce(&HashMap<ComponentId, Slot>) -> T) -> T {
+        let mut inner = self.inner.lock().unwrap();
+        inner.paused = true;
+        while inner.slots.values().any(|slot| slot.component.is_none()) {
+            inner = self.cvar.wait(inner).unwrap();
+        }
+        let result = f(&inner.slots);
+        inner.paused = false;
+        self.cvar.notify_all();
+        result
+    }
+
+    /// Makes every current and future call to next return None
+    pub fn close(&self) {
+        self.inner.lock().unwrap().closed = true;
+        self.cvar.notify_all();
+    }
+
+    /// Lowest timestamp among the messages that are pending or being handled
+    pub fn local_min(&self) -> Timestamp {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .slots
+            .values()
+            .flat_map(|slot| {
+                let next = slot.queue.peek().map(|msg| msg.exec_ts);
+                slot.processing.into_iter().chain(next)
+            })
+            .min()
+            .unwrap_or(Timestamp::MAX)
+    }
+
+    /// Takes back every component; must only be called once every worker is done
+    pub fn into_components(self) -> Vec<Box<dyn Runnable>> {
+        let inner = self.inner.into_inner().unwrap();
+        inner
+            .slots
+            .into_values()
+            .map(|slot| slot.component.unwrap())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Dependencies;
+    use std::io;
+
+    struct Dummy(ComponentId);
+
+    impl Runnable for Dummy {
+        fn id(&self) -> ComponentId {
+            self.0
+        }
+        fn start(&mut self, _messenger: &Messenger) {}
+        fn handle(&mut self, _msg: Message, _messenger: &Messenger) -> io::Result<()> {
+            Ok(())
+        }
+        fn commit(&mut self, _gvt: Timestamp, _line: Option<Timestamp>) -> io::Result<()> {
+            Ok(())
+        }
+        fn end(&mut self) {}
+    }
+
+    // takes a checkpoint at every GVT it commits
+    struct Checkpointed(ComponentId, History, bool);
+
+    impl Runnable for Checkpointed {
+        fn id(&self) -> ComponentId {
+            self.0
+        }
+        fn start(&mut self, _messenger: &Messenger) {}
+        fn handle(&mut self, _msg: Message, _messenger: &Messenger) -> io::Result<()> {
+            Ok(())
+        }
+        fn commit(&mut self, gvt: Timestamp, _line: Option<Timestamp>) -> io::Result<()> {
+            self.1.push((gvt, Dependencies::new()));
+            self.2 = true;
+            Ok(())
+        }
+        fn end(&mut self) {}
+        fn checkpoints(&self) -> io::Result<History> {
+            Ok(self.1.clone())
+        }
+        fn checkpoints_changed(&mut self) -> bool {
+            std::mem::take(&mut self.2)
+        }
+    }
+
+    fn get_msg(to: ComponentId, exec_ts: Timestamp) -> Message {
+        Message {
+            route: String::default(),
+            exec_ts,
+            payload: String::default(),
+            from: 0,
+            to,
+            id: 0,
+            is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
+            sent_ts: 0,
+        }
+    }
+
+    fn get_scheduler() -> Scheduler {
+        Scheduler::new(vec![Box::new(Dummy(1)), Box::new(Dummy(2))], Timestamp::MAX)
+    }
+
+    #[test]
+    fn push_rejects_messages_to_remote_components() {
+        let scheduler = get_scheduler();
+        assert_eq!(scheduler.push(get_msg(3, 10)), Err(get_msg(3, 10)));
+    }
+
+    #[test]
+    fn next_picks_the_lowest_exec_ts_first() {
+        let scheduler = get_scheduler();
+        scheduler.push(get_msg(1, 30)).unwrap();
+        scheduler.push(get_msg(2, 20)).unwrap();
+        scheduler.push(get_msg(1, 10)).unwrap();
+
+        let task = scheduler.next().unwrap();
+        assert_eq!(task.msg, Some(get_msg(1, 10)));
+        scheduler.done(task.component);
+        let task = scheduler.next().unwrap();
+        assert_eq!(task.msg, Some(get_msg(2, 20)));
+        scheduler.done(task.component);
+        let task = scheduler.next().unwrap();
+        assert_eq!(task.msg, Some(get_msg(1, 30)));
+        scheduler.done(task.component);
+    }
+
+    #[test]
+    fn a_component_is_never_handed_to_two_workers() {
+        let scheduler = get_scheduler();
+        scheduler.push(get_msg(1, 10)).unwrap();
+        scheduler.push(get_msg(1, 20)).unwrap();
+        scheduler.push(get_msg(2, 30)).unwrap();
+
+        let first = scheduler.next().unwrap();
+        assert_eq!(first.msg, Some(get_msg(1, 10)));
+        let second = scheduler.next().unwrap();
+        assert_eq!(second.msg, Some(get_msg(2, 30)));
+
+        scheduler.done(first.component);
+        let third = scheduler.next().unwrap();
+        assert_eq!(third.msg, Some(get_msg(1, 20)));
+    }
+
+    #[test]
+    fn localmin_accounts_for_messages_being_handled() {
+        let scheduler = get_scheduler();
+        assert_eq!(scheduler.local_min(), Timestamp::MAX);
+        scheduler.push(get_msg(1, 10)).unwrap();
+        scheduler.push(get_msg(2, 20)).unwrap();
+
+        let task = scheduler.next().unwrap();
+        assert_eq!(scheduler.local_min(), 10);
+        scheduler.done(task.component);
+        assert_eq!(scheduler.local_min(), 20);
+    }
+
+    #[test]
+    fn commit_all_hands_idle_components_and_close_stops_workers() {
+        let scheduler = get_scheduler();
+        scheduler.commit_all();
+        let mut ids = vec![];
+        for _ in 0..2 {
+            let task = scheduler.next().unwrap();
+            assert_eq!(task.msg, None);
+            ids.push(task.component.id());
+            scheduler.done(task.component);
+        }
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+
+        scheduler.push(get_msg(1, 10)).unwrap();
+        scheduler.close();
+        assert!(scheduler.next().is_none());
+    }
+
+    #[test]
+    fn next_only_hands_messages_earlier_than_the_window() {
+        let scheduler = Scheduler::new(vec![Box::new(Dummy(1)), Box::new(Dummy(2))], 20);
+        scheduler.push(get_msg(1, 10)).unwrap();
+        scheduler.push(get_msg(2, 20)).unwrap();
+
+        let task = scheduler.next().unwrap();
+        assert_eq!(task.msg, Some(get_msg(1, 10)));
+        scheduler.done(task.component);
+
+        let waiting = {
+            let mut inner = scheduler.inner.lock().unwrap();
+            inner.next().is_none()
+        };
+        assert!(waiting);
+        assert_eq!(scheduler.local_min(), 20);
+
+        scheduler.set_window(10);
+        scheduler.set_window(21);
+        let task = scheduler.next().unwrap();
+        assert_eq!(task.msg, Some(get_msg(2, 20)));
+    }
+
+    #[test]
+    fn checkpoints_are_only_tracked_with_history() {
+        let get_checkpointed = || -> Vec<Box<dyn Runnable>> {
+            vec![Box::new(Checkpointed(
+                1,
+                vec![(0, Dependencies::new())],
+                false,
+            ))]
+        };
+        let untracked = Scheduler::new(get_checkpointed(), Timestamp::MAX);
+        assert!(untracked.checkpoints(Timestamp::MAX).is_err());
+
+        let scheduler = Scheduler::new(get_checkpointed(), Timestamp::MAX).with_history();
+        scheduler.commit_all();
+        let mut task = scheduler.next().unwrap();
+        task.component.commit(10, None).unwrap();
+        scheduler.done(task.component);
+
+        let timestamps: Vec<Timestamp> = scheduler.checkpoints(Timestamp::MAX).unwrap()[&1]
+            .iter()
+            .map(|(ts, _)| *ts)
+            .collect();
+        assert_eq!(timestamps, vec![0, 10]);
+    }
+}
@@ -0,0 +1,392 @@
+//! Consistent global snapshots of a running federation, taken with marker messages
+//! (Chandy-Lamport).
+//!
+//! Snapshots are for offline inspection only, e.g. to look at the state of a long run while it
+//! goes on: nothing restarts from them. A federation restarts from a savepoint instead (see the
+//! savepoint module).
+//!
+//! A snapshot is triggered by sending Control::Snapshot to any node (see trigger). That node
+//! records the state of its components and the messages waiting in their queues, then sends a
+//! Marker to every other node over the link its messages travel on. A node records its own
+//! share the first time it receives a Marker; from then on, it also records the messages it
+//! receives from every node whose Marker did not arrive yet, since those messages were in
+//! transit when the snapshot was taken. Links are FIFO, as every node sends all of its packets
+//! in order from a single thread.
+//!
+//! A node records its share on its own thread, once every component it runs was given back,
+//! so that it keeps taking part in GVT rounds meanwhile. Until then, it holds back the
+//! messages, markers and snapshot requests it receives, and handles them in the order they
+//! arrived once it is done (see Recorder::hold).
+//!
+//! Once a node received a Marker from every other node, it writes one file per local component
+//! in the snapshot's directory (see ComponentSnapshot and load). Every node writes to the same
+//! path, so the directory must be shared to hold the whole snapshot.
+//!
+//! Components run optimistically, so a snapshot holds each component's state at its LVT,
+//! including what it handled past GVT: the snapshot is consistent, but not final, as a late
+//! message may still undo some of what it records. Only the types registered with
+//! Registry::register_persistent can be recorded; a node running other types reports the
+//! snapshot as failed and writes nothing.
+//!
+//! A node takes part in one snapshot at a time: a snapshot triggered before the previous one
+//! completed everywhere is ignored by the nodes still recording the previous one.
+
+use crate::models::{ComponentId, Control, Message, Packet, Timestamp};
+use crate::scheduler::Scheduler;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+const EXTENSION: &str = "json";
+
+/// What a snapshot records of a component, written to `<id>.json` in the snapshot's directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentSnapshot {
+    pub id: ComponentId,
+    pub lvt: Timestamp,
+    pub state: serde_json::Value,
+
+    /// messages the component received but did not handle yet, including anti-messages that
+    /// arrived before the message they cancel
+    pub queued: Vec<Message>,
+
+    /// messages to the component that were in transit between nodes
+    pub in_transit: Vec<Message>,
+}
+
+/// Asks the node listening on address to take a snapshot of the federation into dir
+pub fn trigger(address: &str, dir: impl Into<PathBuf>) -> io::Result<()> {
+    let packet = Packet::Control {
+        from: String::new(),
+        to: String::from(address),
+        control: Control::Snapshot { dir: dir.into() },
+    };
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(serde_json::to_string(&packet)?.as_bytes())
+}
+
+/// Writes every component snapshot to its own file in dir, creating dir if needed
+pub fn save(dir: &Path, snapshots: &[ComponentSnapshot]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for snapshot in snapshots {
+        let path = dir.join(format!("{}.{}", snapshot.id, EXTENSION));
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(snapshot)?)?;
+        fs::rename(tmp, path)?;
+    }
+    Ok(())
+}
+
+/// Reads back every component snapshot written to dir, in ascending id order
+pub fn load(dir: &Path) -> io::Result<Vec<ComponentSnapshot>> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == EXTENSION) {
+            let snapshot: ComponentSnapshot = serde_json::from_slice(&fs::read(path)?)?;
+            snapshots.push(snapshot);
+        }
+    }
+    snapshots.sort_by_key(|s| s.id);
+    Ok(snapshots)
+}
+
+/// The node's share of the snapshots (see the module documentation)
+pub struct Recorder {
+    address: String,
+
+    // address of the node running each remote component
+    remote_addrs: HashMap<ComponentId, String>,
+
+    current: Mutex<Option<Recording>>,
+
+    // packets received while the node's share is recorded, see hold
+    held: Mutex<Held>,
+}
+
+struct Recording {
+    dir: PathBuf,
+
+    // None until the node's share is recorded, see record
+    components: Option<io::Result<Vec<ComponentSnapshot>>>,
+
+    // nodes whose Marker did not arrive yet
+    open: HashSet<String>,
+}
+
+#[derive(Default)]
+struct Held {
+    // whether packets are held back: set once a recording starts, cleared once every packet
+    // held back meanwhile was released
+    recording: bool,
+    packets: VecDeque<Packet>,
+}
+
+impl Recorder {
+    pub fn new(address: String, remote_addrs: HashMap<ComponentId, String>) -> Recorder {
+        Recorder {
+            address,
+            remote_addrs,
+            current: Mutex::new(None),
+            held: Mutex::new(Held::default()),
+        }
+    }
+
+    /// Holds packet back while the node records its share of a snapshot, and until the
+    /// packets held back before it are released (see release); returns it if it can be
+    /// handled right away
+    ///
+    /// Only messages, markers and snapshot requests are held back, as they must not be
+    /// handled before the node's share is recorded; the node keeps taking part in GVT rounds
+    /// meanwhile.
+    pub fn hold(&self, packet: Packet) -> Option<Packet> {
+        let mut held = self.held.lock().unwrap();
+        let holdable = match &packet {
+            Packet::Message(_) => true,
+            Packet::Control { control, .. } => {
+                matches!(control, Control::Snapshot { .. } | Control::Marker { .. })
+            }
+        };
+        if held.recording && holdable {
+            held.packets.push_back(packet);
+            None
+        } else {
+            Some(packet)
+        }
+    }
+
+    /// Must be called whenever the node receives Control::Snapshot, with from set to None, or
+    /// a Marker from another node
+    ///
+    /// Returns whether the node must record its share of a new snapshot, in which case record
+    /// must be called next; packets are held back until then.
+    pub fn on_marker(&self, dir: PathBuf, from: Option<String>) -> bool {
+        let mut current = self.current.lock().unwrap();
+        match current.as_mut() {
+            Some(recording) if recording.dir != dir => {
+                eprintln!(
+                    "warning: snapshot {} ignored while taking snapshot {}",
+                    dir.display(),
+                    recording.dir.display()
+                );
+                false
+            }
+            Some(recording) => {
+                if let Some(from) = from {
+                    recording.open.remove(&from);
+                }
+                if recording.open.is_empty() {
+                    if let Some(components) = recording.components.take() {
+                        let dir = current.take().unwrap().dir;
+                        Recorder::complete(&dir, components);
+                    }
+                }
+                false
+            }
+            None => {
+                let mut open = self.remote_nodes();
+                if let Some(from) = from {
+                    open.remove(&from);
+                }
+                *current = Some(Recording {
+                    dir,
+                    components: None,
+                    open,
+                });
+                self.held.lock().unwrap().recording = true;
+                true
+            }
+        }
+    }
+
+    /// Records the node's share of the snapshot on_marker started, once every component was
+    /// given back, then sends a Marker to every other node; may take as long as the longest
+    /// message being handled, so it is meant to run on its own thread
+    pub fn record(&self, scheduler: &Scheduler, network_sender: &Sender<Packet>) {
+        let (dir, nodes) = match self.current.lock().unwrap().as_ref() {
+            Some(recording) => (recording.dir.clone(), self.remote_nodes()),
+            None => return,
+        };
+        // markers are sent before the workers resume, so every message sent after the
+        // recording follows them
+        let components = scheduler.snapshot(|| {
+            for node in nodes {
+                network_sender
+                    .send(Packet::Control {
+                        from: self.address.clone(),
+                        to: node,
+                        control: Control::Marker { dir: dir.clone() },
+                    })
+                    .unwrap();
+            }
+        });
+        let mut current = self.current.lock().unwrap();
+        let recording = current.as_mut().unwrap();
+        if recording.open.is_empty() {
+            let dir = current.take().unwrap().dir;
+            Recorder::complete(&dir, components);
+        } else {
+            recording.components = Some(components);
+        }
+    }
+
+    /// Next packet held back while the node recorded its share, in the order they arrived;
+    /// None once there are none left, or if handling one of them started a new recording
+    pub fn release(&self) -> Option<Packet> {
+        let pending = self
+            .current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|recording| recording.components.is_none());
+        if pending {
+            return None;
+        }
+        let mut held = self.held.lock().unwrap();
+        let packet = held.packets.pop_front();
+        held.recording = packet.is_some();
+        packet
+    }
+
+    /// Must be called whenever the node receives a message from another node
+    pub fn on_message(&self, msg: &Message) {
+        let mut current = self.current.lock().unwrap();
+        let Some(recording) = current.as_mut() else {
+            return;
+        };
+        let in_transit = self
+            .remote_addrs
+            .get(&msg.from)
+            .is_some_and(|node| recording.open.contains(node));
+        if let (true, Some(Ok(components))) = (in_transit, &mut recording.components) {
+            if let Some(snapshot) = components.iter_mut().find(|s| s.id == msg.to) {
+                snapshot.in_transit.push(msg.clone());
+            }
+        }
+    }
+
+    fn remote_nodes(&self) -> HashSet<String> {
+        self.remote_addrs
+            .values()
+            .filter(|node| **node != self.address)
+            .cloned()
+            .collect()
+    }
+
+    // writes the node's share once a Marker arrived from every other node
+    fn complete(dir: &Path, components: io::Result<Vec<ComponentSnapshot>>) {
+        if let Err(e) = components.and_then(|components| save(dir, &components)) {
+            eprintln!("error: snapshot {} failed: {}", dir.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::component_manager::{ComponentManager, Runnable};
+    use crate::gateway::Gateway;
+    use crate::models::{CommitAction, Dependencies};
+
+    /// Counts the messages it handles
+    struct Counter;
+
+    impl Gateway<u32> for Counter {
+        fn init(&self) -> (u32, Vec<Message>, Vec<CommitAction>) {
+            (0, Vec::new(), Vec::new())
+        }
+
+        fn on_message(&self, state: u32, _msg: Message) -> (u32, Vec<Message>, Vec<CommitAction>) {
+            (state + 1, Vec::new(), Vec::new())
+        }
+    }
+
+    fn get_message(from: ComponentId, exec_ts: Timestamp) -> Message {
+        Message {
+            id: exec_ts as u32,
+            is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
+            from,
+            to: 1,
+            sent_ts: 0,
+            exec_ts,
+            route: String::from("in"),
+            payload: String::default(),
+        }
+    }
+
+    #[test]
+    fn recorder_records_messages_in_transit_until_every_marker_arrived() {
+        let dir = std::env::temp_dir().join("dcb_snapshot_test");
+        let _ = fs::remove_dir_all(&dir);
+        let component = ComponentManager::new(1, Box::new(Counter), |_, _| true).serializable();
+        let scheduler = Scheduler::new(
+            vec![Box::new(component) as Box<dyn Runnable>],
+            Timestamp::MAX,
+        );
+        scheduler.push(get_message(2, 5)).unwrap();
+
+        let remote_addrs = vec![(2, String::from("B")), (3, String::from("C"))]
+            .into_iter()
+            .collect();
+        let recorder = Recorder::new(String::from("A"), remote_addrs);
+        let (network_sender, network_receiver) = std::sync::mpsc::channel();
+        assert!(recorder.on_marker(dir.clone(), None));
+
+        // only what must not be handled before the recording is held back until it is done
+        assert!(recorder.hold(Packet::Message(get_message(2, 6))).is_none());
+        let cut = Packet::Control {
+            from: String::from("B"),
+            to: String::from("A"),
+            control: Control::Cut { epoch: 1 },
+        };
+        assert_eq!(recorder.hold(cut.clone()), Some(cut));
+        assert!(recorder.release().is_none());
+        recorder.record(&scheduler, &network_sender);
+        assert_eq!(recorder.release(), Some(Packet::Message(get_message(2, 6))));
+        assert!(recorder.release().is_none());
+        let msg = Packet::Message(get_message(2, 6));
+        assert_eq!(recorder.hold(msg.clone()), Some(msg));
+
+        let mut markers: Vec<String> = network_receiver
+            .try_iter()
+            .map(|packet| match packet {
+                Packet::Control {
+                    to,
+                    control: Control::Marker { .. },
+                    ..
+                } => to,
+                _ => panic!(),
+            })
+            .collect();
+        markers.sort();
+        assert_eq!(markers, vec!["B", "C"]);
+
+        recorder.on_message(&get_message(2, 6));
+        assert!(!recorder.on_marker(dir.clone(), Some(String::from("B"))));
+        // the link from B is closed and nothing is written before C's marker
+        recorder.on_message(&get_message(2, 7));
+        recorder.on_message(&get_message(3, 8));
+        assert!(!dir.exists());
+        assert!(!recorder.on_marker(dir.clone(), Some(String::from("C"))));
+
+        let snapshots = load(&dir).unwrap();
+        assert_eq!(
+            snapshots,
+            vec![ComponentSnapshot {
+                id: 1,
+                lvt: 0,
+                state: serde_json::json!(0),
+                queued: vec![get_message(2, 5)],
+                in_transit: vec![get_message(2, 6), get_message(3, 8)],
+            }]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Adapts a JSON payload on its way to a destination, so components written by different
+/// teams can be wired together without adapter components
+///
+/// Transforms are written in the config file as a list applied in order, e.g.
+///     [
+///         { "rename": { "from": "v", "to": "speed" } },
+///         { "scale": { "field": "speed", "factor": 3.6 } }
+///     ]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    /// Converts units: value * factor + offset, applied to a field of a JSON object, or to the
+    /// whole payload if it is a number and field is None
+    Scale {
+        #[serde(default)]
+        field: Option<String>,
+        factor: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+
+    /// Renames a field of a JSON object
+    Rename { from: String, to: String },
+
+    /// Keeps only the given fields of a JSON object
+    Project { fields: Vec<String> },
+}
+
+impl Transform {
+    pub fn apply(&self, value: Value) -> Result<Value, String> {
+        match self {
+            Transform::Scale {
+                field: None,
+                factor,
+                offset,
+            } => scale(&value, *factor, *offset),
+            Transform::Scale {
+                field: Some(field),
+                factor,
+                offset,
+            } => {
+                let mut object = into_object(value)?;
+                let scaled = match object.get(field) {
+                    Some(v) => scale(v, *factor, *offset)?,
+                    None => return Err(format!("missing field {}", field)),
+                };
+                object.insert(field.clone(), scaled);
+                Ok(Value::Object(object))
+            }
+            Transform::Rename { from, to } => {
+                let mut object = into_object(value)?;
+                match object.remove(from) {
+                    Some(v) => object.insert(to.clone(), v),
+                    None => return Err(format!("missing field {}", from)),
+                };
+                Ok(Value::Object(object))
+            }
+            Transform::Project { fields } => {
+                let mut object = into_object(value)?;
+                let mut projected = Map::new();
+                for field in fields {
+                    match object.remove(field) {
+                        Some(v) => projected.insert(field.clone(), v),
+                        None => return Err(format!("missing field {}", field)),
+                    };
+                }
+                Ok(Value::Object(projected))
+            }
+        }
+    }
+}
+
+/// Applies every transform in order; payloads are left untouched when there are none, so
+/// they only have to be JSON when they are transformed
+pub fn apply_all(transforms: &[Transform], payload: &str) -> Result<String, String> {
+    if transforms.is_empty() {
+        return Ok(String::from(payload));
+    }
+    let mut value: Value =
+        serde_json::from_str(payload).map_err(|e| format!("payload is not JSON: {}", e))?;
+    for transform in transforms {
+        value = transform.apply(value)?;
+    }
+    Ok(value.to_string())
+}
+
+fn into_object(value: Value) -> Result<Map<String, Value>, String> {
+    match value {
+        Value::Object(object) => Ok(object),
+        other => Err(format!("{} is not a JSON object", other)),
+    }
+}
+
+fn scale(value: &Value, factor: f64, offset: f64) -> Result<Value, String> {
+    let scaled = match value.as_f64() {
+        Some(v) => v * factor + offset,
+        None => return Err(format!("{} is not a number", value)),
+    };
+    serde_json::Number::from_f64(scaled)
+        .map(Value::Number)
+        .ok_or_else(|| format!("{} is not a valid JSON number", scaled))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn get_transforms(transforms: Value) -> Vec<Transform> {
+        serde_json::from_value(transforms).unwrap()
+    }
+
+    #[test]
+    fn transforms_are_applied_in_order() {
+        let transforms = get_transforms(json!([
+            { "rename": { "from": "v", "to": "speed" } },
+            { "scale": { "field": "speed", "factor": 3.6 } },
+            { "project": { "fields": ["speed", "id"] } }
+        ]));
+        let payload = apply_all(&transforms, r#"{"v": 10, "id": "car", "mass": 1200}"#).unwrap();
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload, json!({ "speed": 36.0, "id": "car" }));
+    }
+
+    #[test]
+    fn scale_converts_whole_payloads() {
+        let celsius_to_fahrenheit = get_transforms(json!([
+            { "scale": { "factor": 1.8, "offset": 32 } }
+        ]));
+        assert_eq!(apply_all(&celsius_to_fahrenheit, "100").unwrap(), "212.0");
+        assert!(apply_all(&celsius_to_fahrenheit, r#""hot""#).is_err());
+    }
+
+    #[test]
+    fn payloads_are_untouched_without_transforms() {
+        assert_eq!(apply_all(&[], "not json").unwrap(), "not json");
+        let rename = get_transforms(json!([{ "rename": { "from": "v", "to": "speed" } }]));
+        assert!(apply_all(&rename, "not json").is_err());
+        assert!(apply_all(&rename, r#"{"speed": 1}"#).is_err());
+    }
+}
@@ -1,47 +1,677 @@
 use crate::component::Component;
+use crate::context::Context;
+use crate::dead_letter::DeadLetterSink;
 use crate::gateway::Gateway;
-use crate::models::{ComponentId, Message, MsgCore, Timestamp};
+use crate::models::{CommitAction, ComponentId, Dependencies, Message, MsgCore, Timestamp};
+use crate::route_pattern;
+use crate::transform::{self, Transform};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 
-#[allow(dead_code)]
-pub struct Translator {
+pub struct Translator<State: Component> {
     pub local_id: ComponentId,
-    pub route_to_dest: HashMap<String, (ComponentId, String)>,
+
+    /// Every destination of each route; a message emitted through a route is copied to all
+    /// of its destinations
+    ///
+    /// Routes may be patterns such as `sensor/*`, whose captures can be used in the routes of
+    /// their destinations (see route_pattern for the precedence rules)
+    pub route_to_dest: HashMap<String, Vec<Destination>>,
+
+    /// Parameters of the local component instance, handed to State::init
+    pub params: State::Params,
+
+    /// Receives the messages that cannot be translated, e.g. those emitted through routes that
+    /// are not in route_to_dest
+    pub dead_letters: Arc<dyn DeadLetterSink>,
+
+    // id of the next message the component emits
+    next_id: AtomicU32,
+}
+
+/// Where a route leads
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Destination {
+    pub to: ComponentId,
+    pub route: String,
+
+    /// Transport delay of the link, added to the exec_ts of every message that goes through it
+    ///
+    /// It is also the link's lookahead: a message sent at T never arrives before T + delay
+    #[serde(default)]
+    pub delay: Timestamp,
+
+    /// Applied in order to the payload of every message that goes through the link
+    #[serde(default)]
+    pub transform: Vec<Transform>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TranslateError {
+    /// The component emitted a message through a route that leads nowhere
+    UnknownRoute { from: ComponentId, msg: MsgCore },
+
+    /// The component emitted a message to be executed before the time it was sent
+    InThePast {
+        from: ComponentId,
+        msg: MsgCore,
+        sent_ts: Timestamp,
+    },
+
+    /// The payload of a message could not be transformed for one of its destinations
+    TransformFailed {
+        from: ComponentId,
+        to: ComponentId,
+        msg: MsgCore,
+        reason: String,
+    },
+
+    /// The message would be executed past Timestamp::MAX: msg was emitted, or goes through a
+    /// link, delay time units after its exec_ts
+    Overflow {
+        from: ComponentId,
+        msg: MsgCore,
+        delay: Timestamp,
+    },
+}
+
+impl fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranslateError::UnknownRoute { from, msg } => write!(
+                f,
+                "component {} emitted a message at {} through unknown route {}",
+                from, msg.exec_ts, msg.route
+            ),
+            TranslateError::InThePast { from, msg, sent_ts } => write!(
+                f,
+                "component {} emitted a message through route {} at {} to be executed at {}",
+                from, msg.route, sent_ts, msg.exec_ts
+            ),
+            TranslateError::TransformFailed {
+                from,
+                to,
+                msg,
+                reason,
+            } => write!(
+                f,
+                "message from component {} through route {} cannot be transformed for {}: {}",
+                from, msg.route, to, reason
+            ),
+            TranslateError::Overflow { from, msg, delay } => write!(
+                f,
+                "component {} emitted a message through route {} to be executed {} after {}, \
+                 past the latest time",
+                from, msg.route, delay, msg.exec_ts
+            ),
+        }
+    }
 }
 
-impl Translator {
-    pub fn translate(&self, msg_core: MsgCore, sent_ts: Timestamp) -> Message {
-        let (destination_id, destination_route) = &self.route_to_dest[&msg_core.route];
+impl<State: Component> Translator<State> {
+    /// Constructor; fails if params cannot be deserialized into the component's Params
+    pub fn new(
+        local_id: ComponentId,
+        route_to_dest: HashMap<String, Vec<Destination>>,
+        params: serde_json::Value,
+        dead_letters: Arc<dyn DeadLetterSink>,
+    ) -> Result<Translator<State>, serde_json::Error> {
+        Ok(Translator {
+            local_id,
+            route_to_dest,
+            params: State::Params::deserialize(params)?,
+            dead_letters,
+            next_id: AtomicU32::new(0),
+        })
+    }
+
+    /// Returns one message per destination of the route, each with its own id, delayed by the
+    /// destination's delay and with the destination's transform applied to its payload
+    ///
+    /// A copy whose payload cannot be transformed, or that would be executed past
+    /// Timestamp::MAX, is returned as an error, the others are still returned
+    pub fn translate(
+        &self,
+        msg_core: MsgCore,
+        sent_ts: Timestamp,
+    ) -> Vec<Result<Message, TranslateError>> {
+        let (destinations, captures) = match self.destinations(&msg_core.route) {
+            Some((destinations, captures)) if !destinations.is_empty() => (destinations, captures),
+            _ => {
+                return vec![Err(TranslateError::UnknownRoute {
+                    from: self.local_id,
+                    msg: msg_core,
+                })]
+            }
+        };
+        if msg_core.exec_ts < sent_ts {
+            return vec![Err(TranslateError::InThePast {
+                from: self.local_id,
+                msg: msg_core,
+                sent_ts,
+            })];
+        }
+        destinations
+            .iter()
+            .map(|dest| {
+                let payload =
+                    transform::apply_all(&dest.transform, &msg_core.payload).map_err(|reason| {
+                        TranslateError::TransformFailed {
+                            from: self.local_id,
+                            to: dest.to,
+                            msg: msg_core.clone(),
+                            reason,
+                        }
+                    })?;
+                let exec_ts = msg_core.exec_ts.checked_add(dest.delay).ok_or_else(|| {
+                    TranslateError::Overflow {
+                        from: self.local_id,
+                        msg: msg_core.clone(),
+                        delay: dest.delay,
+                    }
+                })?;
+
+                Ok(Message {
+                    id: self.next_id(),
+                    is_anti: false,
+                    epoch: 0,
+                    dependencies: Dependencies::new(),
+                    from: self.local_id,
+                    to: dest.to,
+                    sent_ts,
+                    exec_ts,
+                    route: route_pattern::expand(&dest.route, &captures),
+                    payload,
+                })
+            })
+            .collect()
+    }
+
+    /// Finds the route a message emitted through the given route goes through, along with the
+    /// captures of its pattern
+    fn destinations<'a>(&'a self, route: &'a str) -> Option<(&'a Vec<Destination>, Vec<&'a str>)> {
+        if let Some(destinations) = self.route_to_dest.get(route) {
+            return Some((destinations, Vec::new()));
+        }
+        self.route_to_dest
+            .iter()
+            .filter(|(pattern, _)| route_pattern::is_pattern(pattern))
+            .filter_map(|(pattern, destinations)| {
+                let captures = route_pattern::captures(pattern, route)?;
+                Some((pattern, destinations, captures))
+            })
+            .min_by(|a, b| route_pattern::precedence(a.0, b.0))
+            .map(|(_, destinations, captures)| (destinations, captures))
+    }
+
+    /// Events are not routed: they are delivered back to the local component as they are
+    pub fn schedule(&self, event: MsgCore, sent_ts: Timestamp) -> Message {
         Message {
-            id: 0,
+            id: self.next_id(),
             is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
             from: self.local_id,
-            to: destination_id.clone(),
-            sent_ts: sent_ts,
-            exec_ts: msg_core.exec_ts,
-            route: destination_route.clone(),
-            payload: msg_core.payload,
+            to: self.local_id,
+            sent_ts,
+            exec_ts: event.exec_ts,
+            route: event.route,
+            payload: event.payload,
+        }
+    }
+
+    // context in which the local component handles message
+    fn context(&self, message: &Message) -> Context {
+        Context::new(
+            self.local_id,
+            message.exec_ts,
+            Some(message.from),
+            message.sent_ts,
+        )
+    }
+
+    fn next_id(&self) -> u32 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn emit(&self, ctx: Context) -> (Vec<Message>, Vec<CommitAction>) {
+        let sent_ts = ctx.lvt();
+        for error in ctx.dead_letters() {
+            self.dead_letters.receive(error.clone());
         }
+        let (messages, events, actions) = ctx.into_emitted();
+        let messages = messages
+            .into_iter()
+            .flat_map(|m| self.translate(m, sent_ts))
+            .filter_map(|msg| match msg {
+                Ok(msg) => Some(msg),
+                Err(e) => {
+                    self.dead_letters.receive(e);
+                    None
+                }
+            })
+            .chain(events.into_iter().map(|e| self.schedule(e, sent_ts)))
+            .collect();
+        (messages, actions)
     }
 }
 
-impl<State> Gateway<State> for Translator
+impl<State> Gateway<State> for Translator<State>
 where
     State: Component,
 {
-    fn init(&self) -> (State, Vec<Message>) {
-        let (initial_state, messages) = State::init();
-        let messages = messages.into_iter().map(|m| self.translate(m, 0)).collect();
-        (initial_state, messages)
+    fn init(&self) -> (State, Vec<Message>, Vec<CommitAction>) {
+        let mut ctx = Context::new(self.local_id, 0, None, 0);
+        let initial_state = State::init(self.params.clone(), &mut ctx);
+        let (messages, actions) = self.emit(ctx);
+        (initial_state, messages, actions)
     }
 
-    fn on_message(&self, state: State, message: Message) -> (State, Vec<Message>) {
-        let message = MsgCore::from(message);
-        let (new_state, messages) = state.on_message(&message);
-        let messages = messages
+    fn on_message(
+        &self,
+        state: State,
+        message: Message,
+    ) -> (State, Vec<Message>, Vec<CommitAction>) {
+        let mut ctx = self.context(&message);
+        let new_state = state.on_message(&MsgCore::from(message), &mut ctx);
+        let (messages, actions) = self.emit(ctx);
+        (new_state, messages, actions)
+    }
+
+    /// Nothing is translated: dead letters are not reported again, and no id is used up
+    fn replay(&self, state: State, message: Message) -> State {
+        let mut ctx = self.context(&message);
+        state.on_message(&MsgCore::from(message), &mut ctx)
+    }
+
+    fn execute(&self, action: &CommitAction) {
+        State::execute(action);
+    }
+
+    fn on_rollback(&self, state: &State, ts: Timestamp) {
+        state.on_rollback(ts);
+    }
+
+    fn on_commit(&self, state: &State, gvt: Timestamp) {
+        state.on_commit(gvt);
+    }
+
+    fn on_end(&self, state: &State) {
+        state.on_end();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Rollbacks and commits Counter was notified of, with its count at the time
+    static NOTIFIED: Mutex<Vec<(&str, u32, Timestamp)>> = Mutex::new(Vec::new());
+
+    #[derive(Default)]
+    struct Collector(Mutex<Vec<TranslateError>>);
+
+    impl DeadLetterSink for Collector {
+        fn receive(&self, error: TranslateError) {
+            self.0.lock().unwrap().push(error);
+        }
+    }
+
+    /// Counts received messages, forwards them and schedules a tick to itself
+    struct Counter(u32);
+
+    impl Component for Counter {
+        type Params = u32;
+
+        fn init(params: u32, ctx: &mut Context) -> Self {
+            ctx.schedule("tick", "", 1);
+            Counter(params)
+        }
+
+        fn on_message(self, msg: &MsgCore, ctx: &mut Context) -> Self {
+            ctx.send("out", msg.payload.clone(), 10);
+            ctx.schedule("tick", ctx.sender().unwrap().to_string(), 1);
+            Counter(self.0 + 1)
+        }
+
+        fn on_rollback(&self, ts: Timestamp) {
+            NOTIFIED.lock().unwrap().push(("rollback", self.0, ts));
+        }
+
+        fn on_commit(&self, gvt: Timestamp) {
+            NOTIFIED.lock().unwrap().push(("commit", self.0, gvt));
+        }
+    }
+
+    fn get_destination(to: ComponentId, route: &str, delay: Timestamp) -> Destination {
+        Destination {
+            to,
+            route: String::from(route),
+            delay,
+            transform: Vec::new(),
+        }
+    }
+
+    fn get_translator() -> Translator<Counter> {
+        let mut route_to_dest = HashMap::new();
+        route_to_dest.insert(String::from("out"), vec![get_destination(2, "in", 0)]);
+        Translator::new(
+            1,
+            route_to_dest,
+            serde_json::json!(0),
+            Arc::new(Collector::default()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn init_delivers_events_to_self() {
+        let translator = get_translator();
+        let (state, messages, _): (Counter, Vec<Message>, _) = translator.init();
+        assert_eq!(state.0, 0);
+        assert_eq!(
+            messages,
+            vec![Message {
+                id: 0,
+                is_anti: false,
+                epoch: 0,
+                dependencies: Dependencies::new(),
+                from: 1,
+                to: 1,
+                sent_ts: 0,
+                exec_ts: 1,
+                route: String::from("tick"),
+                payload: String::default(),
+            }]
+        );
+    }
+
+    #[test]
+    fn init_receives_instance_params() {
+        let mut translator = get_translator();
+        translator.params = 42;
+        let (state, _, _): (Counter, Vec<Message>, _) = translator.init();
+        assert_eq!(state.0, 42);
+
+        let translator = Translator::<Counter>::new(
+            1,
+            HashMap::new(),
+            serde_json::json!("not a number"),
+            Arc::new(Collector::default()),
+        );
+        assert!(translator.is_err());
+    }
+
+    #[test]
+    fn onmessage_exposes_context_and_translates_emitted_messages() {
+        let translator = get_translator();
+        let received = Message {
+            id: 7,
+            is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
+            from: 3,
+            to: 1,
+            sent_ts: 40,
+            exec_ts: 50,
+            route: String::from("in"),
+            payload: String::from("hello"),
+        };
+        let (state, messages, _) = translator.on_message(Counter(4), received);
+        assert_eq!(state.0, 5);
+        assert_eq!(
+            messages,
+            vec![
+                Message {
+                    id: 0,
+                    is_anti: false,
+                    epoch: 0,
+                    dependencies: Dependencies::new(),
+                    from: 1,
+                    to: 2,
+                    sent_ts: 50,
+                    exec_ts: 60,
+                    route: String::from("in"),
+                    payload: String::from("hello"),
+                },
+                Message {
+                    id: 1,
+                    is_anti: false,
+                    epoch: 0,
+                    dependencies: Dependencies::new(),
+                    from: 1,
+                    to: 1,
+                    sent_ts: 50,
+                    exec_ts: 51,
+                    route: String::from("tick"),
+                    payload: String::from("3"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rollbacks_and_commits_are_forwarded_to_the_component() {
+        let translator = get_translator();
+        translator.on_rollback(&Counter(3), 15);
+        translator.on_commit(&Counter(4), 20);
+        assert_eq!(
+            *NOTIFIED.lock().unwrap(),
+            vec![("rollback", 3, 15), ("commit", 4, 20)]
+        );
+    }
+
+    #[test]
+    fn translate_copies_messages_to_every_destination_with_distinct_ids() {
+        let mut translator = get_translator();
+        translator
+            .route_to_dest
+            .get_mut("out")
+            .unwrap()
+            .push(get_destination(3, "load", 5));
+
+        let msg = MsgCore {
+            payload: String::from("230"),
+            route: String::from("out"),
+            exec_ts: 60,
+        };
+        let msgs: Vec<Message> = translator
+            .translate(msg.clone(), 50)
             .into_iter()
-            .map(|m| self.translate(m, message.exec_ts))
+            .map(Result::unwrap)
             .collect();
-        (new_state, messages)
+        assert_eq!(msgs.len(), 2);
+        assert_eq!((msgs[0].to, msgs[0].route.as_str()), (2, "in"));
+        assert_eq!((msgs[1].to, msgs[1].route.as_str()), (3, "load"));
+        assert!(msgs.iter().all(|m| m.payload == "230"));
+        assert_eq!((msgs[0].exec_ts, msgs[1].exec_ts), (60, 65));
+        assert_ne!(msgs[0].id, msgs[1].id);
+
+        // every copy is cancelled by its own anti-message only
+        let anti = msgs[0].get_anti().unwrap();
+        assert!(anti.is_inverse_of(&msgs[0]));
+        assert!(!anti.is_inverse_of(&msgs[1]));
+
+        let again: Vec<Message> = translator
+            .translate(msg, 50)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert!(again.iter().all(|m| msgs.iter().all(|n| m.id != n.id)));
+    }
+
+    #[test]
+    fn translate_rejects_messages_scheduled_in_the_past() {
+        let translator = get_translator();
+        let msg = MsgCore {
+            payload: String::default(),
+            route: String::from("out"),
+            exec_ts: 40,
+        };
+        assert_eq!(
+            translator.translate(msg.clone(), 50),
+            vec![Err(TranslateError::InThePast {
+                from: 1,
+                msg,
+                sent_ts: 50,
+            })]
+        );
+    }
+
+    #[test]
+    fn translate_rejects_copies_delayed_past_the_latest_time() {
+        let mut translator = get_translator();
+        translator
+            .route_to_dest
+            .get_mut("out")
+            .unwrap()
+            .push(get_destination(3, "load", Timestamp::MAX));
+
+        let msg = MsgCore {
+            payload: String::default(),
+            route: String::from("out"),
+            exec_ts: 60,
+        };
+        let msgs = translator.translate(msg.clone(), 50);
+        assert_eq!(msgs[0].as_ref().unwrap().exec_ts, 60);
+        assert_eq!(
+            msgs[1],
+            Err(TranslateError::Overflow {
+                from: 1,
+                msg,
+                delay: Timestamp::MAX,
+            })
+        );
+    }
+
+    #[test]
+    fn translate_transforms_payloads_for_each_destination() {
+        let mut translator = get_translator();
+        let mut kmh = get_destination(3, "speed", 0);
+        kmh.transform = serde_json::from_value(serde_json::json!([
+            { "scale": { "field": "v", "factor": 3.6 } },
+            { "rename": { "from": "v", "to": "speed" } }
+        ]))
+        .unwrap();
+        translator.route_to_dest.get_mut("out").unwrap().push(kmh);
+
+        let msg = MsgCore {
+            payload: String::from(r#"{"v":10}"#),
+            route: String::from("out"),
+            exec_ts: 60,
+        };
+        let msgs = translator.translate(msg.clone(), 50);
+        assert_eq!(msgs[0].as_ref().unwrap().payload, r#"{"v":10}"#);
+        assert_eq!(msgs[1].as_ref().unwrap().payload, r#"{"speed":36.0}"#);
+
+        let msg = MsgCore {
+            payload: String::from("10"),
+            ..msg
+        };
+        let msgs = translator.translate(msg, 50);
+        assert_eq!(msgs[0].as_ref().unwrap().payload, "10");
+        match &msgs[1] {
+            Err(TranslateError::TransformFailed { from: 1, to: 3, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn translate_uses_the_most_specific_matching_route() {
+        let mut translator = get_translator();
+        let routes = [
+            ("sensor/*", get_destination(2, "reading_$1", 0)),
+            ("sensor/7", get_destination(3, "seven", 0)),
+            ("*/*", get_destination(4, "$2_of_$1", 0)),
+        ];
+        for (route, dest) in routes {
+            translator
+                .route_to_dest
+                .insert(String::from(route), vec![dest]);
+        }
+
+        let translate = |route: &str| {
+            let msg = MsgCore {
+                payload: String::default(),
+                route: String::from(route),
+                exec_ts: 60,
+            };
+            let msg = translator.translate(msg, 50).pop().unwrap().unwrap();
+            (msg.to, msg.route)
+        };
+        assert_eq!(translate("sensor/7"), (3, String::from("seven")));
+        assert_eq!(translate("sensor/8"), (2, String::from("reading_8")));
+        assert_eq!(translate("actuator/8"), (4, String::from("8_of_actuator")));
+        assert_eq!(translate("out"), (2, String::from("in")));
+    }
+
+    #[test]
+    fn unknown_routes_go_to_the_dead_letter_sink() {
+        let collector = Arc::new(Collector::default());
+        let mut translator = get_translator();
+        translator.route_to_dest.clear();
+        translator.dead_letters = collector.clone();
+
+        let msg = MsgCore {
+            payload: String::from("hello"),
+            route: String::from("out"),
+            exec_ts: 60,
+        };
+        let expected = TranslateError::UnknownRoute {
+            from: 1,
+            msg: msg.clone(),
+        };
+        assert_eq!(
+            translator.translate(msg.clone(), 50),
+            vec![Err(expected.clone())]
+        );
+
+        let received = Message {
+            id: 7,
+            is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
+            from: 3,
+            to: 1,
+            sent_ts: 40,
+            exec_ts: 50,
+            route: String::from("in"),
+            payload: String::from("hello"),
+        };
+        let (_, messages, _) = translator.on_message(Counter(4), received);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].route, "tick");
+        assert_eq!(*collector.0.lock().unwrap(), vec![expected]);
+    }
+
+    #[test]
+    fn replay_only_returns_the_new_state() {
+        let collector = Arc::new(Collector::default());
+        let mut translator = get_translator();
+        translator.route_to_dest.clear();
+        translator.dead_letters = collector.clone();
+
+        let received = Message {
+            id: 7,
+            is_anti: false,
+            epoch: 0,
+            dependencies: Dependencies::new(),
+            from: 3,
+            to: 1,
+            sent_ts: 40,
+            exec_ts: 50,
+            route: String::from("in"),
+            payload: String::from("hello"),
+        };
+        let state = translator.replay(Counter(4), received.clone());
+        assert_eq!(state.0, 5);
+        assert!(collector.0.lock().unwrap().is_empty());
+
+        let (_, messages, _) = translator.on_message(state, received);
+        assert_eq!(messages[0].id, 0);
     }
 }